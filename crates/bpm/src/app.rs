@@ -21,7 +21,10 @@ use bpmutil::MyLockFns;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+mod delta;
+mod history;
 mod list;
+mod plan;
 
 const TEMP_DOWNLOAD_PREFX : &str = "temp_download_";
 
@@ -35,6 +38,9 @@ pub struct App {
     arch_filter: Vec<String>,
 
     lockfile: Option<File>,
+
+    /// the active transaction, if any
+    txn: Option<journal::Transaction>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -42,6 +48,10 @@ pub struct Versioning {
     pub pinned_to_version: bool,
     pub pinned_to_channel: bool,
     pub channel: Option<String>,
+
+    /// version requirement that updates must satisfy, such as `^1.4`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
 }
 
 impl Versioning {
@@ -50,6 +60,7 @@ impl Versioning {
             pinned_to_version: false,
             pinned_to_channel: false,
             channel: None,
+            range: None,
         }
     }
     fn pinned_version() -> Self {
@@ -57,17 +68,178 @@ impl Versioning {
             pinned_to_version: true,
             pinned_to_channel: false,
             channel: None,
+            range: None,
         }
     }
     fn pinned_channel(chan: &str) -> Self {
         Self {
             pinned_to_version: false,
             pinned_to_channel: true,
-            channel: Some(chan.to_string())
+            channel: Some(chan.to_string()),
+            range: None,
+        }
+    }
+    fn pinned_range(req: &version::VersionReq) -> Self {
+        Self {
+            pinned_to_version: false,
+            pinned_to_channel: false,
+            channel: None,
+            range: Some(req.to_string()),
         }
     }
 }
 
+/// Ask a yes/no question on the terminal, defaulting to no.
+/// End of input is taken as no.
+fn confirm(question: &str) -> AResult<bool> {
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Check if a version satisfies a dependency version or requirement string.
+/// Strings that fail to parse as a requirement are compared as exact versions.
+fn version_satisfies(req: &str, version: &str) -> bool {
+    match version::VersionReq::parse(req) {
+        Ok(req) => req.matches(version),
+        Err(_) => req == version,
+    }
+}
+
+/// a dependency that is not installed, with its package file in the cache
+#[derive(Debug)]
+struct ResolvedDependency {
+    id: PackageID,
+    versioning: Versioning,
+
+    /// none for a dry run when the package file is not cached
+    cache_file: Option<Utf8PathBuf>,
+
+    package_hash: Option<String>,
+    required_by: String,
+
+    /// bytes downloaded to get the package file, or for a dry run the bytes it would download
+    download_size: Option<u64>,
+}
+
+/// a package given to `bpm install`
+#[derive(Debug)]
+struct InstallItem {
+    /// position on the command line
+    index: usize,
+    name: String,
+    version: String,
+    id: PackageID,
+    versioning: Versioning,
+
+    /// the currently installed version, if any
+    current: Option<db::DbPkg>,
+
+    /// a package file given on the command line
+    local_file: Option<Utf8PathBuf>,
+
+    /// (package file, package hash, downloaded bytes), once the file is available
+    file: Option<(Utf8PathBuf, Option<String>, u64)>,
+
+    /// for a dry run, bytes to download when the package file is not cached
+    download_size: Option<u64>,
+
+    metadata: Option<package::MetaData>,
+
+    error: Option<anyhow::Error>,
+}
+
+#[derive(Debug)]
+enum Prepared {
+    Install(Box<InstallItem>),
+    /// (name, reason) for a package that needs nothing done
+    Unchanged(String, String),
+}
+
+/// what happened to one package given to `bpm install` or `bpm uninstall`
+#[derive(Debug)]
+enum PkgOutcome {
+    Installed(String),
+    Updated(String, String),
+    Reinstalled(String),
+    Uninstalled(String),
+    Unchanged(String),
+    Failed(anyhow::Error),
+    /// undone because another package in the same transaction failed
+    RolledBack,
+}
+
+/// Print a per-package summary when more than one package was given.
+/// Fails if any package failed. With a single package, its own error is returned.
+fn report_outcomes(given: usize, mut outcomes: Vec<(usize, String, PkgOutcome)>, summary: bool) -> AResult<()> {
+
+    outcomes.sort_by_key(|(index, ..)| *index);
+
+    let failed = outcomes.iter().filter(|(_, _, o)| matches!(o, PkgOutcome::Failed(_) | PkgOutcome::RolledBack)).count();
+
+    if given == 1 && outcomes.len() == 1 {
+        if let Some((_, _, PkgOutcome::Failed(e))) = outcomes.pop() {
+            return Err(e);
+        }
+        return Ok(());
+    }
+
+    if summary {
+        println!();
+        let mut tw = tabwriter::TabWriter::new(std::io::stdout());
+        for (_, name, outcome) in &outcomes {
+            let result = match outcome {
+                PkgOutcome::Installed(v)        => format!("installed {v}"),
+                PkgOutcome::Updated(old, new)   => format!("updated {old} -> {new}"),
+                PkgOutcome::Reinstalled(v)      => format!("reinstalled {v}"),
+                PkgOutcome::Uninstalled(v)      => format!("uninstalled {v}"),
+                PkgOutcome::Unchanged(reason)   => reason.clone(),
+                PkgOutcome::Failed(e)           => format!("FAILED: {}", format!("{e:#}").lines().next().unwrap_or_default()),
+                PkgOutcome::RolledBack          => "rolled back".into(),
+            };
+            writeln!(&mut tw, "{}\t{}", name, result)?;
+        }
+        tw.flush()?;
+    } else {
+        for (_, name, outcome) in &outcomes {
+            if let PkgOutcome::Failed(e) = outcome {
+                eprintln!("{name}: {e:#}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} package{} failed", failed, given, tern!(given > 1, "s", ""));
+    }
+
+    Ok(())
+}
+
+/// Order items so that each comes after the items it depends on.
+/// A dependency cycle is broken by taking the first remaining item.
+fn dependency_order<T>(mut items: Vec<T>, depends_on: impl Fn(&T, &T) -> bool) -> Vec<T> {
+    let mut ordered = Vec::with_capacity(items.len());
+    while !items.is_empty() {
+        let next = (0..items.len())
+            .find(|&i| !items.iter().enumerate().any(|(j, other)| i != j && depends_on(&items[i], other)))
+            .unwrap_or(0);
+        ordered.push(items.remove(next));
+    }
+    ordered
+}
+
+/// a newer version of an installed package, found by `plan_updates`
+#[derive(Debug)]
+struct PendingUpdate {
+    name: String,
+    old_version: String,
+    listing: search::SingleListing,
+    versioning: Versioning,
+    cache_file: Option<Utf8PathBuf>,
+}
+
 #[derive(Debug)]
 enum PkgArgType {
     Filepath{
@@ -130,11 +302,12 @@ fn parse_pkg_arg(arg: &str) -> AResult<PkgArgType> {
         });
     }
 
+    // only split once, the version may be a requirement such as `foo@>=1.2`
     let mut split = None;
     if arg.contains("@") {
-        split = Some(arg.split('@'));
+        split = Some(arg.splitn(2, '@'));
     } else if arg.contains('=') {
-        split = Some(arg.split('='));
+        split = Some(arg.splitn(2, '='));
     }
     if let Some(split) = &mut split {
 
@@ -169,6 +342,7 @@ impl App {
             provider_filter: provider::ProviderFilter::empty(),
             arch_filter: vec!["".into()],
             lockfile: None,
+            txn: None,
         }
     }
 
@@ -188,6 +362,11 @@ impl App {
             }
         }
 
+        // an active transaction of our own is not an interrupted one
+        if self.txn.is_none() {
+            self.recover_journal()?;
+        }
+
         Ok(())
     }
 
@@ -207,6 +386,73 @@ impl App {
             }
         }
 
+        if journal::exists(&self.config.db_file) {
+            eprintln!("warning: a previous operation was interrupted, it will be recovered by the next install, update, or uninstall");
+        }
+
+        Ok(())
+    }
+
+    /// finish or roll back an interrupted transaction
+    fn recover_journal(&mut self) -> AResult<()> {
+        match journal::recover(&self.config.db_file).context("failed to recover interrupted transaction")? {
+            Some(journal::Recovery::Finished(op)) => {
+                tracing::debug!("finished interrupted transaction '{op}'");
+            }
+            Some(journal::Recovery::RolledBack(op)) => {
+                println!("Rolled back interrupted operation: {op}");
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Run `f` as a transaction.
+    ///
+    /// Paths passed to `journal_save` and the database file are restored if `f` fails,
+    /// or on the next run if bpm is interrupted. Nested calls join the outer transaction.
+    /// The database is saved once, when the transaction commits.
+    fn transaction<T, F>(&mut self, op: &str, f: F) -> AResult<T>
+        where F: FnOnce(&mut Self) -> AResult<T>
+    {
+        if self.txn.is_some() {
+            return f(self);
+        }
+
+        self.txn = Some(journal::Transaction::begin(&self.config.db_file, op)?);
+
+        let ret = f(self);
+
+        let txn = self.txn.take().expect("transaction missing");
+        let ret = ret.and_then(|v| self.save_db().map(|_| v));
+        match ret {
+            Ok(v) => {
+                txn.commit()?;
+                Ok(v)
+            }
+            Err(e) => {
+                eprintln!("{op} failed, rolling back");
+                if let Err(rollback_err) = txn.rollback(&self.config.db_file) {
+                    eprintln!("{:#}", rollback_err);
+                }
+                self.load_db()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Should a confirmation prompt be shown?
+    /// Not when `yes` (--yes) was given, `[ui] assume_yes` is set, or stdin is not a terminal,
+    /// a non-interactive run must never wait for input.
+    fn should_confirm(&self, yes: bool) -> bool {
+        !yes && !self.config.ui_assume_yes && std::io::stdin().is_terminal()
+    }
+
+    /// record the state of a path in an install location in the active transaction, before modifying or removing it
+    fn journal_save(&self, location: &Utf8Path, path: &Utf8Path) -> AResult<()> {
+        if let Some(txn) = &self.txn {
+            txn.save(location, path)?;
+        }
         Ok(())
     }
 
@@ -258,7 +504,13 @@ impl App {
         self.load_db()
     }
 
+    /// Write the database file.
+    /// Inside a transaction this is deferred until the transaction commits.
     pub fn save_db(&self) -> AResult<()> {
+        if self.txn.is_some() {
+            tracing::trace!("saving database deferred to end of transaction");
+            return Ok(());
+        }
         tracing::trace!("saving database");
         // write to a temp file first, then move the tempfile over the existing dbfile
         let mut path = self.config.db_file.clone();
@@ -375,7 +627,8 @@ impl App {
     }
 
     /// install a package from a local package file.
-    fn install_pkg_file(&mut self, file_path: Utf8PathBuf, package_hash: Option<String>, versioning: Versioning, target: Option<&String>) -> AResult<()> {
+    /// into `location`, or the package's own mount point (or the default target) when `None`.
+    fn install_pkg_file(&mut self, file_path: Utf8PathBuf, package_hash: Option<String>, versioning: Versioning, location: Option<config::PathType>, auto_installed: bool) -> AResult<()> {
 
         tracing::debug!("install_pkg_file {}", file_path);
 
@@ -407,6 +660,9 @@ impl App {
             anyhow::bail!("failed to install {}, package file failed integrity check", pkg_name);
         }
 
+        self.check_trust(package_file_filename, &mut file)
+            .with_context(|| format!("failed to install {pkg_name}, signature check failed"))?;
+
         let mut metadata = package::get_metadata(&mut file).context("error reading metadata")?;
 
         // package filename must match package metadata
@@ -423,7 +679,10 @@ impl App {
             anyhow::bail!("failed to installed {}, package file is corrupt. Package architecture file filename does not match package internal metadata.", pkg_name);
         }
 
-        let install_dir = self.get_mountpoint_dir(&metadata, target)?;
+        let install_dir = match location {
+            Some(location) => location,
+            None => self.get_mountpoint_dir(&metadata, None)?,
+        };
         let install_dir_full = install_dir.full_path()?;
 
         // create the mount point dir if it doesn't exist
@@ -454,14 +713,7 @@ impl App {
         details.location = Some(install_dir);
         details.versioning = versioning;
         details.package_file_filename = Some(package_file_filename.to_string());
-
-        self.db.add_package(details);
-
-        self.db.cache_touch(package_file_filename, None);
-        self.db.cache_unuse_all_versions(pkg_name);
-        self.db.cache_set_in_use(package_file_filename, true);
-
-        self.save_db()?;
+        details.auto_installed = auto_installed;
 
         #[cfg(unix)]
         let mut ro_dirs = HashMap::new();
@@ -499,6 +751,8 @@ impl App {
                 }
             }
 
+            self.journal_save(&install_dir_full, &installed_path)?;
+
             let installed_ok = entry.unpack_in(&install_dir_full)?;
 
             bar.set_message(String::from(path.as_str()));
@@ -533,11 +787,57 @@ impl App {
 
         bar.finish_and_clear();
 
+        let mut entry = db::HistoryEntry::new(db::HistoryAction::Install, pkg_name);
+        entry.new_version = Some(metadata.version.clone());
+        entry.arch = metadata.arch.clone();
+        entry.package_hash = package_hash;
+        entry.new_versioning = Some(details.versioning.clone());
+        entry.location = details.location.clone();
+        entry.auto_installed = auto_installed;
+
+        // only record the package once its files are in place
+        self.db.add_package(details);
+        self.db.history_add(entry, self.config.history_limit);
+
+        self.db.cache_touch(package_file_filename, None);
+        self.db.cache_unuse_all_versions(pkg_name);
+        self.db.cache_set_in_use(package_file_filename, true);
+
+        self.save_db()?;
+
         println!("Installation complete");
 
         Ok(())
     }
 
+    /// `find_package_version`, but if the package or version can not be found and `[scan] auto_refresh` is set,
+    /// rescan the package in providers whose package lists are older than that, then try again.
+    fn find_package_version_refresh(&self, pkg_name: &str, which: Option<&str>, required_arch: Option<&str>, preferred_arch: Option<&str>) -> AResult<(search::SingleListing, Versioning)> {
+
+        let result = self.find_package_version(pkg_name, which, required_arch, preferred_arch);
+        if result.is_ok() {
+            return result;
+        }
+
+        let Some(max_age) = self.config.scan_auto_refresh else {
+            return result;
+        };
+
+        let now = chrono::Utc::now();
+        let stale : Vec<_> = self.filtered_providers().filter(|p| p.is_stale(pkg_name, max_age, now)).collect();
+        if stale.is_empty() {
+            return result;
+        }
+
+        eprintln!("Refreshing package lists for {pkg_name}");
+        if let Err(e) = self.scan_providers(&stale, &[pkg_name], Duration::ZERO) {
+            eprintln!("warning: failed to refresh package lists: {e:#}");
+            return result;
+        }
+
+        self.find_package_version(pkg_name, which, required_arch, preferred_arch)
+    }
+
     /// search our local cached files for pkg@version, if not found there, search providers
     fn find_package_version(&self, pkg_name: &str, which: Option<&str>, required_arch: Option<&str>, preferred_arch: Option<&str>) -> AResult<(search::SingleListing, Versioning)> {
 
@@ -559,7 +859,7 @@ impl App {
             .take(tern!(required_arch.is_some(), 1, self.arch_filter.len()));
 
         // first search our locally cached files
-        if let Some(version) = which {
+        if let Some(version) = which && !version::VersionReq::is_req(version) {
 
             for arch in arch_filter_iter.clone() {
                 let path = self.cache_package_lookup(&PackageID {
@@ -604,6 +904,13 @@ impl App {
             } else if pinfo.has_version(v) {
                 pinfo.versions.retain(|version, _vlist| version.as_str() == v);
                 versioning.pinned_to_version = true;
+            } else if version::VersionReq::is_req(v) {
+                let req = version::VersionReq::parse(v)?;
+                pinfo.versions.retain(|version, _vlist| req.matches(version.as_str()));
+                if pinfo.versions.is_empty() {
+                    anyhow::bail!("no version of package '{pkg_name}' satisfies '{req}'");
+                }
+                versioning = Versioning::pinned_range(&req);
             } else {
                 anyhow::bail!("could not find version or channel '{v}' for package '{pkg_name}'");
             }
@@ -635,13 +942,288 @@ impl App {
     /// `bpm install foo`
     /// or `bpm install foo@1.2.3`
     /// or `bpm install path/to/foo_1.2.3.bpm`
-    /// install a package from a provider or directly from a file
-    pub fn install_cmd(&mut self, pkg_name_or_filepath: &str, no_pin: bool, update: bool, reinstall: bool, target: Option<&String>) -> AResult<()> {
+    /// or `bpm install foo bar@1.2 path/to/baz_3.0.0.bpm`
+    /// install packages from a provider or directly from a file
+    ///
+    /// Package files are fetched in parallel, then everything is installed in dependency order in one transaction.
+    /// A package that can't be resolved or fetched is reported as failed without stopping the others.
+    /// If installing one package fails, the transaction rolls back all of them.
+    ///
+    /// With `dry_run`, print the plan of what would be installed instead, without changing anything.
+    pub fn install_cmd(&mut self, pkgs: &[&String], no_pin: bool, update: bool, reinstall: bool, target: Option<&String>, dry_run: bool, json: bool) -> AResult<()> {
 
-        self.exclusive_lock()?;
+        if dry_run {
+            self.shared_lock()?;
+        } else {
+            self.exclusive_lock()?;
+        }
 
         let _trace_span = tracing::trace_span!("bpm install").entered();
 
+        if !dry_run {
+            self.create_load_db()?;
+        } else if self.db_file_exists() {
+            self.load_db()?;
+        }
+
+        let quiet = dry_run && json;
+        let mut outcomes = Vec::new();
+        let mut items = Vec::new();
+
+        for (index, arg) in pkgs.iter().enumerate() {
+            match self.prepare_install(arg, no_pin, update, reinstall, dry_run, quiet) {
+                Ok(Prepared::Install(mut item)) => {
+                    item.index = index;
+                    items.push(*item);
+                }
+                Ok(Prepared::Unchanged(name, reason)) => outcomes.push((index, name, PkgOutcome::Unchanged(reason))),
+                Err(e) => outcomes.push((index, arg.to_string(), PkgOutcome::Failed(e))),
+            }
+        }
+
+        // get every package file, local files are copied into the cache (or read in place by a dry run)
+        // and packages from providers are fetched in parallel
+        let mut fetch = Vec::new();
+        for (idx, item) in items.iter_mut().enumerate() {
+            if let Some(path) = item.local_file.clone() {
+                if dry_run {
+                    item.file = Some((path, None, 0));
+                    continue;
+                }
+                match self.cache_store_file(&path) {
+                    Ok((is_new, cache_file, package_hash)) => {
+                        if is_new && let Some(filename) = cache_file.file_name() {
+                            self.db.cache_insert(filename, package_hash.clone(), None);
+                        }
+                        item.file = Some((cache_file, package_hash, 0));
+                    }
+                    Err(e) => item.error = Some(e),
+                }
+            } else if let Some(path) = self.cache_package_lookup(&item.id) {
+                item.file = Some((path, None, 0));
+            } else if dry_run {
+                // a dry run plans from the provider's listing, without downloading
+                item.download_size = self.dry_run_package_file(&item.id).1;
+            } else {
+                fetch.push(idx);
+            }
+        }
+
+        if !fetch.is_empty() {
+            let cache_dir = self.config.cache_dir.clone();
+            let ids = fetch.iter().map(|idx| items[*idx].id.clone()).collect::<Vec<_>>();
+            for (idx, ret) in fetch.into_iter().zip(self.fetch_parallel(&ids, &cache_dir)) {
+                match ret {
+                    Ok((_is_new, path, package_hash)) => {
+                        if let Some(filename) = path.file_name() {
+                            self.db.cache_insert(filename, package_hash.clone(), None);
+                        }
+                        let size = get_filesize(path.as_str()).unwrap_or(0);
+                        items[idx].file = Some((path, package_hash, size));
+                    }
+                    Err(e) => {
+                        let id = &items[idx].id;
+                        items[idx].error = Some(e.context(format!("failed to fetch {} {}", id.name, id.version)));
+                    }
+                }
+            }
+        }
+
+        // resolve (and fetch) every missing dependency before installing anything,
+        // a dependency that can't be found fails the package that needs it.
+        // Packages given on the command line are not resolved as dependencies of each other.
+        let mut visited = items.iter().filter(|i| i.error.is_none()).map(|i| i.name.clone()).collect::<HashSet<_>>();
+        let mut dependencies = Vec::new();
+        for item in items.iter_mut().filter(|i| i.error.is_none()) {
+
+            // a dry run does not know the dependencies of a package that is not cached
+            let Some((path, ..)) = &item.file else {
+                continue;
+            };
+            let metadata = File::open(path).context("failed to open package file")
+                .and_then(|mut file| package::get_metadata(&mut file).context("error reading metadata"));
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    item.error = Some(e);
+                    continue;
+                }
+            };
+
+            // a failed package leaves no dependencies behind
+            let visited_before = visited.clone();
+            let resolved_before = dependencies.len();
+            if let Err(e) = self.resolve_dependencies(&metadata, &mut visited, &mut dependencies, dry_run) {
+                visited = visited_before;
+                dependencies.truncate(resolved_before);
+                item.error = Some(e);
+                continue;
+            }
+
+            item.metadata = Some(metadata);
+        }
+
+        // a package that depends on a failed package fails too
+        loop {
+            let failed = items.iter().filter(|i| i.error.is_some()).map(|i| i.name.clone()).collect::<HashSet<_>>();
+            let mut changed = false;
+            for item in items.iter_mut().filter(|i| i.error.is_none()) {
+                let Some(metadata) = &item.metadata else {
+                    continue;
+                };
+                let dep = metadata.dependencies.keys().find(|dep| {
+                    failed.contains(*dep) && !self.db.installed.iter().any(|p| &p.metadata.name == *dep)
+                });
+                if let Some(dep) = dep {
+                    item.error = Some(anyhow::anyhow!("dependency '{dep}' failed"));
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let (items, failed) : (Vec<_>, Vec<_>) = items.into_iter().partition(|i| i.error.is_none());
+        for item in failed {
+            outcomes.push((item.index, item.name, PkgOutcome::Failed(item.error.expect("error missing"))));
+        }
+
+        // packages given on the command line are installed after those they depend on
+        let items = dependency_order(items, |a, b| {
+            a.metadata.as_ref().is_some_and(|m| m.dependencies.contains_key(&b.name))
+        });
+
+        if dry_run {
+
+            let mut plan = plan::Plan::default();
+
+            for dep in &dependencies {
+                let mut planned = plan::PlannedPackage::new(plan::PlanAction::Install, &dep.id.name);
+                planned.new_version = Some(dep.id.version.clone());
+                planned.arch = dep.id.arch.clone();
+                planned.required_by = Some(dep.required_by.clone());
+                planned.download_size = dep.download_size;
+                if let Some(path) = &dep.cache_file {
+                    let dep_metadata = package::get_metadata(&mut File::open(path).context("failed to open package file")?)
+                        .with_context(|| format!("error reading metadata of dependency '{}'", dep.id.name))?;
+                    planned.diff_files(None, Some(&dep_metadata));
+                }
+                plan.packages.push(planned);
+            }
+
+            for item in &items {
+                let action = match (item.current.is_some(), reinstall) {
+                    (false, _)    => plan::PlanAction::Install,
+                    (true, true)  => plan::PlanAction::Reinstall,
+                    (true, false) => plan::PlanAction::Update,
+                };
+                let mut planned = plan::PlannedPackage::new(action, &item.name);
+                planned.old_version = item.current.as_ref().map(|p| p.metadata.version.clone());
+                planned.new_version = Some(item.version.clone());
+                planned.arch = item.id.arch.clone();
+                planned.download_size = item.file.as_ref().map_or(item.download_size, |f| Some(f.2));
+                if let Some(metadata) = &item.metadata {
+                    planned.arch = metadata.arch.clone();
+                    planned.diff_files(item.current.as_ref().map(|p| &p.metadata), Some(metadata));
+                }
+                plan.packages.push(planned);
+            }
+
+            let keep = dependencies.iter().filter_map(|d| d.cache_file.as_ref())
+                .chain(items.iter().filter_map(|i| i.file.as_ref().map(|f| &f.0)))
+                .filter_map(|path| path.file_name())
+                .collect::<Vec<_>>();
+            self.plan_auto_clean(&mut plan, &keep);
+
+            plan.print(json)?;
+
+            return report_outcomes(pkgs.len(), outcomes, false);
+        }
+
+        if items.is_empty() {
+            // no transaction ran, save package files added to the cache and packages marked explicit
+            self.save_db()?;
+            return report_outcomes(pkgs.len(), outcomes, true);
+        }
+
+        let names = items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>().join(" ");
+
+        // the package being installed when the transaction failed
+        let mut failing = None;
+
+        let ret = self.transaction(&format!("install {names}"), |app| {
+
+            // dependencies are ordered such that they are installed before anything that depends on them
+            for dep in dependencies {
+                failing = Some(dep.id.name.clone());
+                println!("Installing {} {} (dependency of {})", dep.id.name, dep.id.version, dep.required_by);
+                let cache_file = dep.cache_file.expect("dependency package file missing");
+                app.install_pkg_file(cache_file, dep.package_hash, dep.versioning, None, true)
+                    .with_context(|| format!("failed to install dependency '{}'", dep.id.name))?;
+            }
+
+            for item in &items {
+
+                failing = Some(item.name.clone());
+
+                let (path, package_hash, _size) = item.file.clone().expect("package file missing");
+
+                if let Some(current) = &item.current {
+                    if reinstall {
+                        println!("Installing (re-install) {} {}", item.name, item.version);
+                    } else {
+                        println!("Updating {} from version {} to {}", item.name, current.metadata.version, item.version);
+                    }
+                    app.update_inplace(&item.name, path, package_hash, item.versioning.clone())?;
+                } else {
+                    println!("Installing {} {}", item.name, item.version);
+                    let metadata = item.metadata.as_ref().expect("metadata missing");
+                    let location = target.map(|target| app.get_mountpoint_dir(metadata, Some(target))).transpose()?;
+                    app.install_pkg_file(path, package_hash, item.versioning.clone(), location, false)?;
+                }
+            }
+
+            Ok(())
+        });
+
+        match ret {
+            Ok(()) => {
+                for item in items {
+                    let outcome = match &item.current {
+                        Some(_) if reinstall => PkgOutcome::Reinstalled(item.version),
+                        Some(current) => PkgOutcome::Updated(current.metadata.version.clone(), item.version),
+                        None => PkgOutcome::Installed(item.version),
+                    };
+                    outcomes.push((item.index, item.name, outcome));
+                }
+            }
+            Err(e) => {
+                // a failed dependency is blamed on the first package
+                let failed = items.iter().position(|i| failing.as_ref() == Some(&i.name)).unwrap_or(0);
+                let mut error = Some(e);
+                for (pos, item) in items.into_iter().enumerate() {
+                    if pos == failed && let Some(e) = error.take() {
+                        outcomes.push((item.index, item.name, PkgOutcome::Failed(e)));
+                    } else {
+                        outcomes.push((item.index, item.name, PkgOutcome::RolledBack));
+                    }
+                }
+            }
+        }
+
+        if self.config.cache_auto_clean {
+            tracing::trace!("[install] cache auto clean");
+            let _ = self.cache_clean();
+        }
+
+        report_outcomes(pkgs.len(), outcomes, true)
+    }
+
+    /// Work out what to install for one `bpm install` argument.
+    /// Prints why nothing needs to be done for a package that is already installed, unless `quiet`.
+    fn prepare_install(&mut self, pkg_name_or_filepath: &str, no_pin: bool, update: bool, reinstall: bool, dry_run: bool, quiet: bool) -> AResult<Prepared> {
+
         let mut pkg_file_path : Option<Utf8PathBuf> = None;
         let pkg_name : String;
         let mut pkg_version : Option<String> = None;
@@ -668,22 +1250,29 @@ impl App {
             }
         }
 
-        self.create_load_db()?;
-
         // check if this package is already installed
         // gather info about the current installation
         let current_install = self.db.installed.iter().find(|p| p.metadata.name.as_str() == pkg_name);
         let current_install = current_install.cloned();
-        let current_install = current_install.as_ref();
         let already_installed = current_install.is_some();
 
-        let current_version : Option<&str> = current_install.map(|v| v.metadata.version.as_str());
-        let current_arch    : Option<&str> = current_install.and_then(|v| v.metadata.arch.as_deref().or(Some("noarch")));
+        let current_version : Option<&str> = current_install.as_ref().map(|v| v.metadata.version.as_str());
+        let current_arch    : Option<&str> = current_install.as_ref().and_then(|v| v.metadata.arch.as_deref().or(Some("noarch")));
+
+        // the user asked for this package by name, it is no longer just a dependency
+        if current_install.as_ref().is_some_and(|p| p.auto_installed) && !dry_run {
+            if let Some(pkg) = self.db.installed.iter_mut().find(|p| p.metadata.name == pkg_name) {
+                pkg.auto_installed = false;
+            }
+            println!("Package {} marked as explicitly installed.", pkg_name);
+        }
 
         // if that package is already installed AND --update/--reinstall was not given, then we're done
         if already_installed && !(update || reinstall) {
-            println!("Package {} ({}) is already installed. Pass --update to install a different version.", pkg_name, current_version.unwrap_or(""));
-            return Ok(());
+            if !quiet {
+                println!("Package {} ({}) is already installed. Pass --update to install a different version.", pkg_name, current_version.unwrap_or(""));
+            }
+            return Ok(Prepared::Unchanged(pkg_name, "already installed".into()));
         }
 
         let (pkg_version, pkg_arch, mut versioning) = if from_file {
@@ -694,7 +1283,7 @@ impl App {
             //   OR they may not have, in which case we're grabbing the greatest version.
             // Packages prefer to stay on the same arch that they currently have installed.
             let (listing, versioning) =
-                self.find_package_version(
+                self.find_package_version_refresh(
                     pkg_name.as_str(),
                     pkg_version.as_deref(), // None OR specific version or channel given by user
                     None,                   // no specific arch required,
@@ -711,7 +1300,7 @@ impl App {
             version=pkg_version,
             channel=?versioning.channel,
             arch=pkg_arch,
-            pin =% (if versioning.pinned_to_version { "version" } else if versioning.pinned_to_channel { "channel" } else { "None" }),
+            pin =% (if versioning.pinned_to_version { "version" } else if versioning.pinned_to_channel { "channel" } else if versioning.range.is_some() { "range" } else { "None" }),
             "[install]"
         );
 
@@ -721,109 +1310,381 @@ impl App {
 
         let version_same = already_installed && current_version == Some(pkg_version.as_str());
         let arch_same    = already_installed && package::ArchMatcher::from(current_arch).matches(&pkg_arch);
-        let pinning_same = current_install.is_some_and(|info| info.versioning == versioning);
+        let pinning_same = current_install.as_ref().is_some_and(|info| info.versioning == versioning);
 
         if version_same && !reinstall {
 
-            println!("No change. Package {} at version {} is already installed.", pkg_name, pkg_version);
+            if !quiet {
+                println!("No change. Package {} at version {} is already installed.", pkg_name, pkg_version);
 
-            if !arch_same {
-                println!("This looks like an attempt to change package architecture from '{}' to '{}'.",  current_arch.unwrap_or("noarch"), pkg_arch.as_deref().unwrap_or("noarch"));
-                println!("Pass --reinstall to change package architecture.");
+                if !arch_same {
+                    println!("This looks like an attempt to change package architecture from '{}' to '{}'.",  current_arch.unwrap_or("noarch"), pkg_arch.as_deref().unwrap_or("noarch"));
+                    println!("Pass --reinstall to change package architecture.");
+                }
             }
 
-            if !pinning_same && update {
-                self.db.set_versioning(&pkg_name, versioning.clone());
-                self.save_db()?;
+            if !pinning_same && update && !dry_run {
+                self.set_versioning(&pkg_name, versioning.clone())?;
                 if versioning.pinned_to_channel {
                     println!("Updated pin to channel {}.", versioning.channel.as_deref().unwrap_or("?"));
                 } else if versioning.pinned_to_version {
                     println!("Updated pin to version {}.", pkg_version);
+                } else if let Some(range) = &versioning.range {
+                    println!("Updated pin to version range {}.", range);
                 } else {
                     println!("Updated pinning, no longer pinned to a version or channel");
                 }
             }
-            return Ok(());
+            return Ok(Prepared::Unchanged(pkg_name, "no change".into()));
         }
 
-        // continue to installation
-
         let id = PackageID {
             name: pkg_name.clone(),
             version: pkg_version.to_string(),
             arch: pkg_arch,
         };
 
-        let (is_new, cache_file, package_hash) = if let Some(path) = pkg_file_path {
-            self.cache_store_file(&path)?
+        Ok(Prepared::Install(Box::new(InstallItem {
+            index: 0,
+            name: pkg_name,
+            version: pkg_version,
+            id,
+            versioning,
+            current: current_install,
+            local_file: pkg_file_path,
+            file: None,
+            download_size: None,
+            metadata: None,
+            error: None,
+        })))
+    }
+
+    /// Get a package's file from the cache, or fetch it from a provider.
+    /// A new cache file is recorded in the db, which the caller saves.
+    /// returns (path, package_hash, downloaded_bytes)
+    fn require_package_file(&mut self, id: &PackageID) -> AResult<(Utf8PathBuf, Option<String>, u64)> {
+        let (is_new, path, hash) = self.cache_package_require(id)?;
+        let mut size = 0;
+        if is_new && let Some(filename) = path.file_name() {
+            self.db.cache_insert(filename, hash.clone(), None);
+            size = get_filesize(path.as_str()).unwrap_or(0);
+        }
+        Ok((path, hash, size))
+    }
+
+    /// Resolve the dependencies of a package that are not yet installed.
+    /// Each dependency's package file is pulled into the cache so that its own dependencies can be resolved.
+    /// A dry run downloads nothing, the dependencies of a package that is not cached are left unresolved.
+    /// `resolved` is filled in install order, dependencies come before their dependents.
+    /// `visited` holds package names already handled, which also guards against dependency cycles.
+    fn resolve_dependencies(&mut self, metadata: &package::MetaData, visited: &mut HashSet<String>, resolved: &mut Vec<ResolvedDependency>, dry_run: bool) -> AResult<()> {
+
+        for (dep_name, dep_version) in &metadata.dependencies {
+
+            // "*" means any version, otherwise an exact version or a requirement such as "^1.4"
+            let which = dep_version.as_deref().filter(|v| v.trim() != "*");
+
+            if !visited.insert(dep_name.clone()) {
+                if let Some(v) = which && let Some(dep) = resolved.iter().find(|d| &d.id.name == dep_name) && !version_satisfies(v, &dep.id.version) {
+                    eprintln!("warning: {} requires {}@{}, but version {} is being installed", metadata.name, dep_name, v, dep.id.version);
+                }
+                continue;
+            }
+
+            if let Some(installed) = self.db.installed.iter().find(|p| &p.metadata.name == dep_name) {
+                if let Some(v) = which && !version_satisfies(v, &installed.metadata.version) {
+                    eprintln!("warning: {} requires {}@{}, but version {} is installed", metadata.name, dep_name, v, installed.metadata.version);
+                }
+                tracing::debug!(name=dep_name, version=installed.metadata.version, "dependency already installed");
+                continue;
+            }
+
+            let (listing, versioning) = self.find_package_version_refresh(dep_name, which, None, metadata.arch.as_deref())
+                .with_context(|| format!("could not resolve dependency '{}{}' of package '{}'",
+                    dep_name,
+                    which.map_or(String::new(), |v| format!("@{v}")),
+                    metadata.name
+                ))?;
+
+            let id = PackageID {
+                name: dep_name.clone(),
+                version: listing.version.to_string(),
+                arch: listing.arch,
+            };
+
+            let (cache_file, package_hash, download_size) = if dry_run {
+                let (cache_file, download_size) = self.dry_run_package_file(&id);
+                (cache_file, None, download_size)
+            } else {
+                let (cache_file, package_hash, download_size) = self.require_package_file(&id)?;
+                (Some(cache_file), package_hash, Some(download_size))
+            };
+
+            if let Some(cache_file) = &cache_file {
+                let dep_metadata = package::get_metadata(&mut File::open(cache_file).context("failed to open package file")?)
+                    .with_context(|| format!("error reading metadata of dependency '{dep_name}'"))?;
+
+                self.resolve_dependencies(&dep_metadata, visited, resolved, dry_run)?;
+            }
+
+            resolved.push(ResolvedDependency {
+                id,
+                versioning,
+                cache_file,
+                package_hash,
+                required_by: metadata.name.clone(),
+                download_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// `bpm uninstall` or `bpm remove`
+    /// uninstall packages
+    ///
+    /// Refuses to uninstall a package that other installed packages depend on,
+    /// unless `force` (uninstall anyway) or `cascade` (also uninstall the dependents) is given.
+    /// Dependents that are being uninstalled in the same invocation don't count.
+    ///
+    /// All packages are uninstalled in one transaction, dependents before the packages they depend on.
+    ///
+    /// With `dry_run`, print the plan of what would be uninstalled instead, without changing anything.
+    pub fn uninstall_cmd(&mut self, pkgs: &[&String], verbose: bool, remove_unowned: bool, force: bool, cascade: bool, dry_run: bool, json: bool, yes: bool) -> AResult<()> {
+
+        if dry_run {
+            self.shared_lock()?;
         } else {
-            //println!("Fetching {pkg_name} {pkg_version}");
-            self.cache_package_require(&id)?
-        };
+            self.exclusive_lock()?;
+        }
 
-        // insert the package file into cache
-        if is_new {
-            if let Some(filename) = cache_file.file_name() {
-                self.db.cache_insert(filename, package_hash.clone(), None);
-                self.save_db()?;
+        // from the package name,
+        // find all files that belong to this package from the db
+
+        if self.db_file_exists() {
+            self.load_db()?;
+        }
+
+        let mut outcomes = Vec::new();
+        let mut requested = Vec::new();
+
+        for (index, pkg_name) in pkgs.iter().enumerate() {
+            if requested.iter().any(|(_, name)| name == *pkg_name) {
+                continue;
+            }
+            if self.db.installed.iter().any(|e| &e.metadata.name == *pkg_name) {
+                requested.push((index, pkg_name.to_string()));
+            } else {
+                if !(dry_run && json) {
+                    println!("package '{pkg_name}' not installed");
+                }
+                outcomes.push((index, pkg_name.to_string(), PkgOutcome::Unchanged("not installed".into())));
             }
         }
 
-        if already_installed {
-            if reinstall {
-                println!("Installing (re-install) {pkg_name} {pkg_version}");
+        // Without --cascade or --force a package with dependents that stay installed can't be removed,
+        // which in turn keeps whatever it depends on.
+        let mut to_remove = Vec::new();
+        loop {
+            let removing = requested.iter().map(|(_, name)| name.clone()).collect::<HashSet<_>>();
+            let blocked = requested.iter().position(|(_, name)| {
+                !cascade && !force && self.db.dependents(name).iter().any(|p| !removing.contains(&p.metadata.name))
+            });
+            let Some(pos) = blocked else { break };
+            let (index, pkg_name) = requested.remove(pos);
+            let direct = self.db.dependents(&pkg_name).iter()
+                .map(|p| p.metadata.name.as_str())
+                .filter(|name| !removing.contains(*name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let e = anyhow::anyhow!("cannot uninstall {pkg_name}, it is required by: {direct}\nPass --cascade to also uninstall dependents, or --force to uninstall anyway.");
+            outcomes.push((index, pkg_name, PkgOutcome::Failed(e)));
+        }
+
+        for (_, pkg_name) in &requested {
+            let dependents = self.db.dependents_recursive(pkg_name).into_iter()
+                .filter(|name| !requested.iter().any(|(_, r)| r == name))
+                .collect::<Vec<_>>();
+            if dependents.is_empty() {
+                continue;
+            }
+            if cascade {
+                if !dry_run {
+                    println!("Also uninstalling dependents of {}: {}", pkg_name, dependents.join(", "));
+                }
+                for name in dependents {
+                    if !to_remove.contains(&name) {
+                        to_remove.push(name);
+                    }
+                }
             } else {
-                println!("Updating {} from version {} to {}", pkg_name, current_version.unwrap_or("?"), pkg_version);
+                let direct = self.db.dependents(pkg_name).iter().map(|p| p.metadata.name.as_str()).collect::<Vec<_>>().join(", ");
+                eprintln!("warning: uninstalling {} breaks packages that depend on it: {}", pkg_name, direct);
+            }
+        }
+        for (_, pkg_name) in &requested {
+            if !to_remove.contains(pkg_name) {
+                to_remove.push(pkg_name.clone());
             }
-        } else {
-            println!("Installing {pkg_name} {pkg_version}");
         }
 
-        let ret = if already_installed {
-            self.update_inplace(&pkg_name, cache_file, package_hash, versioning)
-        } else {
-            self.install_pkg_file(cache_file, package_hash, versioning, target)
-        };
+        // dependents are removed before the packages they depend on
+        let to_remove = dependency_order(to_remove, |a, b| {
+            self.db.installed.iter().any(|p| &p.metadata.name == b && p.metadata.dependencies.contains_key(a))
+        });
+
+        if dry_run {
+            let mut plan = plan::Plan::default();
+            for name in &to_remove {
+                if let Some(pkg) = self.db.installed.iter().find(|p| &p.metadata.name == name) {
+                    let mut planned = plan::PlannedPackage::new(plan::PlanAction::Uninstall, name);
+                    planned.old_version = Some(pkg.metadata.version.clone());
+                    planned.arch = pkg.metadata.arch.clone();
+                    planned.diff_files(Some(&pkg.metadata), None);
+                    plan.packages.push(planned);
+                }
+            }
+            self.plan_auto_clean(&mut plan, &[]);
+            plan.print(json)?;
+            return report_outcomes(pkgs.len(), outcomes, false);
+        }
+
+        if to_remove.is_empty() {
+            return report_outcomes(pkgs.len(), outcomes, true);
+        }
+
+        if self.should_confirm(yes) {
+            println!("{} package{} to uninstall:", to_remove.len(), tern!(to_remove.len() > 1, "s", ""));
+            for name in &to_remove {
+                if let Some(pkg) = self.db.installed.iter().find(|p| &p.metadata.name == name) {
+                    println!("  {} {}", pkg.metadata.name, pkg.metadata.version);
+                }
+            }
+            if !confirm("Continue?")? {
+                println!("Aborted");
+                return Ok(());
+            }
+        }
+
+        let versions = requested.iter()
+            .map(|(_, name)| self.db.installed.iter().find(|p| &p.metadata.name == name).map(|p| p.metadata.version.clone()).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        let names = requested.iter().map(|(_, name)| name.as_str()).collect::<Vec<_>>().join(" ");
+
+        // the package being uninstalled when the transaction failed
+        let mut failing = None;
+
+        let ret = self.transaction(&format!("uninstall {names}"), |app| {
+            for name in &to_remove {
+                failing = Some(name.clone());
+                app.uninstall_pkg(name, verbose, remove_unowned)?;
+            }
+            Ok(())
+        });
+
+        match ret {
+            Ok(()) => {
+                println!("Uninstall complete");
+                for ((index, name), version) in requested.into_iter().zip(versions) {
+                    outcomes.push((index, name, PkgOutcome::Uninstalled(version)));
+                }
+            }
+            Err(e) => {
+                // a failed dependent removed by --cascade is blamed on the first package
+                let failed = requested.iter().position(|(_, name)| failing.as_ref() == Some(name)).unwrap_or(0);
+                let mut error = Some(e);
+                for (pos, (index, name)) in requested.into_iter().enumerate() {
+                    if pos == failed && let Some(e) = error.take() {
+                        outcomes.push((index, name, PkgOutcome::Failed(e)));
+                    } else {
+                        outcomes.push((index, name, PkgOutcome::RolledBack));
+                    }
+                }
+            }
+        }
 
         if self.config.cache_auto_clean {
-            tracing::trace!("[install] cache auto clean");
+            tracing::trace!("[uninstall] cache auto clean");
             let _ = self.cache_clean();
         }
 
-        return ret;
+        report_outcomes(pkgs.len(), outcomes, true)
     }
 
-    /// `bpm uninstall` or `bpm remove`
-    /// uninstall a package
-    pub fn uninstall_cmd(&mut self, pkg_name: &String, verbose: bool, remove_unowned: bool) -> AResult<()> {
+    /// `bpm autoremove`
+    /// uninstall auto-installed packages that are no longer needed by any installed package
+    pub fn autoremove_cmd(&mut self, yes: bool, verbose: bool) -> AResult<()> {
 
         self.exclusive_lock()?;
 
-        // from the package name,
-        // find all files that belong to this package from the db
-
         if !self.db_file_exists() {
-            println!("package '{pkg_name}' not installed");
-            std::process::exit(0);
+            println!("No packages to remove");
+            return Ok(());
         }
 
         self.load_db()?;
 
-        let found = self.db.installed.iter().find(|e| &e.metadata.name == pkg_name);
+        let orphans = self.db.orphaned_packages();
+        if orphans.is_empty() {
+            println!("No packages to remove");
+            return Ok(());
+        }
+
+        println!("{} package{} to remove:", orphans.len(), tern!(orphans.len() > 1, "s", ""));
+        for name in &orphans {
+            if let Some(pkg) = self.db.installed.iter().find(|p| &p.metadata.name == name) {
+                println!("  {} {}", pkg.metadata.name, pkg.metadata.version);
+            }
+        }
+
+        // autoremove chooses what to remove itself, a non-interactive run needs explicit consent
+        if !yes && !self.config.ui_assume_yes && !std::io::stdin().is_terminal() {
+            anyhow::bail!("not running interactively, pass --yes to remove packages");
+        }
 
-        if found.is_none() {
-            println!("package '{pkg_name}' not installed");
-            std::process::exit(0);
+        if self.should_confirm(yes) && !confirm("Remove these packages?")? {
+            println!("Aborted");
+            return Ok(());
         }
 
-        let pkg = found.unwrap();
+        self.transaction("autoremove", |app| {
+            for name in &orphans {
+                app.uninstall_pkg(name, verbose, false)?;
+            }
+            Ok(())
+        })?;
+
+        println!("Autoremove complete");
+
+        if self.config.cache_auto_clean {
+            tracing::trace!("[autoremove] cache auto clean");
+            let _ = self.cache_clean();
+        }
+
+        Ok(())
+    }
+
+    /// remove an installed package's files and its db entry
+    fn uninstall_pkg(&mut self, pkg_name: &str, verbose: bool, remove_unowned: bool) -> AResult<()> {
+
+        let pkg = self.db.installed.iter().find(|e| e.metadata.name == pkg_name).context("package not installed")?;
         let package_file_filename = pkg.package_file_filename.clone();
 
         println!("Uninstalling {} {}", pkg.metadata.name, pkg.metadata.version);
 
         self.delete_package_files(pkg, verbose, remove_unowned)?;
 
+        let mut entry = db::HistoryEntry::new(db::HistoryAction::Uninstall, pkg_name);
+        entry.old_version = Some(pkg.metadata.version.clone());
+        entry.arch = pkg.metadata.arch.clone();
+        entry.package_hash = package_file_filename.as_deref().and_then(|f| self.db.cache_get_hash(f));
+        entry.old_versioning = Some(pkg.versioning.clone());
+        entry.location = pkg.location.clone();
+        entry.auto_installed = pkg.auto_installed;
+
         self.db.remove_package(pkg.metadata.id());
+        self.db.history_add(entry, self.config.history_limit);
 
         if let Some(filename) = package_file_filename {
             self.db.cache_set_in_use(&filename, false);
@@ -835,13 +1696,6 @@ impl App {
 
         self.save_db()?;
 
-        println!("Uninstall complete");
-
-        if self.config.cache_auto_clean {
-            tracing::trace!("[uninstall] cache auto clean");
-            let _ = self.cache_clean();
-        }
-
         Ok(())
     }
 
@@ -865,6 +1719,9 @@ impl App {
             tracing::trace!("package integrity check pass");
         }
 
+        self.check_trust(package_file_filename, &mut new_package_fd)
+            .with_context(|| format!("failed to install {pkg_name}, signature check failed"))?;
+
         let new_metadata = package::get_metadata(&mut new_package_fd).context("error reading metadata")?;
 
         let new_files = new_metadata.files.clone();
@@ -872,6 +1729,20 @@ impl App {
         // gather info about the old package (the currently installed version)
         let current_pkg_info = self.db.installed.iter().find(|e| e.metadata.name == pkg_name).context("package is not currently installed")?;
         let old_package_filename = current_pkg_info.package_file_filename.clone();
+        let auto_installed = current_pkg_info.auto_installed;
+        let previous = old_package_filename.as_ref().map(|filename| db::PreviousVersion {
+            version: current_pkg_info.metadata.version.clone(),
+            package_file_filename: filename.clone(),
+            package_hash: self.db.cache_get_hash(filename),
+        });
+
+        let mut entry = db::HistoryEntry::new(db::HistoryAction::Update, pkg_name);
+        entry.old_version = Some(current_pkg_info.metadata.version.clone());
+        entry.new_version = Some(new_metadata.version.clone());
+        entry.arch = new_metadata.arch.clone();
+        entry.package_hash = package_hash.clone();
+        entry.old_versioning = Some(current_pkg_info.versioning.clone());
+        entry.new_versioning = Some(versioning.clone());
         let mut old_files = current_pkg_info.metadata.files.clone();
 
         let location = current_pkg_info.location.as_ref().context("installed package has no location")?.clone();
@@ -893,10 +1764,17 @@ impl App {
         // these are the files to remove
         let remove_files = old_files;
 
+        for path in remove_files.keys() {
+            self.journal_save(&location_full, &join_path_utf8!(&location_full, path))?;
+        }
+
+        // unowned dir contents are not journaled, so a transaction leaves them in place
+        let remove_unowned = self.txn.is_none();
+
         let delete_thread = std::thread::spawn({
             let location_full = location_full.clone();
             let pkg_name = pkg_name.to_string();
-            move || {
+            move || -> AResult<()> {
 
                 let iter = remove_files.iter().map(|(path, info)| {
                     let path = join_path_utf8!(&location_full, &path);
@@ -911,10 +1789,7 @@ impl App {
 
                 let iter = delete_bar.wrap_iter(iter).with_finish(indicatif::ProgressFinish::AndClear);
 
-                let delete_ok = Self::delete_files(iter, false, true);
-                if let Err(e) = delete_ok {
-                    eprintln!("error deleting files {:?}", e);
-                }
+                Self::delete_files(iter, false, remove_unowned).context("error deleting files")
             }
         });
 
@@ -1031,9 +1906,7 @@ impl App {
 
         // wait for deletes to be finished before starting unpacks,
         // there could be conflicting file types
-        if delete_thread.join().is_err() {
-            anyhow::bail!("Error in file deletion thread");
-        }
+        delete_thread.join().map_err(|_| anyhow::anyhow!("Error in file deletion thread"))??;
 
         let update_bar = bpmutil::status::global().add_task(Some("update"), Some(pkg_name), Some((new_files.len() - skip_files.len()) as u64));
         update_bar.set_style(indicatif::ProgressStyle::with_template(
@@ -1048,6 +1921,9 @@ impl App {
         let mut zstd = zstd::stream::read::Decoder::new(&mut inner_tar)?;
         let mut data_tar = tar::Archive::new(&mut zstd);
 
+        #[cfg(unix)]
+        let mut ro_dirs = HashMap::new();
+
         for entry in data_tar.entries()? {
             let mut entry = entry?;
             let path = entry.path()?;
@@ -1057,20 +1933,58 @@ impl App {
             } else {
                 tracing::debug!("updating   {}", path);
                 update_bar.set_message(String::from(path.as_str()));
-                let _ok = entry.unpack_in(&location_full);
-                //TODO handle error
+
+                let updated_path = join_path_utf8!(&location_full, &path);
+
+                // check if the parent dir is readonly, unpacking a dir entry sets its mode,
+                // need to add write permission to be able to update files in the dir
+                #[cfg(unix)]
+                if let Some(parent_dir) = updated_path.parent() && !ro_dirs.contains_key(parent_dir) {
+                    ro_dirs.insert(parent_dir.to_path_buf(), None);
+                    if let Ok(md) = std::fs::metadata(parent_dir) {
+
+                        let mut perms = md.permissions();
+                        let mut mode = perms.mode();
+
+                        if 0 == (mode & 0o200) {
+                            // user does not have write access
+                            ro_dirs.insert(parent_dir.to_path_buf(), Some(mode));
+
+                            mode |= 0o200;
+                            perms.set_mode(mode);
+                            let _ = std::fs::set_permissions(parent_dir, perms);
+                        }
+                    }
+                }
+
+                self.journal_save(&location_full, &updated_path)?;
+                entry.unpack_in(&location_full).with_context(|| format!("failed to unpack {path}"))?;
                 update_bar.inc(1);
             }
         }
 
+        // restore the permissions on any readonly dir that was modified during the update
+        #[cfg(unix)]
+        for (dir, mode) in ro_dirs {
+            if let Some(mode) = mode {
+                let md = std::fs::metadata(&dir)?;
+                let mut perms = md.permissions();
+                perms.set_mode(mode);
+                let _ = std::fs::set_permissions(&dir, perms);
+            }
+        }
+
         update_bar.finish_and_clear();
 
         let mut details = db::DbPkg::new(new_metadata);
         details.location = Some(location);
         details.versioning = versioning;
         details.package_file_filename = Some(String::from(package_file_filename));
+        details.auto_installed = auto_installed;
+        details.previous = previous;
 
         self.db.add_package(details);
+        self.db.history_add(entry, self.config.history_limit);
         self.db.cache_touch(package_file_filename, None);
         self.db.cache_unuse_all_versions(pkg_name);
         self.db.cache_set_in_use(package_file_filename, true);
@@ -1086,19 +2000,143 @@ impl App {
         Ok(())
     }
 
-    /// `bpm update`
-    pub fn update_packages_cmd(&mut self, pkgs: &[&String]) -> AResult<()> {
+    /// `bpm rollback`
+    /// restore the version of a package that was installed before its last update,
+    /// using the package file kept in the cache. No provider is contacted.
+    pub fn rollback_cmd(&mut self, pkg_name: &str) -> AResult<()> {
 
         self.exclusive_lock()?;
 
+        if !self.db_file_exists() {
+            anyhow::bail!("package '{pkg_name}' not installed");
+        }
+
+        self.load_db()?;
+
+        let pkg = self.db.installed.iter().find(|p| p.metadata.name == pkg_name).with_context(|| format!("package '{pkg_name}' not installed"))?;
+        let current_version = pkg.metadata.version.clone();
+        let previous = pkg.previous.clone().with_context(|| format!("no previous version of {pkg_name} to roll back to"))?;
+
+        let path = join_path_utf8!(&self.config.cache_dir, "packages", &previous.package_file_filename);
+        if !path.exists() {
+            anyhow::bail!("cannot roll back {pkg_name}, package file {} is no longer in the cache", previous.package_file_filename);
+        }
+
+        // stay on the previous version until the user unpins
+        let versioning = Versioning::pinned_version();
+
+        println!("Rolling back {} {} -> {}", pkg_name, current_version, previous.version);
+        self.transaction(&format!("rollback {pkg_name}"), |app| {
+            app.update_inplace(pkg_name, path, previous.package_hash.clone(), versioning)
+        })?;
+
+        println!("Rollback complete, {pkg_name} is pinned to {}. Use `bpm unpin {pkg_name}` to allow updates again.", previous.version);
+
+        Ok(())
+    }
+
+    /// `bpm update`
+    ///
+    /// With `dry_run`, print the plan of what would be updated instead, without changing anything.
+    pub fn update_packages_cmd(&mut self, pkgs: &[&String], dry_run: bool, json: bool, yes: bool) -> AResult<()> {
+
+        if dry_run {
+            self.shared_lock()?;
+        } else {
+            self.exclusive_lock()?;
+        }
+
         // nothing to do if the db file doesn't exist yet, nothing to update
         if !self.db_file_exists() {
+            if dry_run {
+                return plan::Plan::default().print(json);
+            }
             return Ok(());
         }
 
         self.load_db()?;
 
         // determine which packages need to be updated
+        let mut updates = self.plan_updates(pkgs, dry_run && json)?;
+
+        if dry_run {
+            return self.print_update_plan(updates, json);
+        }
+
+        if updates.is_empty() {
+            println!("No updates to apply");
+            return Ok(());
+        }
+
+        println!("{} package{} to update:", updates.len(), tern!(updates.len() > 1, "s", ""));
+        for update in &updates {
+            println!("  {}: {} -> {}", update.name, update.old_version, update.listing.version);
+        }
+
+        if self.should_confirm(yes) && !confirm("Continue?")? {
+            println!("Aborted");
+            return Ok(());
+        }
+
+        if updates.iter().any(|update| update.cache_file.is_none()) {
+
+            println!("Fetching Packages");
+
+            for update in &mut updates {
+                if update.cache_file.is_none() {
+                    let ret = self.cache_package_require(&PackageID{
+                        name: update.name.to_string(),
+                        version: update.listing.version.to_string(),
+                        arch: update.listing.arch.clone(),
+                    });
+
+                    if let Ok((is_new, path, hash)) = ret {
+
+                        // if the new package file is new, insert into the cache
+                        if is_new {
+                            if let Some(filename) = path.file_name() {
+                                self.db.cache_insert(filename, hash, None);
+                            }
+                        }
+
+                        update.cache_file = Some(path);
+
+                    } else {
+                        println!("failed to fetch {} {}, skipping", update.name, update.listing.version);
+                    }
+                }
+            }
+        }
+
+        // remove any updates that don't have an incoming package file
+        updates.retain(|update| update.cache_file.is_some());
+
+        let count = self.transaction("update", |app| {
+            let mut count = 0;
+            for update in updates {
+                if let Some(path) = update.cache_file {
+                    println!("Updating {} {} -> {}", update.name, update.old_version, update.listing.version);
+                    app.update_inplace(&update.name, path, None, update.versioning)?;
+                    count += 1;
+                }
+            }
+            Ok(count)
+        })?;
+
+        println!("Updates Complete, {} package{} updated", count, tern!(count > 1, "s", ""));
+
+        if self.config.cache_auto_clean {
+            tracing::trace!("[update] cache auto clean");
+            let _ = self.cache_clean();
+        }
+
+        Ok(())
+    }
+
+    /// Find the installed packages that have a newer version available.
+    /// Only packages named in `pkgs` are considered, or all installed packages if `pkgs` is empty.
+    fn plan_updates(&self, pkgs: &[&String], quiet: bool) -> AResult<Vec<PendingUpdate>> {
+
         let mut updates = Vec::new();
 
         for pkg in &self.db.installed {
@@ -1109,7 +2147,9 @@ impl App {
             }
 
             if pkg.versioning.pinned_to_version {
-                println!("{} is pinned to {}, skipping", pkg.metadata.name, pkg.metadata.version);
+                if !quiet {
+                    println!("{} is pinned to {}, skipping", pkg.metadata.name, pkg.metadata.version);
+                }
                 continue;
             }
 
@@ -1123,109 +2163,109 @@ impl App {
                 }
             }
 
+            // or within a version range?
+            if let Some(range) = &pkg.versioning.range {
+                channel = Some(range.clone());
+            }
+
             let required_arch = &pkg.metadata.arch;
             let required_arch = required_arch.as_deref().or(Some("noarch"));
 
-            let result = self.find_package_version(&pkg.metadata.name, channel.as_deref(), required_arch, None);
+            let result = self.find_package_version_refresh(&pkg.metadata.name, channel.as_deref(), required_arch, None);
 
             if let Ok((listing, versioning)) = result {
                 // if the version is the same as already installed, skip the update
                 if listing.version == Version::from(pkg.metadata.version.as_str()) {
-                    println!("{} already up-to-date", pkg.metadata.name);
+                    if !quiet {
+                        println!("{} already up-to-date", pkg.metadata.name);
+                    }
                     continue;
                 }
-                let cached_file = self.cache_package_lookup(&PackageID{
+                let cache_file = self.cache_package_lookup(&PackageID{
                     name: pkg.metadata.name.clone(),
                     version: listing.version.to_string(),
                     arch: listing.arch.clone(),
                 });
 
-                updates.push((pkg.metadata.name.clone(), pkg.metadata.version.clone(), listing, versioning, cached_file));
+                updates.push(PendingUpdate {
+                    name: pkg.metadata.name.clone(),
+                    old_version: pkg.metadata.version.clone(),
+                    listing,
+                    versioning,
+                    cache_file,
+                });
             }
         }
 
-        if updates.is_empty() {
-            println!("No updates to apply");
-            return Ok(());
-        }
-
-        println!("{} package{} to update:", updates.len(), tern!(updates.len() > 1, "s", ""));
-        for (name, oldv, listing, _versioning, _cache_file) in &updates {
-            println!("  {}: {} -> {}", name, oldv, listing.version);
-        }
+        Ok(updates)
+    }
 
-        if updates.iter().any(|(_name, _version, _listing, _versioning, cache_file)| cache_file.is_none()) {
+    /// print what `bpm update` would do with the given updates
+    fn print_update_plan(&mut self, updates: Vec<PendingUpdate>, json: bool) -> AResult<()> {
 
-            println!("Fetching Packages");
+        let mut plan = plan::Plan::default();
+        let mut keep = Vec::new();
 
-            for (name, _oldv, listing, _versioning, cache_file) in &mut updates {
-                if cache_file.is_none() {
-                    let ret = self.cache_package_require(&PackageID{
-                        name: name.to_string(),
-                        version: listing.version.to_string(),
-                        arch: listing.arch.clone(),
-                    });
+        for update in updates {
 
-                    if let Ok((is_new, path, hash)) = ret {
+            let id = PackageID {
+                name: update.name.clone(),
+                version: update.listing.version.to_string(),
+                arch: update.listing.arch.clone(),
+            };
+            let (path, download_size) = match update.cache_file {
+                Some(path) => (Some(path), Some(0)),
+                None => self.dry_run_package_file(&id),
+            };
 
-                        // if the new package file is new, insert into the cache
-                        if is_new {
-                            if let Some(filename) = path.file_name() {
-                                self.db.cache_insert(filename, hash, None);
-                            }
-                        }
+            let mut planned = plan::PlannedPackage::new(plan::PlanAction::Update, &update.name);
+            planned.old_version = Some(update.old_version);
+            planned.new_version = Some(id.version.clone());
+            planned.arch = id.arch.clone();
+            planned.download_size = download_size;
 
-                        *cache_file = Some(path);
+            if let Some(path) = path {
+                let metadata = package::get_metadata(&mut File::open(&path).context("failed to open package file")?)
+                    .context("error reading metadata")?;
+                let current = self.db.installed.iter().find(|p| p.metadata.name == update.name);
+                planned.diff_files(current.map(|p| &p.metadata), Some(&metadata));
 
-                    } else {
-                        println!("failed to fetch {} {}, skipping", name, listing.version);
-                    }
+                if let Some(filename) = path.file_name() {
+                    keep.push(filename.to_string());
                 }
             }
-        }
-
-        // remove any updates that don't have an incoming package file
-        updates.retain(|(_name, _version, _listing, _versioning, cache_file)| cache_file.is_some());
 
-        let mut count = 0;
-        for (name, oldv, listing, versioning, cache_file) in updates {
-            if let Some(path) = cache_file {
-                println!("Updating {} {} -> {}", name, oldv, listing.version);
-                self.update_inplace(&name, path, None, versioning)?;
-                count += 1;
-            }
+            plan.packages.push(planned);
         }
 
-        println!("Updates Complete, {} package{} updated", count, tern!(count > 1, "s", ""));
-
-        //TODO can put confirmation prompt here
+        let keep = keep.iter().map(String::as_str).collect::<Vec<_>>();
+        self.plan_auto_clean(&mut plan, &keep);
 
-        if self.config.cache_auto_clean {
-            tracing::trace!("[update] cache auto clean");
-            let _ = self.cache_clean();
-        }
-
-        Ok(())
+        plan.print(json)
     }
 
     /// `bpm pin`
-    /// Pin a package to a channel or the currently installed version
-    pub fn pin(&mut self, pkg_name: &str, channel: Option<&str>) -> AResult<()> {
+    /// Pin a package to a channel, a version range, or the currently installed version
+    pub fn pin(&mut self, pkg_name: &str, channel: Option<&str>, range: Option<&str>) -> AResult<()> {
 
         self.exclusive_lock()?;
 
+        let range = range.map(version::VersionReq::parse).transpose()?;
+
         if self.db_file_exists() {
             self.load_db()?;
-            for pkg in &mut self.db.installed {
-                if pkg.metadata.name == pkg_name {
-                    if let Some(channel) = channel {
-                        pkg.versioning = Versioning::pinned_channel(channel);
-                    } else {
-                        pkg.versioning = Versioning::pinned_version();
+            if let Some(pkg) = self.db.installed.iter().find(|p| p.metadata.name == pkg_name) {
+                let versioning = if let Some(channel) = channel {
+                    Versioning::pinned_channel(channel)
+                } else if let Some(range) = &range {
+                    if !range.matches(&pkg.metadata.version) {
+                        println!("Installed version {} does not satisfy '{}', `bpm update {}` will change versions.", pkg.metadata.version, range, pkg_name);
                     }
-                    self.save_db()?;
-                    return Ok(());
-                }
+                    Versioning::pinned_range(range)
+                } else {
+                    Versioning::pinned_version()
+                };
+                return self.set_versioning(pkg_name, versioning);
             }
         }
 
@@ -1240,18 +2280,34 @@ impl App {
 
         if self.db_file_exists() {
             self.load_db()?;
-            for pkg in &mut self.db.installed {
-                if pkg.metadata.name == pkg_name {
-                    pkg.versioning = Versioning::unpinned();
-                    self.save_db()?;
-                    return Ok(());
-                }
+            if self.db.installed.iter().any(|p| p.metadata.name == pkg_name) {
+                return self.set_versioning(pkg_name, Versioning::unpinned());
             }
         }
 
         anyhow::bail!("package not found");
     }
 
+    /// change the versioning of an installed package, recording it in the history
+    fn set_versioning(&mut self, pkg_name: &str, versioning: Versioning) -> AResult<()> {
+
+        let pkg = self.db.installed.iter().find(|p| p.metadata.name == pkg_name).context("package not found")?;
+
+        let action = tern!(versioning == Versioning::unpinned(), db::HistoryAction::Unpin, db::HistoryAction::Pin);
+        let mut entry = db::HistoryEntry::new(action, pkg_name);
+        entry.old_version = Some(pkg.metadata.version.clone());
+        entry.new_version = Some(pkg.metadata.version.clone());
+        entry.arch = pkg.metadata.arch.clone();
+        entry.old_versioning = Some(pkg.versioning.clone());
+        entry.new_versioning = Some(versioning.clone());
+
+        if entry.old_versioning != entry.new_versioning {
+            self.db.set_versioning(pkg_name, versioning);
+            self.db.history_add(entry, self.config.history_limit);
+        }
+        self.save_db()
+    }
+
     /// `bpm verify`
     /// Verify package state.
     ///
@@ -1634,6 +2690,10 @@ impl App {
 
         let count = pkg.metadata.files.len();
 
+        for path in pkg.metadata.files.keys() {
+            self.journal_save(&location_full, &join_path_utf8!(&location_full, path))?;
+        }
+
         let iter = pkg.metadata.files.iter().map(|(path, info)| {
             let path = join_path_utf8!(&location_full, path);
             (path, info)
@@ -1705,7 +2765,7 @@ impl App {
                         let full_path = pkg_loc.join(path);
                         //println!("{:30} {}", path, full_path);
                         if full_path == needle {
-                            println!("{} {}", &pkg.metadata.name, &pkg.metadata.version);
+                            println!("{} {}", pkg.metadata.name, pkg.metadata.version);
                             return Ok(());
                         }
                     }
@@ -1771,6 +2831,33 @@ impl App {
         anyhow::bail!("package not installed")
     }
 
+    /// `bpm query rdeps <pkg>`
+    /// print the installed packages that depend on a package
+    pub fn query_rdeps(&mut self, pkg_name: &str, recursive: bool) -> AResult<()> {
+
+        self.shared_lock()?;
+
+        if !self.db_file_exists() {
+            return Ok(());
+        }
+
+        self.load_db()?;
+
+        let names = if recursive {
+            self.db.dependents_recursive(pkg_name)
+        } else {
+            self.db.dependents(pkg_name).iter().map(|p| p.metadata.name.clone()).collect()
+        };
+
+        for name in names {
+            if let Some(pkg) = self.db.installed.iter().find(|p| p.metadata.name == name) {
+                println!("{} {}", pkg.metadata.name, pkg.metadata.version);
+            }
+        }
+
+        Ok(())
+    }
+
     /// `bpm query kv`
     pub fn query_kv(&mut self, pkg_names: Option<&[&str]>, keys: Option<&[&str]>) -> AResult<()> {
 
@@ -1951,14 +3038,57 @@ impl App {
         Ok(())
     }
 
+    /// `bpm publish`
+    ///
+    /// Upload a package file to a provider, then rescan the provider for that package.
+    pub fn publish_cmd(&mut self, path: &Utf8Path, provider_name: &str, channel: Option<&str>) -> AResult<()> {
+
+        self.shared_lock()?;
+
+        let provider = self.config.providers.iter()
+            .find(|p| p.name == provider_name)
+            .with_context(|| format!("no provider named '{provider_name}'"))?;
+
+        if let Some(channel) = channel && !package::is_valid_package_name(channel) {
+            anyhow::bail!("invalid channel name '{channel}'");
+        }
+
+        let filename = path.file_name().context("invalid package path")?;
+        let (pkg_name, _version, _arch) = package::split_parts(filename)
+            .filter(|_| package::is_packagefile_name(filename))
+            .with_context(|| format!("'{filename}' is not a package file name"))?;
+
+        // check the file before sending it anywhere
+        let mut file = File::open(path).with_context(|| format!("failed to open {path}"))?;
+        let check = package::package_integrity_check_full(&mut file, Some(filename), None)?;
+        if !check.good() {
+            anyhow::bail!("package failed integrity check");
+        }
+
+        provider.as_provide().publish(path, channel, provider.token.as_deref())
+            .with_context(|| format!("failed to publish to provider '{provider_name}'"))?;
+
+        println!("Published {filename} to {provider_name}");
+
+        self.scan_providers(&[provider], &[pkg_name], Duration::ZERO)
+    }
+
     /// `bpm scan`
-    pub fn scan_cmd(&mut self, debounce: Option<Duration>) -> AResult<()> {
+    ///
+    /// With `pkg_names`, only those packages are rescanned and merged into each provider's package list.
+    pub fn scan_cmd(&mut self, debounce: Option<Duration>, pkg_names: &[&String]) -> AResult<()> {
 
         // This would normally be an exclusive lock because this modifies files.
         // However, we're using a shared lock here and exclusive locks on the individual provider
         // files to support running multiple processes doing scans in parrallel
         self.shared_lock()?;
 
+        if !pkg_names.is_empty() {
+            let pkg_names : Vec<&str> = pkg_names.iter().map(|s| s.as_str()).collect();
+            let providers : Vec<_> = self.filtered_providers().collect();
+            return self.scan_providers(&providers, &pkg_names, Duration::ZERO);
+        }
+
         tracing::trace!("[scan] checking debounce times");
 
         let now = chrono::Utc::now().round_subsecs(0);
@@ -1992,6 +3122,18 @@ impl App {
             return Ok(());
         }
 
+        let providers : Vec<_> = self.filtered_providers().collect();
+        self.scan_providers(&providers, &[], debounce)
+    }
+
+    /// Scan providers in parallel, writing each provider's package list to its cache file.
+    ///
+    /// With `pkg_names`, only those packages are scanned, replacing their entries in the existing package lists.
+    /// Otherwise the package lists are replaced, unless a concurrent scan just finished within `debounce`.
+    fn scan_providers(&self, providers: &[&provider::Provider], pkg_names: &[&str], debounce: Duration) -> AResult<()> {
+
+        let now = chrono::Utc::now().round_subsecs(0);
+
         // make sure there is a cache/provider dir to cache search results in
         create_dir(join_path!(&self.config.cache_dir, "provider"))?;
 
@@ -2004,7 +3146,7 @@ impl App {
 
         std::thread::scope(|s| {
 
-            for provider in self.filtered_providers() {
+            for &provider in providers {
 
                 while tcount.load(std::sync::atomic::Ordering::SeqCst) >= tmax {
                     // this doesn't need to be any more sophisticated than a spin wait
@@ -2021,12 +3163,7 @@ impl App {
                     // decrement the thread counter upon drop (when this thread is done)
                     let _decr = AtomicDecrDrop{val: &tcount};
 
-                    let mut lock_path = provider.cache_file.clone();
-                    if let Some(e) = lock_path.extension() {
-                        lock_path = lock_path.with_extension(format!("{}.lock", e));
-                    } else {
-                        lock_path = lock_path.with_extension(".lock");
-                    }
+                    let lock_path = provider::lock_path(&provider.cache_file);
 
                     let mut lock_contended = false;
                     let lock_file = bpmutil::open_lockfile(&lock_path)?;
@@ -2039,7 +3176,7 @@ impl App {
                     // if the first attempt to get the lockfile failed, something else had the file
                     // locked, likely another scan in parallel. Check if the file was just updated,
                     // and skip the scan if so.
-                    if lock_contended {
+                    if lock_contended && pkg_names.is_empty() {
                         tracing::debug!("[scan] [t{tid}] lockfile was contended, checking if debounce is satisfied");
                         if let Ok(data) = provider.load_file() {
                             let debounce_timepoint = data.scan_time + tern!(debounce.is_zero(), Duration::from_secs(10), debounce);
@@ -2056,9 +3193,66 @@ impl App {
                     let arch_filter = if arch_filter.is_empty() { None } else { Some(arch_filter) };
                     let arch_filter = arch_filter.as_deref();
 
-                    let list = scan_result::Scan::scan(provider.as_provide(), arch_filter);
+                    if provider.signed_index {
+                        let previous_sequence = provider.load_file().ok().and_then(|data| data.index_sequence);
+                        match Self::read_signed_index(provider, previous_sequence) {
+                            Ok(mut index) => {
+                                if let Some(arch_filter) = arch_filter {
+                                    index.packages.filter_arch(arch_filter);
+                                }
+                                tracing::debug!("[scan] [t{}] {}: index sequence {}", tid, &provider.name, index.sequence);
+                                let out = provider::ProviderFile {
+                                    scan_time: chrono::Utc::now().round_subsecs(0),
+                                    index_sequence: Some(index.sequence),
+                                    http_pages: None,
+                                    package_scan_times: Default::default(),
+                                    packages: index.packages,
+                                };
+                                let s = serde_json::to_string_pretty(&out)?;
+                                let mut file = File::create(&provider.cache_file)?;
+                                file.write_all(s.as_bytes())?;
+                            }
+                            Err(e) => {
+                                // keep the package list from the last index that was accepted
+                                eprintln!("warning: rejecting index from provider {}: {:#}", provider.name, e);
+                            }
+                        }
+                        return Ok(());
+                    }
+
+                    if !pkg_names.is_empty() {
+                        let mut data = provider.load_file().unwrap_or_else(|_| provider::ProviderFile {
+                            // never fully scanned
+                            scan_time: chrono::DateTime::UNIX_EPOCH,
+                            index_sequence: None,
+                            http_pages: None,
+                            package_scan_times: Default::default(),
+                            packages: Default::default(),
+                        });
+                        for &pkg_name in pkg_names {
+                            match scan_result::Scan::scan_package(provider.as_provide(), pkg_name, arch_filter) {
+                                Ok(mut found) => {
+                                    found.filter_package(&[pkg_name]);
+                                    tracing::debug!("[scan] [t{}] {}: {} {} versions", tid, &provider.name, pkg_name, found.version_count());
+                                    data.packages.packages.remove(pkg_name);
+                                    data.packages.merge(found);
+                                    data.package_scan_times.insert(pkg_name.to_string(), chrono::Utc::now().round_subsecs(0));
+                                }
+                                Err(e) => {
+                                    tracing::warn!("[scan] [t{}] error while scanning {} for {}: {}", tid, &provider.name, pkg_name, e);
+                                }
+                            }
+                        }
+                        let s = serde_json::to_string_pretty(&data)?;
+                        let mut file = File::create(&provider.cache_file)?;
+                        file.write_all(s.as_bytes())?;
+                        return Ok(());
+                    }
+
+                    let previous_pages = provider.load_file().ok().and_then(|data| data.http_pages);
+                    let list = provider.as_provide().scan_cached(arch_filter, previous_pages);
 
-                    if let Ok(list) = list {
+                    if let Ok((list, http_pages)) = list {
 
                         let pkg_count = list.packages.len();
                         let ver_count : usize = list.packages.iter().map(|pkg_info| pkg_info.1.versions.len()).sum();
@@ -2069,6 +3263,9 @@ impl App {
                         // write the package list to a cache file
                         let out = provider::ProviderFile {
                             scan_time: now,
+                            index_sequence: None,
+                            http_pages,
+                            package_scan_times: Default::default(),
                             packages: list,
                         };
 
@@ -2079,6 +3276,9 @@ impl App {
                         tracing::warn!("[scan] [t{}] error while scanning {}: {}", tid, &provider.name, list.unwrap_err());
                         let out = provider::ProviderFile {
                             scan_time: now,
+                            index_sequence: None,
+                            http_pages: None,
+                            package_scan_times: Default::default(),
                             packages: Default::default(),
                         };
                         let s = serde_json::to_string_pretty(&out)?;
@@ -2095,9 +3295,57 @@ impl App {
         Ok(())
     }
 
+    /// Read a provider's index and check that it is signed by a trusted key, has not expired,
+    /// and is not older than the last index accepted from the provider.
+    /// Package uris in the returned index are absolute.
+    fn read_signed_index(provider: &provider::Provider, previous_sequence: Option<u64>) -> AResult<scan_result::index::Index> {
+
+        use scan_result::index::*;
+
+        let inner = provider.as_provide();
+
+        let read = || -> AResult<(Vec<u8>, package::sign::Signature)> {
+            let data = inner.read_file(INDEX_FILE_NAME)?.context("provider has no index")?;
+            let signature = inner.read_file(INDEX_SIGNATURE_FILE_NAME)?.context("index is not signed")?;
+            Ok((data, package::sign::Signature::from_reader(&mut signature.as_slice())?))
+        };
+
+        // the index and its signature are two files, read them again if they were replaced in between
+        let (mut data, mut signature) = read()?;
+        if signature.verify(&data).is_err() {
+            tracing::debug!("index signature does not match, reading the index again");
+            (data, signature) = read()?;
+        }
+
+        let key = signature.verify(&data).context("index signature is invalid")?;
+        if !provider.trust.keys.contains(&key) {
+            anyhow::bail!("index is signed by an untrusted key {}", signature.key);
+        }
+
+        let mut index = Index::from_slice(&data)?;
+
+        // without an expiry time an old index could be served forever
+        let Some(expires) = index.expires else {
+            anyhow::bail!("signed index has no expiry time");
+        };
+        if index.is_expired(chrono::Utc::now()) {
+            anyhow::bail!("index expired at {expires}");
+        }
+
+        if let Some(previous) = previous_sequence && index.sequence < previous {
+            anyhow::bail!("index sequence {} is older than the last accepted sequence {}", index.sequence, previous);
+        }
+
+        index.map_uris(|uri| inner.file_uri(uri))?;
+
+        Ok(index)
+    }
+
     /// clear any temp download files that may be left behind by
     /// any previous runs that were killed while downloading
-    fn clear_temp_downloads(cache_dir: &Utf8Path) {
+    ///
+    /// With `keep_partial`, partial downloads modified more recently than that are kept to be resumed.
+    fn clear_temp_downloads(cache_dir: &Utf8Path, keep_partial: Option<std::time::Duration>) {
 
         if let Ok(files) = std::fs::read_dir(join_path_utf8!(cache_dir, "packages")) {
             for file in files.flatten() {
@@ -2108,6 +3356,14 @@ impl App {
                     && let Some(name) = path.file_name()
                     && name.starts_with(TEMP_DOWNLOAD_PREFX) {
 
+                    if let Some(keep_partial) = keep_partial
+                        && name.ends_with(".fetch")
+                        && file.metadata().and_then(|m| m.modified()).is_ok_and(|t| t.elapsed().unwrap_or_default() < keep_partial)
+                    {
+                        tracing::trace!("keeping partial download {}", name);
+                        continue;
+                    }
+
                     let fd = std::fs::File::open(&path);
                     if let Ok(fd) = fd && let Ok(true) = fd.my_try_lock() {
                         tracing::trace!("removing {}", name);
@@ -2128,11 +3384,6 @@ impl App {
 
         self.exclusive_lock()?;
 
-        let retention = self.config.cache_retention;
-
-        let now = chrono::Utc::now().round_subsecs(0);
-        tracing::trace!("cache retention {:?} ({})", retention, now - retention);
-
         // it's okay if the db doesn't exist, then we won't preserve any in-use package files,
         // and none should be in use if the db doesn't exist.
         let _ = self.load_db();
@@ -2145,6 +3396,53 @@ impl App {
             join_path!(&pkg_dir, &f.filename).exists()
         });
 
+        for path in self.cache_clean_candidates() {
+            if let Some(filename) = path.file_name() {
+                let ok = std::fs::remove_file(&path);
+                if let Err(e) = ok {
+                    eprintln!("failed to remove {}: {}", path, e);
+                } else {
+                    self.db.cache_files.retain(|e| e.filename != filename);
+                }
+            }
+        }
+
+        // save changes to db
+        self.save_db()?;
+
+        // also remove any temp download files if we can get an exclusive lock on them
+        Self::clear_temp_downloads(&self.config.cache_dir, Some(self.config.cache_retention));
+
+        Ok(())
+    }
+
+    /// `bpm cache clean --dry-run`
+    /// print the package files that would be removed
+    pub fn cache_clean_dry_run(&mut self, json: bool) -> AResult<()> {
+
+        self.shared_lock()?;
+
+        let _ = self.load_db();
+
+        let plan = plan::Plan {
+            cache_evictions: self.plan_cache_clean(&[]),
+            ..Default::default()
+        };
+        plan.print(json)
+    }
+
+    /// package files in the cache that are expired or unknown to the db, and not in use
+    fn cache_clean_candidates(&self) -> Vec<Utf8PathBuf> {
+
+        let retention = self.config.cache_retention;
+
+        let now = chrono::Utc::now().round_subsecs(0);
+        tracing::trace!("cache retention {:?} ({})", retention, now - retention);
+
+        let pkg_dir = join_path!(&self.config.cache_dir, "packages");
+
+        let mut candidates = Vec::new();
+
         for path in walkdir::WalkDir::new(&pkg_dir)
             .max_depth(1) // package files are a flat list at the root dir
             .into_iter()
@@ -2181,29 +3479,18 @@ impl App {
                 tracing::trace!(touch=?touch, in_use=in_use, "[cache clean] {} {}", tern!(remove, "remove", "keep"), filename);
 
                 if remove {
-                    let ok = std::fs::remove_file(&path);
-                    if let Err(e) = ok {
-                        eprintln!("failed to remove {}: {}", path, e);
-                    } else {
-                        self.db.cache_files.retain(|e| e.filename != filename);
-                    }
+                    candidates.push(path);
                 }
             }
         }
 
-        // save changes to db
-        self.save_db()?;
-
-        // also remove any temp download files if we can get an exclusive lock on them
-        Self::clear_temp_downloads(&self.config.cache_dir);
-
-        Ok(())
+        candidates
     }
 
     /// `bpm cache clear`
     /// Remove all cached package files.
     /// Optionally including the package files that are "in use" (packages thats are currently installed).
-    pub fn cache_clear(&mut self, in_use: bool) -> AResult<()> {
+    pub fn cache_clear(&mut self, in_use: bool, yes: bool) -> AResult<()> {
 
         self.exclusive_lock()?;
 
@@ -2219,6 +3506,8 @@ impl App {
             join_path!(&pkg_dir, &f.filename).exists()
         });
 
+        let mut remove_paths = Vec::new();
+
         for path in walkdir::WalkDir::new(&pkg_dir)
             .max_depth(1) // package files are a flat list at the root dir
             .into_iter()
@@ -2230,22 +3519,38 @@ impl App {
                 let mut remove = true;
 
                 match self.db.cache_files.iter().find(|e| e.filename == filename) {
-                    None => { }
-                    Some(ent) => {
-                        if ent.in_use && !in_use {
-                            remove = false;
-                        }
+                    Some(ent) if ent.in_use && !in_use => {
+                        remove = false;
                     }
+                    _ => { }
                 }
 
                 if remove {
-                    tracing::trace!("cache clear - remove {}", filename);
-                    let ok = std::fs::remove_file(&path);
-                    if let Err(e) = ok {
-                        eprintln!("failed to remove {}: {}", path, e);
-                    } else {
-                        self.db.cache_files.retain(|e| e.filename != filename);
-                    }
+                    remove_paths.push(path);
+                }
+            }
+        }
+
+        if !remove_paths.is_empty() && self.should_confirm(yes) {
+            let size = remove_paths.iter().filter_map(|p| get_filesize(p.as_str()).ok()).sum::<u64>();
+            println!("{} cache file{} to remove ({}):", remove_paths.len(), tern!(remove_paths.len() > 1, "s", ""), indicatif::HumanBytes(size));
+            for path in &remove_paths {
+                println!("  {}", path.file_name().unwrap_or(path.as_str()));
+            }
+            if !confirm("Continue?")? {
+                println!("Aborted");
+                return Ok(());
+            }
+        }
+
+        for path in remove_paths {
+            if let Some(filename) = path.file_name() {
+                tracing::trace!("cache clear - remove {}", filename);
+                let ok = std::fs::remove_file(&path);
+                if let Err(e) = ok {
+                    eprintln!("failed to remove {}: {}", path, e);
+                } else {
+                    self.db.cache_files.retain(|e| e.filename != filename);
                 }
             }
         }
@@ -2253,7 +3558,7 @@ impl App {
         self.save_db()?;
 
         // also remove any temp download files if we can get an exclusive lock on them
-        Self::clear_temp_downloads(&self.config.cache_dir);
+        Self::clear_temp_downloads(&self.config.cache_dir, None);
 
         Ok(())
     }
@@ -2453,52 +3758,24 @@ impl App {
             ids.push(id);
         }
 
-        let providers : Vec<&provider::Provider> = self.filtered_providers().collect();
-
-        let mut jobs = std::cmp::max(1, self.config.cache_fetch_jobs as usize);
-        jobs = std::cmp::min(jobs, ids.len());
-
-        let ids = Mutex::new(ids);
-        let touch : Mutex<Vec<_>> = Mutex::new(Vec::new());
-
-        let error_count = std::sync::atomic::AtomicU32::new(error_count);
-
-        std::thread::scope(|s| {
-            for _tid in 0..jobs {
-                let ids = &ids;
-                let cache_dir = &self.config.cache_dir;
-                let providers = &providers;
-                let error_count = &error_count;
-                let touch = &touch;
-                let _t = s.spawn(move || {
-                    loop {
-                        let id = match ids.lock().unwrap().pop() {
-                            None => { break; },
-                            Some(id) => id
-                        };
-                        if let Ok((is_new, cache_path, hash)) = Self::mt_cache_package_require(providers, cache_dir, &id) {
-                            tracing::trace!("cache fetched {}", cache_path);
-                            if is_new {
-                                touch.lock().unwrap().push((cache_path, hash));
-                            }
-                        } else {
-                            tracing::warn!("failed to fetch {}", id.name);
-                            eprintln!("failed to fetch {}@{}", id.name, id.version);
-                            error_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                        }
+        let cache_dir = self.config.cache_dir.clone();
+        for (id, ret) in ids.iter().zip(self.fetch_parallel(&ids, &cache_dir)) {
+            match ret {
+                Ok((is_new, cache_path, hash)) => {
+                    tracing::trace!("cache fetched {}", cache_path);
+                    if is_new && let Some(fname) = cache_path.file_name() {
+                        self.db.cache_insert(fname, hash, None);
                     }
-                });
-            }
-        });
-
-        for (path, hash) in touch.into_inner().unwrap() {
-            if let Some(fname) = path.file_name() {
-                self.db.cache_insert(fname, hash, None);
+                }
+                Err(_) => {
+                    tracing::warn!("failed to fetch {}", id.name);
+                    eprintln!("failed to fetch {}@{}", id.name, id.version);
+                    error_count += 1;
+                }
             }
         }
         self.save_db()?;
 
-        let error_count = error_count.into_inner();
         if error_count > 0 {
             return Err(anyhow::anyhow!("failed to fetch {} package{}", error_count, tern!(error_count == 1, "", "s")));
         }
@@ -2595,6 +3872,39 @@ impl App {
         }
     }
 
+    /// LOOKUP or FETCH several packages, see `mt_cache_package_require`.
+    /// Fetches up to `cache.fetch_jobs` packages at a time. Results are in the same order as `ids`.
+    fn fetch_parallel(&self, ids: &[PackageID], cache_dir: &Utf8Path) -> Vec<AResult<(bool, Utf8PathBuf, Option<String>)>> {
+
+        let providers : Vec<&provider::Provider> = self.filtered_providers().collect();
+
+        let mut jobs = std::cmp::max(1, self.config.cache_fetch_jobs as usize);
+        jobs = std::cmp::min(jobs, ids.len());
+
+        let next = std::sync::atomic::AtomicUsize::new(0);
+        let results : Mutex<Vec<_>> = Mutex::new((0..ids.len()).map(|_| None).collect());
+
+        std::thread::scope(|s| {
+            for _tid in 0..jobs {
+                let next = &next;
+                let results = &results;
+                let providers = &providers;
+                let _t = s.spawn(move || {
+                    loop {
+                        let idx = next.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        let Some(id) = ids.get(idx) else { break };
+                        let ret = Self::mt_cache_package_require(providers, cache_dir, id);
+                        results.lock().unwrap()[idx] = Some(ret);
+                    }
+                });
+            }
+        });
+
+        results.into_inner().unwrap().into_iter()
+            .map(|ret| ret.unwrap_or_else(|| Err(anyhow::anyhow!("package was not fetched"))))
+            .collect()
+    }
+
     /// LOOKUP a package in the cache
     /// OR FETCH a package and cache it.
     ///
@@ -2637,29 +3947,65 @@ impl App {
                 if let Some(pkg_info) = packages.packages.get(&id.name) {
                     if let Some(vlist) = pkg_info.versions.get(&id.version.as_str().into()) {
 
-                        for info in vlist.iter().filter(|ent| package::ArchMatcher::from(id.arch.as_deref()).matches(ent.arch.as_deref())) {
+                        // each matching file, on each of the provider's mirrors
+                        let candidates = vlist.iter()
+                            .filter(|ent| package::ArchMatcher::from(id.arch.as_deref()).matches(ent.arch.as_deref()))
+                            .flat_map(|info| {
+                                provider.as_provide().file_uris(&info.uri).into_iter().enumerate().map(move |(mirror, uri)| (info, mirror, uri))
+                            });
 
-                            tracing::debug!(uri=info.uri, "[cache fetch] trying");
+                        for (info, mirror, uri) in candidates {
+
+                            tracing::debug!(uri, "[cache fetch] trying");
 
                             let final_path = join_path_utf8!(&cache_dir, "packages", &info.filename);
 
-                            let rand_str = Alphanumeric.sample_string(&mut rand::thread_rng(), 6);
-                            let temp_path = final_path.with_file_name(format!("{}{}_{}.fetch", TEMP_DOWNLOAD_PREFX, rand_str, &info.filename));
+                            // the temp file is named after the package file so that an interrupted download can be resumed
+                            let mut temp_path = final_path.with_file_name(format!("{}{}.fetch", TEMP_DOWNLOAD_PREFX, info.filename));
 
-                            let mut file = std::fs::OpenOptions::new().create(true).read(true).write(true).truncate(true).open(&temp_path)?;
+                            let mut file = std::fs::OpenOptions::new().create(true).read(true).write(true).truncate(false).open(&temp_path)?;
 
                             if let Ok(true) = file.my_try_lock() {
                                 // good
                             } else {
-                                tracing::warn!("could not acquire lock on temp download file {}", temp_path);
+                                // another process is downloading this file, download a separate copy
+                                tracing::debug!("could not acquire lock on temp download file {}", temp_path);
+                                let rand_str = Alphanumeric.sample_string(&mut rand::thread_rng(), 6);
+                                temp_path = final_path.with_file_name(format!("{}{}_{}.fetch", TEMP_DOWNLOAD_PREFX, rand_str, info.filename));
+                                file = std::fs::OpenOptions::new().create(true).read(true).write(true).truncate(true).open(&temp_path)?;
+                                if !matches!(file.my_try_lock(), Ok(true)) {
+                                    tracing::warn!("could not acquire lock on temp download file {}", temp_path);
+                                }
                             }
 
-                            let result = provider.as_provide().fetch(&mut file, id, &info.uri);
+                            // continue a partial download left by an earlier attempt, perhaps from another mirror,
+                            // otherwise rebuild the package from a delta if an older version is cached, else download it all.
+                            // A delta is only tried with the first mirror.
+                            let resumed = file.metadata().is_ok_and(|m| m.len() > 0);
+                            let mut delta_check = None;
+                            let result = if resumed {
+                                tracing::debug!(uri, "[cache fetch] resuming partial download");
+                                provider.as_provide().fetch_resume(&mut file, id, &uri, info.size)
+                            } else if mirror > 0 {
+                                provider.as_provide().fetch_resume(&mut file, id, &uri, info.size)
+                            } else {
+                                match Self::mt_fetch_delta(provider, cache_dir, id, info, &mut file) {
+                                    Ok(check) => {
+                                        delta_check = Some(check);
+                                        Ok(0)
+                                    }
+                                    Err(e) => {
+                                        tracing::debug!(uri, "[cache fetch] no delta: {e:#}");
+                                        provider.as_provide().fetch_resume(&mut file, id, &uri, info.size)
+                                    }
+                                }
+                            };
                             match result {
-                                Err(ref _e) => { tracing::warn!(uri=info.uri, "[cache fetch] fetch failed"); }
-                                Ok(_v)      => { tracing::debug!(uri=info.uri, "[cache fetch] fetch success"); }
+                                Err(ref _e) => { tracing::warn!(uri, "[cache fetch] fetch failed"); }
+                                Ok(_v)      => { tracing::debug!(uri, "[cache fetch] fetch success"); }
                             }
 
+                            // a failed download stays in the temp file, to be resumed by the next attempt
                             let mut err = result.is_err();
                             if !err { err = file.sync_all().is_err(); }
                             if !err { err = file.rewind().is_err(); }
@@ -2667,17 +4013,49 @@ impl App {
                             // the packagefile's hash, to be inserted into the cache for faster verification later
                             let mut hash = None;
 
+                            // the file must match the hash the provider listed, if it listed one
+                            let listed_hash_ok = |check: &package::CheckResult| info.blake3.as_ref().is_none_or(|listed| *listed == check.file_hash);
+
                             if !err {
 
-                                // integrity check the package
-                                let check = package::package_integrity_check_full(&mut file, Some(&info.filename), None);
+                                // integrity check the package, a rebuilt package was already checked
+                                let mut check = match delta_check.take() {
+                                    Some(check) => Ok(check),
+                                    None => package::package_integrity_check_full(&mut file, Some(&info.filename), None),
+                                };
+
+                                // the start of a resumed download may be from a different file, try once more from scratch
+                                if resumed && !check.as_ref().is_ok_and(|check| check.good() && listed_hash_ok(check)) {
+                                    tracing::debug!("[cache fetch] resumed download of {} is bad, downloading again", info.filename);
+                                    check = file.set_len(0)
+                                        .and_then(|_| file.rewind())
+                                        .map_err(anyhow::Error::from)
+                                        .and_then(|_| provider.as_provide().fetch_resume(&mut file, id, &uri, info.size))
+                                        .and_then(|_| { file.sync_all()?; file.rewind()?; Ok(()) })
+                                        .and_then(|_| package::package_integrity_check_full(&mut file, Some(&info.filename), None));
+                                }
 
                                 if let Ok(check) = check && check.good() {
                                     // good!
                                     tracing::trace!("[cache fetch] integrity check good {}", info.filename);
+
+                                    let rejected = if listed_hash_ok(&check) {
+                                        provider.trust.check(&mut file).err()
+                                    } else {
+                                        Some(anyhow::anyhow!("blake3 hash {} does not match the listed hash", check.file_hash))
+                                    };
                                     hash = Some(check.file_hash);
+
+                                    if let Some(e) = rejected {
+                                        eprintln!("warning: rejecting {} from provider {}: {:#}", info.filename, provider.name, e);
+                                        provider.as_provide().report_bad_file(&uri);
+                                        drop(file);
+                                        let _ = std::fs::remove_file(&temp_path);
+                                        err = true;
+                                    }
                                 } else {
                                     tracing::trace!("[cache fetch] integrity check fail {}", info.filename);
+                                    provider.as_provide().report_bad_file(&uri);
                                     // delete temp file and return error
                                     drop(file);
                                     let _ = std::fs::remove_file(&temp_path);
@@ -2702,6 +4080,37 @@ impl App {
         Err(anyhow::anyhow!("failed to fetch package"))
     }
 
+    /// Check the signature of a package file against the trust policy of every provider that lists it,
+    /// or the `[trust]` keys if no provider lists it.
+    ///
+    /// The cache is keyed by filename only, so the file may have come from any of those providers.
+    /// It has to satisfy all of them, unsigned files are only accepted if every one of them allows it.
+    fn check_trust(&self, filename: &str, file: &mut File) -> AResult<()> {
+
+        let Some((name, version, _arch)) = package::split_parts(filename) else {
+            return self.config.trust.check(file);
+        };
+
+        let mut listed_by_any = false;
+
+        for provider in &self.config.providers {
+            let Ok(provider::ProviderFile{packages, ..}) = provider.load_file() else { continue };
+            let listed = packages.packages.get(name)
+                .and_then(|info| info.versions.get(&version.into()))
+                .is_some_and(|vlist| vlist.iter().any(|info| info.filename == filename));
+            if listed {
+                listed_by_any = true;
+                provider.trust.check(file).with_context(|| format!("not trusted by provider {}", provider.name))?;
+            }
+        }
+
+        if !listed_by_any {
+            self.config.trust.check(file)?;
+        }
+
+        Ok(())
+    }
+
     /// Directly copy a file into the cache dir
     ///
     /// returns (did_copy, cached_path, packagefile_hash)
@@ -2764,6 +4173,12 @@ impl App {
             anyhow::bail!("package integrity check failed, rejecting package");
         }
 
+        if let Err(e) = self.config.trust.check(&mut file) {
+            drop(file);
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e.context("signature check failed, rejecting package"));
+        }
+
         tracing::trace!("moving {} to {}", temp_path, cache_path);
         std::fs::rename(&temp_path, &cache_path).context("rename file")?;
 
@@ -0,0 +1,102 @@
+use super::App;
+use anyhow::Context;
+use anyhow::Result;
+use crate::*;
+use package::PackageID;
+use std::fs::File;
+use std::io::Seek;
+use version::VersionString;
+
+impl App {
+
+    /// Rebuild a package file from a delta and an older version of the package in the cache.
+    ///
+    /// Deltas the provider lists are tried first, smallest first,
+    /// then the provider's usual delta location from the newest older version in the cache.
+    /// The rebuilt file is fully checked. On failure, `file` is left empty for a full download.
+    pub(super) fn mt_fetch_delta(provider: &provider::Provider, cache_dir: &Utf8Path, id: &PackageID, info: &scan_result::VersionInfo, file: &mut File) -> Result<package::CheckResult> {
+
+        let cached = Self::mt_cached_versions(cache_dir, &id.name, info.arch.as_deref());
+
+        // (delta uri, delta size, old package file)
+        let mut candidates : Vec<(String, Option<u64>, Utf8PathBuf)> = Vec::new();
+
+        let mut listed : Vec<_> = info.deltas.iter()
+            .filter_map(|(from, delta)| {
+                cached.iter()
+                    .find(|(version, _path)| version.as_str() == from)
+                    .map(|(_version, path)| (delta.size, delta.uri.clone(), path.clone()))
+            })
+            .collect();
+        listed.sort_by_key(|(size, _uri, _path)| *size);
+        candidates.extend(listed.into_iter().map(|(size, uri, path)| (uri, Some(size), path)));
+
+        let target = VersionString::from(id.version.as_str());
+        let newest_older = cached.iter()
+            .filter(|(version, _path)| *version < target)
+            .max_by(|a, b| a.0.cmp(&b.0));
+        if let Some((from, path)) = newest_older
+            && !info.deltas.contains_key(from.as_str())
+            && let Some(uri) = provider.as_provide().delta_uri(info, &id.name, from.as_str(), &id.version)
+        {
+            candidates.push((uri, None, path.clone()));
+        }
+
+        if candidates.is_empty() {
+            anyhow::bail!("no older version in the cache");
+        }
+
+        for (uri, size, old_path) in candidates {
+            match Self::mt_apply_delta(provider, cache_dir, id, info, &uri, size, &old_path, file) {
+                Ok(check) => {
+                    tracing::debug!(uri, "[cache fetch] rebuilt {} from {}", info.filename, old_path);
+                    return Ok(check);
+                }
+                Err(e) => {
+                    tracing::debug!(uri, "[cache fetch] delta failed: {e:#}");
+                    file.set_len(0)?;
+                    file.rewind()?;
+                }
+            }
+        }
+
+        anyhow::bail!("no usable delta")
+    }
+
+    /// Download a delta and apply it to `old_path`, writing the new package file into `file`.
+    /// The delta is downloaded into a temp file in the cache, removed when done.
+    fn mt_apply_delta(provider: &provider::Provider, cache_dir: &Utf8Path, id: &PackageID, info: &scan_result::VersionInfo, uri: &str, size: Option<u64>, old_path: &Utf8Path, file: &mut File) -> Result<package::CheckResult> {
+
+        let mut delta = tempfile::tempfile_in(join_path_utf8!(cache_dir, "packages")).context("failed to create a temp file for the delta")?;
+        provider.as_provide().fetch(&mut delta, id, uri, size)?;
+        delta.rewind()?;
+
+        let mut old = File::open(old_path).with_context(|| format!("failed to open {old_path}"))?;
+        package::delta::apply_delta(&mut old, &mut delta, file)?;
+
+        file.sync_all()?;
+        file.rewind()?;
+
+        let check = package::package_integrity_check_full(file, Some(&info.filename), None)?;
+        if !check.good() {
+            anyhow::bail!("rebuilt package failed integrity check");
+        }
+        Ok(check)
+    }
+
+    /// versions of a package in the cache, with their files
+    fn mt_cached_versions(cache_dir: &Utf8Path, pkg_name: &str, arch: Option<&str>) -> Vec<(VersionString, Utf8PathBuf)> {
+
+        let Ok(entries) = join_path_utf8!(cache_dir, "packages").read_dir_utf8() else {
+            return Vec::new();
+        };
+
+        entries.flatten()
+            .filter(|entry| package::is_packagefile_name(entry.file_name()))
+            .filter_map(|entry| {
+                let (name, version, file_arch) = package::split_parts(entry.file_name())?;
+                (name == pkg_name && file_arch == arch).then(|| (VersionString::from(version), entry.path().to_path_buf()))
+            })
+            .collect()
+    }
+}
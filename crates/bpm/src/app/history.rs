@@ -0,0 +1,176 @@
+use super::App;
+use anyhow::Result;
+use crate::*;
+use db::HistoryAction;
+use db::HistoryEntry;
+use package::PackageID;
+use std::io::IsTerminal;
+
+/// short description of a versioning, for display
+fn describe_versioning(versioning: &Versioning) -> String {
+    if let Some(channel) = &versioning.channel {
+        format!("channel {channel}")
+    } else if let Some(range) = &versioning.range {
+        format!("range {range}")
+    } else if versioning.pinned_to_version {
+        "version".into()
+    } else {
+        "unpinned".into()
+    }
+}
+
+/// what changed in a history entry, for display
+fn describe_change(entry: &HistoryEntry) -> String {
+    let old = entry.old_version.as_deref().unwrap_or("?");
+    let new = entry.new_version.as_deref().unwrap_or("?");
+    match entry.action {
+        HistoryAction::Install => new.to_string(),
+        HistoryAction::Uninstall => old.to_string(),
+        HistoryAction::Update => format!("{old} -> {new}"),
+        HistoryAction::Pin | HistoryAction::Unpin => {
+            let old_v = entry.old_versioning.as_ref().map_or("?".into(), describe_versioning);
+            let new_v = entry.new_versioning.as_ref().map_or("?".into(), describe_versioning);
+            format!("{new} ({old_v} -> {new_v})")
+        }
+    }
+}
+
+impl App {
+
+    /// `bpm history`
+    /// list recorded changes to installed packages, oldest first
+    pub fn history_cmd(&mut self, pkg_name: Option<&String>, limit: usize) -> Result<()> {
+
+        self.shared_lock()?;
+
+        if !self.db_file_exists() {
+            return Ok(());
+        }
+
+        self.load_db()?;
+
+        let entries = self.db.history.iter()
+            .filter(|e| pkg_name.is_none_or(|name| &e.package == name))
+            .collect::<Vec<_>>();
+
+        let skip = tern!(limit > 0, entries.len().saturating_sub(limit), 0);
+
+        let is_term = std::io::stdout().is_terminal();
+        let mut tw = tabwriter::TabWriter::new(std::io::stdout());
+        if is_term {
+            writeln!(&mut tw, "id\ttime\tuser\taction\tpackage\tversion")?;
+        }
+        for entry in entries.iter().skip(skip) {
+            let time = entry.time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S");
+            let user = entry.user.as_deref().unwrap_or("");
+            writeln!(&mut tw, "{}\t{}\t{}\t{}\t{}\t{}", entry.id, time, user, entry.action, entry.package, describe_change(entry))?;
+        }
+        tw.flush()?;
+
+        Ok(())
+    }
+
+    /// `bpm undo <id>`
+    /// reverse a single recorded change
+    pub fn undo_cmd(&mut self, id: u64) -> Result<()> {
+
+        self.exclusive_lock()?;
+
+        if !self.db_file_exists() {
+            anyhow::bail!("no history entry with id {id}");
+        }
+
+        self.load_db()?;
+
+        let entry = self.db.history.iter().find(|e| e.id == id).cloned().with_context(|| format!("no history entry with id {id}"))?;
+        let pkg_name = entry.package.as_str();
+        let installed = self.db.installed.iter().find(|p| p.metadata.name == pkg_name);
+
+        match entry.action {
+            HistoryAction::Install => {
+
+                let new_version = entry.new_version.as_deref().context("history entry has no version")?;
+                match installed {
+                    Some(pkg) if pkg.metadata.version == new_version => {}
+                    Some(pkg) => anyhow::bail!("cannot undo, {} is now at version {}", pkg_name, pkg.metadata.version),
+                    None => anyhow::bail!("cannot undo, {} is no longer installed", pkg_name),
+                }
+
+                let dependents = self.db.dependents(pkg_name).iter().map(|p| p.metadata.name.as_str()).collect::<Vec<_>>().join(", ");
+                if !dependents.is_empty() {
+                    anyhow::bail!("cannot undo, {pkg_name} is required by: {dependents}");
+                }
+
+                self.transaction(&format!("undo {id}"), |app| app.uninstall_pkg(pkg_name, false, false))?;
+            }
+            HistoryAction::Uninstall => {
+
+                if let Some(pkg) = installed {
+                    anyhow::bail!("cannot undo, {} is installed again at version {}", pkg_name, pkg.metadata.version);
+                }
+
+                let version = entry.old_version.clone().context("history entry has no version")?;
+                let path = self.undo_require_package(pkg_name, &version, entry.arch.clone())?;
+                let versioning = entry.old_versioning.clone().unwrap_or_default();
+
+                // back where it was removed from, as a dependency if it was one
+                println!("Installing {pkg_name} {version}");
+                self.transaction(&format!("undo {id}"), |app| {
+                    app.install_pkg_file(path, entry.package_hash.clone(), versioning, entry.location.clone(), entry.auto_installed)
+                })?;
+            }
+            HistoryAction::Update => {
+
+                let new_version = entry.new_version.as_deref().context("history entry has no version")?;
+                let current = match installed {
+                    Some(pkg) if pkg.metadata.version == new_version => pkg,
+                    Some(pkg) => anyhow::bail!("cannot undo, {} is now at version {}", pkg_name, pkg.metadata.version),
+                    None => anyhow::bail!("cannot undo, {} is no longer installed", pkg_name),
+                };
+
+                let arch = current.metadata.arch.clone();
+                let versioning = entry.old_versioning.clone().unwrap_or_else(|| current.versioning.clone());
+                let version = entry.old_version.clone().context("history entry has no version")?;
+                let path = self.undo_require_package(pkg_name, &version, arch)?;
+
+                println!("Updating {pkg_name} {new_version} -> {version}");
+                self.transaction(&format!("undo {id}"), |app| {
+                    app.update_inplace(pkg_name, path, None, versioning)
+                })?;
+            }
+            HistoryAction::Pin | HistoryAction::Unpin => {
+
+                if installed.is_none() {
+                    anyhow::bail!("cannot undo, {} is no longer installed", pkg_name);
+                }
+
+                let versioning = entry.old_versioning.clone().context("history entry has no previous versioning")?;
+                self.set_versioning(pkg_name, versioning)?;
+            }
+        }
+
+        println!("Undid {} {} {}", entry.action, pkg_name, describe_change(&entry));
+
+        Ok(())
+    }
+
+    /// find a specific package version in the cache, or fetch it from a provider
+    fn undo_require_package(&mut self, pkg_name: &str, version: &str, arch: Option<String>) -> Result<Utf8PathBuf> {
+
+        let id = PackageID {
+            name: pkg_name.to_string(),
+            version: version.to_string(),
+            arch,
+        };
+
+        let (is_new, path, hash) = self.cache_package_require(&id)
+            .with_context(|| format!("could not find package file for {pkg_name} {version}"))?;
+
+        if is_new && let Some(filename) = path.file_name() {
+            self.db.cache_insert(filename, hash, None);
+            self.save_db()?;
+        }
+
+        Ok(path)
+    }
+}
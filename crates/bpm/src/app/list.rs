@@ -19,12 +19,13 @@ impl App {
                 let limit = *sub_matches.get_one::<u32>("limit").unwrap();
 
                 let show_arch = sub_matches.get_flag("show-arch");
+                let show_size = sub_matches.get_flag("show-size");
 
                 let arch = args::pull_many_opt(sub_matches, "arch");
                 self.setup_arch_filter(arch);
 
                 self.provider_filter = args::parse_providers(sub_matches);
-                self.list_available_cmd(name, exact, limit, oneline, json, channels, show_arch)?;
+                self.list_available_cmd(name, exact, limit, oneline, json, channels, show_arch, show_size)?;
             }
             Some(("channels", sub_matches)) => {
                 let name = sub_matches.get_one::<String>("pkg");
@@ -74,7 +75,7 @@ impl App {
             }
         }
         for ent in self.db.installed.iter() {
-            let channel = ent.versioning.channel.as_deref().unwrap_or("");
+            let channel = ent.versioning.channel.as_deref().or(ent.versioning.range.as_deref()).unwrap_or("");
             let pinned = tern!(ent.versioning.pinned_to_version, "=", "^");
             if show_arch {
                 let arch = ent.metadata.arch.as_deref().unwrap_or("noarch");
@@ -166,6 +167,7 @@ impl App {
         json: bool,
         channels: Option<Vec<&String>>,
         show_arch: bool,
+        show_size: bool,
     ) -> Result<()> {
 
         self.shared_lock()?;
@@ -222,6 +224,9 @@ impl App {
                 // or with --show-arch:
                 // foo 1.0.0 @noarch +stable 1.0.1-test @linux_x86_64 +beta
                 //
+                // --show-size adds the size in bytes after the version (or arch):
+                // foo 1.0.0 [52311] +stable
+                //
 
                 print!("{name}");
                 for (version, vlist) in take {
@@ -240,6 +245,9 @@ impl App {
                             }
 
                             print!(" {version} @{}", arch.unwrap_or("noarch"));
+                            if show_size && let Some(size) = max_size(vlist.iter().filter(|ent| ent.arch.as_deref() == *arch)) {
+                                print!(" [{size}]");
+                            }
                             if !chans.is_empty() {
                                 print!(" +{}", chans.join(" +"));
                             }
@@ -254,6 +262,9 @@ impl App {
                         }
 
                         print!(" {version}");
+                        if show_size && let Some(size) = max_size(vlist.iter()) {
+                            print!(" [{size}]");
+                        }
                         if !chans.is_empty() {
                             print!(" +{}", chans.join(" +"));
                         }
@@ -266,6 +277,8 @@ impl App {
                 // {"package":"foo","versions":[["1.0.0",["stable"]],["1.0.1-test",["beta"]]]}
                 // or with --show-arch:
                 // {"package":"foo","versions":[["1.0.0",["stable"],null],["1.0.1-test",["beta"],"linux_x86_64"]]}
+                // --show-size appends the size in bytes, or null:
+                // {"package":"foo","versions":[["1.0.0",["stable"],52311]]}
 
                 let mut json_versions = Vec::new();
                 for (version, vlist) in take {
@@ -281,7 +294,11 @@ impl App {
                             for chan in vlist.iter().filter(|ent| ent.arch.as_deref() == *arch).flat_map(|ent| ent.channels.iter()).map(|c| c.as_str()) {
                                 bsearch_insert(&mut chans, chan);
                             }
-                            json_versions.push(serde_json::json!([version.as_str(), chans, arch]));
+                            let mut entry = serde_json::json!([version.as_str(), chans, arch]);
+                            if show_size && let Some(entry) = entry.as_array_mut() {
+                                entry.push(max_size(vlist.iter().filter(|ent| ent.arch.as_deref() == *arch)).into());
+                            }
+                            json_versions.push(entry);
                         }
                     } else {
 
@@ -291,7 +308,11 @@ impl App {
                                 bsearch_insert(&mut chans, chan);
                             }
                         }
-                        json_versions.push(serde_json::json!([version.to_string(), chans]));
+                        let mut entry = serde_json::json!([version.to_string(), chans]);
+                        if show_size && let Some(entry) = entry.as_array_mut() {
+                            entry.push(max_size(vlist.iter()).into());
+                        }
+                        json_versions.push(entry);
                     }
 
                 }
@@ -305,6 +326,10 @@ impl App {
                 // foo
                 //   1.0.0
                 //   1.0.1-test beta
+                //
+                // or with --show-size:
+                // foo
+                //   1.0.0 [51.09 KiB]
 
                 println!("{name}");
                 for (version, vlist) in take {
@@ -317,6 +342,9 @@ impl App {
 
                         for arch in archs.iter() {
                             print!("  {version} ({})", arch.unwrap_or("noarch"));
+                            if show_size && let Some(size) = max_size(vlist.iter().filter(|ent| ent.arch.as_deref() == *arch)) {
+                                print!(" [{}]", indicatif::HumanBytes(size));
+                            }
                             chans.clear();
                             for chan in vlist.iter().filter(|ent| ent.arch.as_deref() == *arch).flat_map(|ent| ent.channels.iter()).map(|c| c.as_str()) {
                                 bsearch_insert(&mut chans, chan);
@@ -326,6 +354,9 @@ impl App {
                     } else {
 
                         print!("  {version}");
+                        if show_size && let Some(size) = max_size(vlist.iter()) {
+                            print!(" [{}]", indicatif::HumanBytes(size));
+                        }
                         chans.clear();
                         for info in vlist.iter() {
                             for chan in info.channels.iter() {
@@ -344,6 +375,13 @@ impl App {
     }
 }
 
+/// the size of the largest file, None if no size is listed
+///
+/// a version listed for several archs has a file for each
+fn max_size<'a, I: IntoIterator<Item = &'a scan_result::VersionInfo>>(infos: I) -> Option<u64> {
+    infos.into_iter().filter_map(|info| info.size).max()
+}
+
 /// insert into vec, maintain sorted ordering, no duplicates
 ///
 /// binary search for inseration index, then insert there if not found
@@ -0,0 +1,247 @@
+//! Dry-run plans for install, update, uninstall, and cache clean
+
+use super::App;
+use anyhow::Result;
+use crate::*;
+use bpmutil::get_filesize;
+use package::PackageID;
+use std::io::IsTerminal;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanAction {
+    Install,
+    Reinstall,
+    Update,
+    Uninstall,
+}
+
+impl std::fmt::Display for PlanAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Install   => "install",
+            Self::Reinstall => "reinstall",
+            Self::Update    => "update",
+            Self::Uninstall => "uninstall",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// One package change in a plan
+#[derive(Debug, Serialize)]
+pub struct PlannedPackage {
+    pub action: PlanAction,
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+
+    /// installed as a dependency of this package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_by: Option<String>,
+
+    /// bytes to download, 0 if the package file is already cached, none if the provider does not list the size
+    pub download_size: Option<u64>,
+
+    /// none when the package file is not cached, its contents are not known without downloading it
+    #[serde(flatten)]
+    pub files: Option<FileChanges>,
+}
+
+/// counts of the files a package change adds, removes and replaces
+#[derive(Debug, Default, Serialize)]
+pub struct FileChanges {
+    pub files_added: usize,
+    pub files_removed: usize,
+    pub files_replaced: usize,
+}
+
+impl PlannedPackage {
+
+    pub fn new(action: PlanAction, name: &str) -> Self {
+        Self {
+            action,
+            name: name.to_string(),
+            old_version: None,
+            new_version: None,
+            arch: None,
+            required_by: None,
+            download_size: Some(0),
+            files: None,
+        }
+    }
+
+    /// count the files that change going from `old` to `new`.
+    /// Dirs, files with the same hash, and symlinks with the same target are unchanged.
+    pub fn diff_files(&mut self, old: Option<&package::MetaData>, new: Option<&package::MetaData>) {
+
+        let changes = self.files.get_or_insert_default();
+
+        let old_files = old.map(|m| &m.files);
+        let new_files = new.map(|m| &m.files);
+
+        for (path, new_file) in new_files.into_iter().flatten() {
+            match old_files.and_then(|files| files.get(path)) {
+                None => changes.files_added += 1,
+                Some(old_file) if std::mem::discriminant(&old_file.filetype) != std::mem::discriminant(&new_file.filetype) => {
+                    changes.files_removed += 1;
+                    changes.files_added += 1;
+                }
+                Some(old_file) if old_file.filetype != new_file.filetype => changes.files_replaced += 1,
+                Some(old_file) if !new_file.filetype.is_file() || (old_file.hash.is_some() && old_file.hash == new_file.hash) => {}
+                Some(_) => changes.files_replaced += 1,
+            }
+        }
+
+        for path in old_files.into_iter().flat_map(|files| files.keys()) {
+            if !new_files.is_some_and(|files| files.contains_key(path)) {
+                changes.files_removed += 1;
+            }
+        }
+    }
+}
+
+/// A package file the cache would remove
+#[derive(Debug, Serialize)]
+pub struct PlannedEviction {
+    pub filename: String,
+    pub size: u64,
+}
+
+/// Everything a command would do, without doing it
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub packages: Vec<PlannedPackage>,
+    pub cache_evictions: Vec<PlannedEviction>,
+}
+
+impl Plan {
+
+    /// total of the known download sizes
+    pub fn download_size(&self) -> u64 {
+        self.packages.iter().filter_map(|p| p.download_size).sum()
+    }
+
+    pub fn print(&self, json: bool) -> Result<()> {
+
+        if json {
+            #[derive(Serialize)]
+            struct Out<'a> {
+                #[serde(flatten)]
+                plan: &'a Plan,
+                download_size: u64,
+            }
+            println!("{}", serde_json::to_string_pretty(&Out { plan: self, download_size: self.download_size() })?);
+            return Ok(());
+        }
+
+        if self.packages.is_empty() && self.cache_evictions.is_empty() {
+            println!("Nothing to do");
+            return Ok(());
+        }
+
+        if !self.packages.is_empty() {
+            let mut tw = tabwriter::TabWriter::new(std::io::stdout());
+            if std::io::stdout().is_terminal() {
+                writeln!(&mut tw, "action\tpackage\tversion\tdownload\tadded\tremoved\treplaced")?;
+            }
+            for pkg in &self.packages {
+                let version = match (&pkg.old_version, &pkg.new_version) {
+                    (Some(old), Some(new)) if old != new => format!("{old} -> {new}"),
+                    (_, Some(v)) | (Some(v), None) => v.clone(),
+                    (None, None) => String::new(),
+                };
+                let name = match &pkg.required_by {
+                    Some(parent) => format!("{} (dependency of {})", pkg.name, parent),
+                    None => pkg.name.clone(),
+                };
+                let download = pkg.download_size.map_or("?".into(), |size| indicatif::HumanBytes(size).to_string());
+                let (added, removed, replaced) = match &pkg.files {
+                    Some(f) => (f.files_added.to_string(), f.files_removed.to_string(), f.files_replaced.to_string()),
+                    None => ("?".into(), "?".into(), "?".into()),
+                };
+                writeln!(&mut tw, "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    pkg.action,
+                    name,
+                    version,
+                    download,
+                    added,
+                    removed,
+                    replaced,
+                )?;
+            }
+            tw.flush()?;
+            if self.download_size() > 0 {
+                println!("Download size: {}", indicatif::HumanBytes(self.download_size()));
+            }
+            if self.packages.iter().any(|p| p.files.is_none()) {
+                println!("Files and dependencies of packages that are not cached are not known until they are downloaded");
+            }
+        }
+
+        if !self.cache_evictions.is_empty() {
+            let freed = self.cache_evictions.iter().map(|e| e.size).sum::<u64>();
+            println!("{} cache file{} to evict ({}):", self.cache_evictions.len(), tern!(self.cache_evictions.len() > 1, "s", ""), indicatif::HumanBytes(freed));
+            for ent in &self.cache_evictions {
+                println!("  {} ({})", ent.filename, indicatif::HumanBytes(ent.size));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl App {
+
+    /// Where a dry run finds a package file, without downloading anything.
+    /// returns (the file if it is cached, bytes to download),
+    /// the size is 0 for a cached file and none when no provider lists it.
+    pub(super) fn dry_run_package_file(&self, id: &PackageID) -> (Option<Utf8PathBuf>, Option<u64>) {
+
+        if let Some(path) = self.cache_package_lookup(id) {
+            return (Some(path), Some(0));
+        }
+
+        let size = self.filtered_providers()
+            .filter_map(|provider| provider.load_file().ok())
+            .find_map(|file| {
+                file.packages.packages.get(&id.name)?
+                    .versions.get(&id.version.as_str().into())?
+                    .iter()
+                    .find(|info| package::ArchMatcher::from(id.arch.as_deref()).matches(info.arch.as_deref()))
+                    .map(|info| info.size)
+            })
+            .flatten();
+
+        (None, size)
+    }
+
+    /// package files that `bpm cache clean` would remove, excluding `keep`
+    pub(super) fn plan_cache_clean(&self, keep: &[&str]) -> Vec<PlannedEviction> {
+        self.cache_clean_candidates()
+            .into_iter()
+            .filter_map(|path| {
+                let filename = path.file_name()?.to_string();
+                if keep.contains(&filename.as_str()) {
+                    return None;
+                }
+                let size = get_filesize(path.as_str()).unwrap_or(0);
+                Some(PlannedEviction { filename, size })
+            })
+            .collect()
+    }
+
+    /// evictions for a plan, if the cache is cleaned automatically after the operation
+    pub(super) fn plan_auto_clean(&self, plan: &mut Plan, keep: &[&str]) {
+        if self.config.cache_auto_clean {
+            plan.cache_evictions = self.plan_cache_clean(keep);
+        }
+    }
+}
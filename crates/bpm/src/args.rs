@@ -28,6 +28,19 @@ fn arch_arg() -> Arg {
         .action(ArgAction::Append)
 }
 
+fn yes_arg() -> Arg {
+    arg!(-y --yes "Do not ask for confirmation")
+}
+
+fn dry_run_arg() -> Arg {
+    arg!(--"dry-run" "Print what would be done without changing anything")
+}
+
+fn dry_run_json_arg() -> Arg {
+    arg!(--json "With --dry-run, print the plan in json format")
+        .requires("dry-run")
+}
+
 pub fn get_cli() -> Command {
 
     const STYLES: styling::Styles = styling::Styles::styled()
@@ -47,10 +60,19 @@ pub fn get_cli() -> Command {
         .subcommand(
             Command::new("scan")
                 .about("Scan providers, updating provider package list caches")
+                .arg(arg!([pkg]... "Only rescan these packages, keeping the rest of the package lists"))
                 .arg(arg!(--debounce <time> "Do not scan agian if last scan was within the given time"))
                 .arg(providers_arg())
                 .arg(arch_arg())
         )
+        .subcommand(
+            Command::new("publish")
+                .about("Upload a package file to a provider")
+                .arg(arg!(<file> "package file"))
+                .arg(arg!(--provider <name> "provider to upload to").required(true))
+                .arg(arg!(--channel <name> "also add the version to a channel"))
+                .after_help("The provider must be a bpmd server, its `token` in the config file is sent as a bearer token.")
+        )
         .subcommand(
             Command::new("search")
                 .about("Search for packages")
@@ -63,7 +85,7 @@ pub fn get_cli() -> Command {
                 .about("List installed or available packages")
                 .subcommand(Command::new("installed").about("List currently installed packages")
                     .arg(arg!(--"show-arch" "Show package architecture"))
-                    .after_help("Print a table of package name, version, channel, and optionally package architecture.\n\nThe version is prefixed with either = or ^.\n  = indicates that the package is pinned to this version\n  ^ means the package is eligible for updates.\nIf a channel is listed, the package is pinned to that channel.")
+                    .after_help("Print a table of package name, version, channel, and optionally package architecture.\n\nThe version is prefixed with either = or ^.\n  = indicates that the package is pinned to this version\n  ^ means the package is eligible for updates.\nIf a channel is listed, the package is pinned to that channel.\nIf a version range is listed, updates stay within that range.")
                 )
                 .subcommand(Command::new("available").about("List available packages")
                     .alias("avail")
@@ -78,6 +100,7 @@ pub fn get_cli() -> Command {
                         .conflicts_with("json")
                     )
                     .arg(arg!(--"show-arch" "Show package architecture"))
+                    .arg(arg!(--"show-size" "Show download size, if the provider lists it"))
                     .arg(arch_arg())
                     .arg(arg!(--channels <channels> "Filter on only the given channels")
                         .alias("channel")
@@ -98,28 +121,53 @@ pub fn get_cli() -> Command {
         .subcommand(
             Command::new("install")
                 .about("Install new packages")
-                .arg(arg!(<pkg> "Package name or path to local package file."))
+                .arg(arg!(<pkg>... "Package names or paths to local package files. A name may be followed by @ and a version, channel, or version range such as foo@^1.4"))
                 .arg(arg!(--"no-pin" "Do not pin to a specific version. Package may immediately be a candidate for updating."))
                 .arg(arg!(-u --update "Install a different version of an already installed package. No effect if pkg is not already installed."))
                 .arg(arg!(--reinstall "Allow installing the same version again."))
                 .arg(arg!(-t --target <location> "Install into user specified <location>. Can be prefixed with \"MOUNT:\" to name a mount in the config file."))
+                .arg(dry_run_arg())
+                .arg(dry_run_json_arg())
                 .arg(providers_arg())
                 .arg(arch_arg())
+                .after_help("Packages that can't be found or fetched are skipped, the rest are installed in one transaction.\nIf installing any of them fails, all of them are rolled back.")
         )
         .subcommand(
             Command::new("uninstall")
                 .visible_alias("remove")
                 .about("Remove installed packages")
-                .arg(arg!(<pkg> "package name or path to local package file"))
+                .arg(arg!(<pkg>... "Package(s) to uninstall"))
                 .arg(arg!(-v --verbose))
                 .arg(arg!(--"remove-unowned" "Remove any unowned files"))
+                .arg(arg!(--force "Uninstall even if other installed packages depend on this package"))
+                .arg(arg!(--cascade "Also uninstall all packages that depend on this package")
+                    .conflicts_with("force")
+                )
+                .arg(yes_arg())
+                .arg(dry_run_arg())
+                .arg(dry_run_json_arg())
+        )
+        .subcommand(
+            Command::new("autoremove")
+                .about("Remove packages that were installed as dependencies and are no longer needed")
+                .arg(yes_arg())
+                .arg(arg!(-v --verbose))
         )
         .subcommand(
             Command::new("update")
                 .about("Update packages")
                 .arg(arg!([pkg]... "package name or path to local package file"))
+                .arg(yes_arg())
+                .arg(dry_run_arg())
+                .arg(dry_run_json_arg())
                 .arg(providers_arg())
         )
+        .subcommand(
+            Command::new("rollback")
+                .about("Restore the version of a package that was installed before its last update")
+                .arg(arg!(<pkg> "Package to roll back"))
+                .after_help("The previous package file must still be in the cache, no provider is contacted.\nThe package is pinned to the restored version.")
+        )
         .subcommand(
             Command::new("verify")
                 .about("Perform consistency check on package state")
@@ -143,6 +191,10 @@ pub fn get_cli() -> Command {
                 .subcommand(Command::new("owner").about("Query which package owns a local file")
                     .arg(arg!(<file> "The file to find the owner of"))
                 )
+                .subcommand(Command::new("rdeps").about("Query which installed packages depend on a package")
+                    .arg(arg!(<pkg> "The package to find the dependents of"))
+                    .arg(arg!(-r --recursive "Include packages that depend on it indirectly"))
+                )
                 .subcommand(Command::new("list-files").about("Query the list of files from a package")
                     .alias("files")
                     .arg(arg!(<pkg> "The package to list the files of"))
@@ -193,15 +245,34 @@ pub fn get_cli() -> Command {
         )
         .subcommand(
             Command::new("pin")
-                .about("Pin a package to the version that is currently installed or to a channel")
+                .about("Pin a package to the version that is currently installed, to a channel, or to a version range")
                 .arg(arg!(<pkg> "Package being pinned"))
                 .arg(arg!(-c --channel <channel> "A channel to pin the package to"))
+                .arg(arg!(-r --range <range> "A version range to pin the package to, such as \"^1.4\" or \">=1.2, <2\"")
+                    .conflicts_with("channel")
+                )
         )
         .subcommand(
             Command::new("unpin")
-                .about("Unpin a package from a version or channel")
+                .about("Unpin a package from a version, channel, or version range")
                 .arg(arg!(<pkg> "Package being pinned"))
         )
+        .subcommand(
+            Command::new("history")
+                .about("Show the history of changes to installed packages")
+                .arg(arg!([pkg] "Only show changes to this package"))
+                .arg(arg!(-n --limit <N> "Only show the N most recent changes")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("0")
+                )
+        )
+        .subcommand(
+            Command::new("undo")
+                .about("Reverse a change listed by `bpm history`")
+                .arg(arg!(<id> "The history id of the change to undo")
+                    .value_parser(clap::value_parser!(u64))
+                )
+        )
         //.subcommand(
         //    Command::new("inspect")
         //)
@@ -234,11 +305,14 @@ pub fn get_cli() -> Command {
                 .subcommand(
                     Command::new("clean")
                         .about("Clean expired cache entries")
+                        .arg(dry_run_arg())
+                        .arg(dry_run_json_arg())
                 )
                 .subcommand(
                     Command::new("clear")
                         .about("Clear the cache")
                         .arg(arg!(--"in-use" "Allow evicting package files that are currently in use"))
+                        .arg(yes_arg())
                 )
                 .subcommand(
                     Command::new("evict")
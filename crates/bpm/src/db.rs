@@ -1,6 +1,7 @@
 use chrono::SubsecRound;
 use package::PackageID;
 use crate::*;
+use std::collections::HashSet;
 
 //const DB_VERSION : u32 = 1;
 
@@ -34,7 +35,28 @@ pub struct DbPkg {
 
     pub metadata: package::MetaData,
 
-    pub package_file_filename: Option<String>
+    pub package_file_filename: Option<String>,
+
+    /// Installed as a dependency of another package, not explicitly by the user.
+    #[serde(default)]
+    pub auto_installed: bool,
+
+    /// The version that was installed before the last update, used by `bpm rollback`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<PreviousVersion>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreviousVersion {
+
+    pub version: String,
+
+    pub package_file_filename: String,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package_hash: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,6 +78,107 @@ pub struct CacheFile {
     pub retention: Option<std::time::Duration>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryAction {
+    Install,
+    Update,
+    Uninstall,
+    Pin,
+    Unpin,
+}
+
+impl std::fmt::Display for HistoryAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Install   => "install",
+            Self::Update    => "update",
+            Self::Uninstall => "uninstall",
+            Self::Pin       => "pin",
+            Self::Unpin     => "unpin",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A record of one change to an installed package
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+
+    pub id: u64,
+
+    pub time: chrono::DateTime<chrono::offset::Utc>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    pub action: HistoryAction,
+
+    pub package: String,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_version: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_version: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+
+    /// hash of the package file that was installed, or removed for an uninstall
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package_hash: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_versioning: Option<Versioning>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_versioning: Option<Versioning>,
+
+    /// where the package was installed, or removed from for an uninstall
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<config::PathType>,
+
+    /// the package was installed as a dependency
+    #[serde(default)]
+    pub auto_installed: bool,
+}
+
+impl HistoryEntry {
+    pub fn new(action: HistoryAction, package: &str) -> Self {
+        Self {
+            id: 0,
+            time: chrono::Utc::now().round_subsecs(0),
+            user: current_user(),
+            action,
+            package: package.to_string(),
+            old_version: None,
+            new_version: None,
+            arch: None,
+            package_hash: None,
+            old_versioning: None,
+            new_versioning: None,
+            location: None,
+            auto_installed: false,
+        }
+    }
+}
+
+fn current_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|u| !u.is_empty())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Db {
     //version: u32,
@@ -63,6 +186,10 @@ pub struct Db {
 
     #[serde(default)]
     pub cache_files: Vec<CacheFile>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<HistoryEntry>,
 }
 
 impl DbPkg {
@@ -72,6 +199,8 @@ impl DbPkg {
             location: None,
             versioning: Versioning::default(),
             package_file_filename: None,
+            auto_installed: false,
+            previous: None,
         }
     }
 }
@@ -81,6 +210,7 @@ impl Db {
         Self {
             installed: Vec::new(),
             cache_files: Vec::new(),
+            history: Vec::new(),
         }
     }
 
@@ -208,6 +338,79 @@ impl Db {
         }
     }
 
+    /// installed packages that directly depend on a package
+    pub fn dependents(&self, pkg_name: &str) -> Vec<&DbPkg> {
+        self.installed.iter()
+            .filter(|p| p.metadata.dependencies.contains_key(pkg_name))
+            .collect()
+    }
+
+    /// Names of all installed packages that directly or indirectly depend on a package.
+    /// Ordered such that a package comes before anything it depends on, so it is safe to uninstall in order.
+    pub fn dependents_recursive(&self, pkg_name: &str) -> Vec<String> {
+        fn visit(db: &Db, pkg_name: &str, visited: &mut HashSet<String>, out: &mut Vec<String>) {
+            for dep in db.dependents(pkg_name) {
+                if visited.insert(dep.metadata.name.clone()) {
+                    visit(db, &dep.metadata.name, visited, out);
+                    out.push(dep.metadata.name.clone());
+                }
+            }
+        }
+        let mut visited = HashSet::from([pkg_name.to_string()]);
+        let mut out = Vec::new();
+        visit(self, pkg_name, &mut visited, &mut out);
+        out
+    }
+
+    /// Auto-installed packages that no explicitly installed package depends on, directly or indirectly.
+    /// Removing one package can orphan others, those are included too, as are dependency cycles.
+    /// Ordered such that a package comes before anything it depends on.
+    pub fn orphaned_packages(&self) -> Vec<String> {
+
+        let find = |name: &str| self.installed.iter().find(|p| p.metadata.name == name);
+
+        // everything reachable from an explicitly installed package is kept
+        let mut keep : HashSet<&str> = HashSet::new();
+        let mut stack : Vec<&DbPkg> = self.installed.iter().filter(|p| !p.auto_installed).collect();
+        while let Some(pkg) = stack.pop() {
+            if keep.insert(&pkg.metadata.name) {
+                stack.extend(pkg.metadata.dependencies.keys().filter_map(|name| find(name)));
+            }
+        }
+
+        // order the rest by dependencies, post-order puts a package after what it depends on
+        fn visit<'a>(db: &'a Db, pkg: &'a DbPkg, keep: &HashSet<&str>, visited: &mut HashSet<&'a str>, out: &mut Vec<String>) {
+            if keep.contains(pkg.metadata.name.as_str()) || !visited.insert(&pkg.metadata.name) {
+                return;
+            }
+            for name in pkg.metadata.dependencies.keys() {
+                if let Some(dep) = db.installed.iter().find(|p| &p.metadata.name == name) {
+                    visit(db, dep, keep, visited, out);
+                }
+            }
+            out.push(pkg.metadata.name.clone());
+        }
+
+        let mut visited = HashSet::new();
+        let mut orphans = Vec::new();
+        for pkg in &self.installed {
+            visit(self, pkg, &keep, &mut visited, &mut orphans);
+        }
+        orphans.reverse();
+        orphans
+    }
+
+    /// Append an entry to the history, assigning it the next id.
+    /// The oldest entries are dropped to keep at most `limit` of them, 0 means no limit.
+    pub fn history_add(&mut self, mut entry: HistoryEntry, limit: usize) {
+        entry.id = self.history.last().map_or(1, |e| e.id + 1);
+        self.history.push(entry);
+        if limit > 0 && self.history.len() > limit {
+            let excess = self.history.len() - limit;
+            self.history.drain(..excess);
+        }
+    }
+
     pub fn set_versioning(&mut self, pkg_name: &str, versioning: Versioning) {
         for pkg in &mut self.installed {
             if pkg.metadata.name == pkg_name {
@@ -218,3 +421,87 @@ impl Db {
     }
 
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// a db with packages given as (name, auto installed, dependencies)
+    fn make_db(pkgs: &[(&str, bool, &[&str])]) -> Db {
+        let mut db = Db::new();
+        for (name, auto_installed, deps) in pkgs {
+            let mut metadata = package::MetaData::new(PackageID { name: name.to_string(), version: "1.0".into(), arch: None });
+            for dep in *deps {
+                metadata.add_dependency(package::DependencyID { name: dep.to_string(), version: None });
+            }
+            let mut pkg = DbPkg::new(metadata);
+            pkg.auto_installed = *auto_installed;
+            db.add_package(pkg);
+        }
+        db
+    }
+
+    fn position(list: &[String], name: &str) -> usize {
+        list.iter().position(|n| n == name).unwrap()
+    }
+
+    #[test]
+    fn chain() {
+        // a -> b -> c
+        let mut db = make_db(&[("a", false, &["b"]), ("b", true, &["c"]), ("c", true, &[])]);
+
+        let names : Vec<&str> = db.dependents("c").iter().map(|p| p.metadata.name.as_str()).collect();
+        assert_eq!(names, ["b"]);
+        assert_eq!(db.dependents_recursive("c"), ["a", "b"]);
+        assert!(db.dependents_recursive("a").is_empty());
+        assert!(db.orphaned_packages().is_empty());
+
+        db.remove_package(PackageID { name: "a".into(), version: "1.0".into(), arch: None });
+        assert_eq!(db.orphaned_packages(), ["b", "c"]);
+    }
+
+    #[test]
+    fn diamond() {
+        // a -> b, a -> c, b -> d, c -> d
+        let mut db = make_db(&[("a", false, &["b", "c"]), ("b", true, &["d"]), ("c", true, &["d"]), ("d", true, &[])]);
+
+        let deps = db.dependents_recursive("d");
+        assert_eq!(deps.len(), 3);
+        assert!(position(&deps, "a") < position(&deps, "b"));
+        assert!(position(&deps, "a") < position(&deps, "c"));
+        assert!(db.orphaned_packages().is_empty());
+
+        db.remove_package(PackageID { name: "a".into(), version: "1.0".into(), arch: None });
+        let orphans = db.orphaned_packages();
+        assert_eq!(orphans.len(), 3);
+        assert!(position(&orphans, "b") < position(&orphans, "d"));
+        assert!(position(&orphans, "c") < position(&orphans, "d"));
+    }
+
+    #[test]
+    fn cycle() {
+        // a -> b -> c -> b
+        let mut db = make_db(&[("a", false, &["b"]), ("b", true, &["c"]), ("c", true, &["b"])]);
+
+        let mut deps = db.dependents_recursive("c");
+        assert_eq!(position(&deps, "a"), 0);
+        deps.sort();
+        assert_eq!(deps, ["a", "b"]);
+        assert!(db.orphaned_packages().is_empty());
+
+        db.remove_package(PackageID { name: "a".into(), version: "1.0".into(), arch: None });
+        let mut orphans = db.orphaned_packages();
+        orphans.sort();
+        assert_eq!(orphans, ["b", "c"]);
+    }
+
+    #[test]
+    fn explicit_is_not_orphan() {
+        // b was installed explicitly, then a came to depend on it
+        let mut db = make_db(&[("a", true, &["b"]), ("b", false, &["c"]), ("c", true, &[])]);
+        assert_eq!(db.orphaned_packages(), ["a"]);
+
+        db.remove_package(PackageID { name: "a".into(), version: "1.0".into(), arch: None });
+        assert!(db.orphaned_packages().is_empty());
+    }
+}
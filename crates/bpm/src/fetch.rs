@@ -1,7 +1,23 @@
 use anyhow::Result;
 use package::PackageID;
-use std::io::Write;
+use std::fs::File;
+use std::io::{Seek, Write};
 
 pub trait Fetch {
-    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str) -> Result<u64>;
+    /// `size` is the size of the file if the provider listed it, for progress
+    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str, size: Option<u64>) -> Result<u64>;
+
+    /// Download into `file`, which may already hold the start of the file from an earlier attempt.
+    /// Sources that can not continue a download start over.
+    fn fetch_resume(&self, file: &mut File, pkg: &PackageID, url: &str, size: Option<u64>) -> Result<u64> {
+        file.set_len(0)?;
+        file.rewind()?;
+        self.fetch(file, pkg, url, size)
+    }
+
+    /// uri of a file, given its path relative to the provider root
+    fn file_uri(&self, path: &str) -> Result<String>;
+
+    /// read a small file relative to the provider root, None if it does not exist
+    fn read_file(&self, path: &str) -> Result<Option<Vec<u8>>>;
 }
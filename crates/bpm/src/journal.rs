@@ -0,0 +1,548 @@
+//! Transaction journal for operations that modify installed packages.
+//!
+//! While a transaction is active, a journal directory exists next to the database file.
+//! It holds a snapshot of the database from when the transaction began and a log of every
+//! path that was about to be modified.
+//! Files that get overwritten or removed are moved into a `.bpm-journal` dir in their install location,
+//! which is on the same filesystem, so backing up a file never copies it.
+//! A read-only directory is made writable for that, and its mode is restored when the transaction ends.
+//!
+//! Committing a transaction removes the journal. A journal that still exists when bpm
+//! starts belongs to an interrupted transaction, which is either finished (if it had
+//! committed) or rolled back.
+
+use crate::*;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const LOG_FILE : &str = "journal";
+const DB_SNAPSHOT : &str = "db.snapshot";
+const LOCATION_BACKUP_DIR : &str = ".bpm-journal";
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    /// start of a transaction
+    Begin {
+        op: String,
+        time: chrono::DateTime<chrono::Utc>,
+        db_existed: bool,
+    },
+    /// the path did not exist
+    Created { path: Utf8PathBuf },
+    /// the path was a regular file, it was moved to `backup`
+    Saved { path: Utf8PathBuf, backup: Utf8PathBuf },
+    /// the path was a symlink
+    Link { path: Utf8PathBuf, to: Utf8PathBuf },
+    /// the path was a directory
+    Dir { path: Utf8PathBuf },
+    /// the directory was read-only with this mode, and was made writable
+    Mode { path: Utf8PathBuf, mode: u32 },
+    /// all changes are complete, the journal can be removed
+    Commit,
+}
+
+/// What was done with an interrupted transaction
+#[derive(Debug)]
+pub enum Recovery {
+    /// the transaction had committed, only cleanup was left
+    Finished(String),
+    /// the transaction was rolled back
+    RolledBack(String),
+}
+
+/// the journal directory for a database file, `db.json` -> `db.json.journal`
+pub fn journal_dir(db_file: &Utf8Path) -> Utf8PathBuf {
+    let mut path = db_file.to_path_buf();
+    let ext = path.extension().map_or("journal".into(), |e| format!("{}.journal", e));
+    path.set_extension(ext);
+    path
+}
+
+#[derive(Debug)]
+pub struct Transaction {
+    dir: Utf8PathBuf,
+    log: Mutex<BufWriter<File>>,
+    next_backup: AtomicU64,
+
+    /// backup dirs created in install locations
+    backup_dirs: Mutex<BTreeSet<Utf8PathBuf>>,
+
+    /// parent dirs of saved paths, with the mode to restore on the ones that were made writable
+    parent_dirs: Mutex<BTreeMap<Utf8PathBuf, Option<u32>>>,
+}
+
+impl Transaction {
+
+    /// Begin a new transaction, snapshotting the database file.
+    pub fn begin(db_file: &Utf8Path, op: &str) -> AResult<Self> {
+
+        let dir = journal_dir(db_file);
+        if dir.exists() {
+            anyhow::bail!("an unfinished transaction journal exists at {dir}");
+        }
+
+        tracing::debug!(op, "begin transaction");
+
+        std::fs::create_dir_all(&dir).context("failed to create transaction journal")?;
+
+        let db_existed = db_file.exists();
+        if db_existed {
+            std::fs::copy(db_file, dir.join(DB_SNAPSHOT)).context("failed to snapshot database")?;
+        }
+
+        let log = File::create(dir.join(LOG_FILE)).context("failed to create transaction journal")?;
+        let txn = Self {
+            dir,
+            log: Mutex::new(BufWriter::new(log)),
+            next_backup: AtomicU64::new(0),
+            backup_dirs: Mutex::new(BTreeSet::new()),
+            parent_dirs: Mutex::new(BTreeMap::new()),
+        };
+
+        txn.write(&Record::Begin {
+            op: op.to_string(),
+            time: chrono::Utc::now(),
+            db_existed,
+        })?;
+
+        Ok(txn)
+    }
+
+    fn write(&self, record: &Record) -> AResult<()> {
+        let mut log = self.log.lock().unwrap();
+        serde_json::to_writer(&mut *log, record)?;
+        log.write_all(b"\n")?;
+        log.flush()?;
+        Ok(())
+    }
+
+    /// Make a read-only dir writable until the transaction ends, recording its mode first.
+    #[cfg(unix)]
+    fn make_writable(&self, dir: &Utf8Path) -> AResult<()> {
+
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut parent_dirs = self.parent_dirs.lock().unwrap();
+        if parent_dirs.insert(dir.to_path_buf(), None).is_some() {
+            return Ok(());
+        }
+
+        let md = match std::fs::metadata(dir) {
+            Ok(md) => md,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {dir}")),
+        };
+        let mode = md.permissions().mode();
+        if mode & 0o200 != 0 {
+            return Ok(());
+        }
+
+        self.write(&Record::Mode { path: dir.to_path_buf(), mode })?;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(mode | 0o200))
+            .with_context(|| format!("failed to make {dir} writable"))?;
+        parent_dirs.insert(dir.to_path_buf(), Some(mode));
+        Ok(())
+    }
+
+    /// Record the state of a path in the install `location` before it gets modified or removed.
+    ///
+    /// Regular files are moved into the location's backup dir, so the path no longer exists after this call.
+    /// A file that can not be moved there, such as one on another filesystem, is an error.
+    /// The path's parent dir stays writable until the transaction ends.
+    pub fn save(&self, location: &Utf8Path, path: &Utf8Path) -> AResult<()> {
+
+        #[cfg(unix)]
+        if let Some(parent) = path.parent() {
+            self.make_writable(parent)?;
+        }
+
+        let md = match std::fs::symlink_metadata(path) {
+            Ok(md) => md,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return self.write(&Record::Created { path: path.to_path_buf() });
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to read {path}")),
+        };
+
+        if md.is_symlink() {
+            let to = std::fs::read_link(path).with_context(|| format!("failed to read symlink {path}"))?;
+            let to = Utf8PathBuf::from_path_buf(to).map_err(|_| anyhow::anyhow!("symlink target is not utf8: {path}"))?;
+            self.write(&Record::Link { path: path.to_path_buf(), to })
+        } else if md.is_dir() {
+            self.write(&Record::Dir { path: path.to_path_buf() })
+        } else {
+            let backup_dir = location.join(LOCATION_BACKUP_DIR);
+            if self.backup_dirs.lock().unwrap().insert(backup_dir.clone()) {
+                std::fs::create_dir_all(&backup_dir).with_context(|| format!("failed to create {backup_dir}"))?;
+            }
+            let backup_path = backup_dir.join(self.next_backup.fetch_add(1, Ordering::Relaxed).to_string());
+
+            // record first, a backup that is missing during rollback means the file was never touched
+            self.write(&Record::Saved { path: path.to_path_buf(), backup: backup_path.clone() })?;
+
+            std::fs::rename(path, &backup_path)
+                .with_context(|| format!("failed to move {path} to {backup_path}, is it on a different filesystem than its install location?"))?;
+            Ok(())
+        }
+    }
+
+    /// Mark the transaction complete and remove the journal.
+    pub fn commit(self) -> AResult<()> {
+        self.write(&Record::Commit)?;
+        let log = self.log.into_inner().unwrap().into_inner()?;
+        log.sync_all()?;
+        drop(log);
+        tracing::debug!("commit transaction");
+        for dir in self.backup_dirs.into_inner().unwrap() {
+            remove_backup_dir(&dir)?;
+        }
+        for (dir, mode) in self.parent_dirs.into_inner().unwrap() {
+            if let Some(mode) = mode {
+                restore_mode(&dir, mode)?;
+            }
+        }
+        std::fs::remove_dir_all(&self.dir).context("failed to remove transaction journal")?;
+        Ok(())
+    }
+
+    /// Undo all recorded changes and restore the database file.
+    pub fn rollback(self, db_file: &Utf8Path) -> AResult<()> {
+        drop(self.log);
+        rollback_dir(&self.dir, db_file)
+    }
+}
+
+fn read_records(dir: &Utf8Path) -> AResult<Vec<Record>> {
+    let file = File::open(dir.join(LOG_FILE)).context("failed to open transaction journal")?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        // a partially written last line is ignored
+        match serde_json::from_str(&line?) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+    }
+    Ok(records)
+}
+
+fn remove_backup_dir(dir: &Utf8Path) -> AResult<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).with_context(|| format!("failed to remove {dir}")),
+        _ => Ok(()),
+    }
+}
+
+/// the backup dirs that saved files were moved to
+fn backup_dirs(records: &[Record]) -> BTreeSet<Utf8PathBuf> {
+    records.iter()
+        .filter_map(|r| match r {
+            Record::Saved { backup, .. } => backup.parent().map(Utf8Path::to_path_buf),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Restore the mode of a dir that was made writable, unless the dir was removed since.
+fn restore_mode(dir: &Utf8Path, mode: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        match std::fs::set_permissions(dir, std::fs::Permissions::from_mode(mode)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (dir, mode);
+    Ok(())
+}
+
+/// Retry `op` with the parent dir of `path` made writable, if the parent is read-only.
+/// A package can make a dir read-only again after the transaction made it writable.
+fn with_writable_parent(path: &Utf8Path, op: impl Fn() -> std::io::Result<()>) -> std::io::Result<()> {
+    match op() {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {}
+        ret => return ret,
+    }
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() && let Ok(md) = std::fs::metadata(parent) {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = md.permissions().mode();
+        if mode & 0o200 == 0 {
+            std::fs::set_permissions(parent, std::fs::Permissions::from_mode(mode | 0o200))?;
+            let ret = op();
+            let _ = std::fs::set_permissions(parent, std::fs::Permissions::from_mode(mode));
+            return ret;
+        }
+    }
+    op()
+}
+
+/// the modes of the dirs that were made writable
+fn dir_modes(records: &[Record]) -> Vec<(&Utf8Path, u32)> {
+    records.iter()
+        .filter_map(|r| match r {
+            Record::Mode { path, mode } => Some((path.as_path(), *mode)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn remove_path(path: &Utf8Path) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(md) if md.is_dir() => std::fs::remove_dir(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn create_symlink(to: &Utf8Path, path: &Utf8Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(to, path);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(to, path);
+}
+
+fn rollback_dir(dir: &Utf8Path, db_file: &Utf8Path) -> AResult<()> {
+
+    let records = read_records(dir).unwrap_or_default();
+    let mut errors = 0;
+
+    for record in records.iter().rev() {
+
+        let ret = match record {
+            Record::Begin{..} | Record::Commit => Ok(()),
+            Record::Created { path } => {
+                tracing::trace!("rollback remove {path}");
+                match with_writable_parent(path, || remove_path(path)) {
+                    // a dir may contain files that were not part of the transaction
+                    Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => Ok(()),
+                    ret => ret,
+                }
+            }
+            Record::Saved { path, backup } => {
+                if backup.exists() {
+                    tracing::trace!("rollback restore {path}");
+                    let _ = remove_path(path);
+                    if let Some(parent) = path.parent() {
+                        let _ = std::fs::create_dir_all(parent);
+                    }
+                    with_writable_parent(path, || std::fs::rename(backup, path))
+                } else {
+                    Ok(())
+                }
+            }
+            Record::Link { path, to } => {
+                let current = std::fs::read_link(path).ok();
+                if current.as_deref() == Some(to.as_std_path()) {
+                    Ok(())
+                } else {
+                    tracing::trace!("rollback restore {path} -> {to}");
+                    let _ = remove_path(path);
+                    if let Some(parent) = path.parent() {
+                        let _ = std::fs::create_dir_all(parent);
+                    }
+                    with_writable_parent(path, || create_symlink(to, path))
+                }
+            }
+            Record::Dir { path } => {
+                std::fs::create_dir_all(path)
+            }
+            Record::Mode { path, mode } => {
+                // the files in the dir have been restored
+                tracing::trace!("rollback mode {path} {mode:o}");
+                restore_mode(path, *mode)
+            }
+        };
+
+        if let Err(e) = ret {
+            errors += 1;
+            eprintln!("rollback error: {:?}: {}", record, e);
+        }
+    }
+
+    // restore the database
+    let db_existed = records.iter().find_map(|r| match r {
+        Record::Begin { db_existed, .. } => Some(*db_existed),
+        _ => None,
+    });
+    match db_existed {
+        Some(true) => {
+            std::fs::copy(dir.join(DB_SNAPSHOT), db_file).context("failed to restore database")?;
+        }
+        Some(false) => {
+            let _ = std::fs::remove_file(db_file);
+        }
+        None => {
+            // the transaction never began, nothing to restore
+        }
+    }
+
+    if errors == 0 {
+        for backup_dir in backup_dirs(&records) {
+            remove_backup_dir(&backup_dir)?;
+        }
+    }
+
+    std::fs::remove_dir_all(dir).context("failed to remove transaction journal")?;
+
+    if errors > 0 {
+        anyhow::bail!("{errors} error{} during rollback", if errors > 1 { "s" } else { "" });
+    }
+
+    Ok(())
+}
+
+/// Finish or roll back an interrupted transaction, if there is one.
+pub fn recover(db_file: &Utf8Path) -> AResult<Option<Recovery>> {
+
+    let dir = journal_dir(db_file);
+    if !dir.exists() {
+        return Ok(None);
+    }
+
+    let records = read_records(&dir).unwrap_or_default();
+    let op = records.iter().find_map(|r| match r {
+        Record::Begin { op, .. } => Some(op.clone()),
+        _ => None,
+    }).unwrap_or_else(|| "unknown operation".into());
+
+    if matches!(records.last(), Some(Record::Commit)) {
+        for backup_dir in backup_dirs(&records) {
+            remove_backup_dir(&backup_dir)?;
+        }
+        for (dir, mode) in dir_modes(&records).into_iter().rev() {
+            restore_mode(dir, mode)?;
+        }
+        std::fs::remove_dir_all(&dir).context("failed to remove transaction journal")?;
+        return Ok(Some(Recovery::Finished(op)));
+    }
+
+    rollback_dir(&dir, db_file)?;
+    Ok(Some(Recovery::RolledBack(op)))
+}
+
+/// Is there a journal for an interrupted transaction?
+pub fn exists(db_file: &Utf8Path) -> bool {
+    journal_dir(db_file).exists()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn rollback_restores_files_and_db() -> AResult<()> {
+
+        let dir = std::env::temp_dir().join(format!("bpm-journal-test-{}", std::process::id()));
+        let dir = Utf8PathBuf::from_path_buf(dir).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let db_file = dir.join("db.json");
+        std::fs::write(&db_file, "before")?;
+
+        let kept = dir.join("kept.txt");
+        let removed = dir.join("removed.txt");
+        let created = dir.join("created.txt");
+        std::fs::write(&kept, "kept")?;
+        std::fs::write(&removed, "removed")?;
+
+        let txn = Transaction::begin(&db_file, "test")?;
+        txn.save(&dir, &kept)?;
+        std::fs::write(&kept, "overwritten")?;
+        txn.save(&dir, &removed)?;
+        let _ = std::fs::remove_file(&removed);
+        txn.save(&dir, &created)?;
+        std::fs::write(&created, "created")?;
+        std::fs::write(&db_file, "after")?;
+
+        // simulate an interrupted run
+        drop(txn);
+        assert!(exists(&db_file));
+        assert!(matches!(recover(&db_file)?, Some(Recovery::RolledBack(op)) if op == "test"));
+
+        assert_eq!(std::fs::read_to_string(&kept)?, "kept");
+        assert_eq!(std::fs::read_to_string(&removed)?, "removed");
+        assert!(!created.exists());
+        assert_eq!(std::fs::read_to_string(&db_file)?, "before");
+        assert!(!exists(&db_file));
+        assert!(!dir.join(LOCATION_BACKUP_DIR).exists());
+
+        // a committed transaction leaves changes in place
+        let txn = Transaction::begin(&db_file, "test")?;
+        txn.save(&dir, &created)?;
+        std::fs::write(&created, "created")?;
+        txn.save(&dir, &kept)?;
+        std::fs::write(&kept, "overwritten")?;
+        txn.commit()?;
+        assert!(created.exists());
+        assert_eq!(std::fs::read_to_string(&kept)?, "overwritten");
+        assert!(!dir.join(LOCATION_BACKUP_DIR).exists());
+        assert!(!exists(&db_file));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn readonly_dir() -> AResult<()> {
+
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path: &Utf8Path| -> AResult<u32> { Ok(std::fs::metadata(path)?.permissions().mode() & 0o777) };
+
+        let dir = std::env::temp_dir().join(format!("bpm-journal-ro-test-{}", std::process::id()));
+        let dir = Utf8PathBuf::from_path_buf(dir).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let db_file = dir.join("db.json");
+        std::fs::write(&db_file, "before")?;
+
+        let ro_dir = dir.join("ro");
+        let file = ro_dir.join("file.txt");
+        std::fs::create_dir(&ro_dir)?;
+        std::fs::write(&file, "before")?;
+        std::fs::set_permissions(&ro_dir, std::fs::Permissions::from_mode(0o555))?;
+
+        // the dir is writable while the transaction is active, and read-only again after a rollback
+        let txn = Transaction::begin(&db_file, "test")?;
+        txn.save(&dir, &file)?;
+        assert_eq!(mode(&ro_dir)?, 0o755);
+        std::fs::write(&file, "after")?;
+        drop(txn);
+        assert!(matches!(recover(&db_file)?, Some(Recovery::RolledBack(_))));
+        assert_eq!(std::fs::read_to_string(&file)?, "before");
+        assert_eq!(mode(&ro_dir)?, 0o555);
+
+        // and after a commit
+        let txn = Transaction::begin(&db_file, "test")?;
+        txn.save(&dir, &file)?;
+        std::fs::write(&file, "after")?;
+        txn.commit()?;
+        assert_eq!(std::fs::read_to_string(&file)?, "after");
+        assert_eq!(mode(&ro_dir)?, 0o555);
+
+        // a committed transaction that was interrupted before cleanup
+        let txn = Transaction::begin(&db_file, "test")?;
+        txn.save(&dir, &file)?;
+        assert_eq!(mode(&ro_dir)?, 0o755);
+        txn.write(&Record::Commit)?;
+        drop(txn);
+        assert!(matches!(recover(&db_file)?, Some(Recovery::Finished(_))));
+        assert_eq!(mode(&ro_dir)?, 0o555);
+
+        std::fs::set_permissions(&ro_dir, std::fs::Permissions::from_mode(0o755))?;
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
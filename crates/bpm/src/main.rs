@@ -3,14 +3,13 @@
 
 // ideas for future features
 // - capability probing
-// - multiple paths for a provider OR provider groups. (probably groups)
-// - fastpath, pre-scan, pre-scan age limit, version it
 
 mod app;
 mod args;
 mod config;
 mod db;
 mod fetch;
+mod journal;
 mod macros;
 mod provider;
 mod search;
@@ -144,12 +143,17 @@ fn main() -> AResult<()> {
     match matches.subcommand() {
         Some(("cache", sub_matches)) => {
             match sub_matches.subcommand() {
-                Some(("clean", _matches)) => {
-                    app.cache_clean()?;
+                Some(("clean", matches)) => {
+                    if matches.get_flag("dry-run") {
+                        app.cache_clean_dry_run(matches.get_flag("json"))?;
+                    } else {
+                        app.cache_clean()?;
+                    }
                 },
                 Some(("clear", matches)) => {
                     let in_use = matches.get_flag("in-use");
-                    app.cache_clear(in_use)?;
+                    let yes = matches.get_flag("yes");
+                    app.cache_clear(in_use, yes)?;
                 },
                 Some(("evict", matches)) => {
                     let pkg = matches.get_one::<String>("pkg").unwrap();
@@ -202,27 +206,47 @@ fn main() -> AResult<()> {
 
             app.provider_filter = args::parse_providers(sub_matches);
 
-            app.scan_cmd(debounce)?;
+            let pkg_names : Vec<_> = sub_matches.get_many::<String>("pkg").map_or(Vec::new(), |v| v.collect());
+
+            app.scan_cmd(debounce, &pkg_names)?;
+        }
+        Some(("publish", sub_matches)) => {
+            let file = Utf8PathBuf::from(sub_matches.get_one::<String>("file").unwrap());
+            let provider = sub_matches.get_one::<String>("provider").unwrap();
+            let channel = sub_matches.get_one::<String>("channel");
+            app.publish_cmd(&file, provider, channel.map(|s| s.as_str()))?;
         }
         Some(("install", sub_matches)) => {
 
             let no_pin = sub_matches.get_flag("no-pin");
-            let pkg_name = sub_matches.get_one::<String>("pkg").unwrap();
+            let pkg_names : Vec<_> = sub_matches.get_many::<String>("pkg").unwrap().collect();
             let update = sub_matches.get_flag("update");
             let reinstall = sub_matches.get_flag("reinstall");
             let target = sub_matches.get_one::<String>("target");
+            let dry_run = sub_matches.get_flag("dry-run");
+            let json = sub_matches.get_flag("json");
 
             let arch = args::pull_many_opt(sub_matches, "arch");
             app.setup_arch_filter(arch);
 
             app.provider_filter = args::parse_providers(sub_matches);
-            app.install_cmd(pkg_name, no_pin, update, reinstall, target)?;
+            app.install_cmd(&pkg_names, no_pin, update, reinstall, target, dry_run, json)?;
         }
         Some(("uninstall", sub_matches)) => {
-            let pkg_name = sub_matches.get_one::<String>("pkg").unwrap();
+            let pkg_names : Vec<_> = sub_matches.get_many::<String>("pkg").unwrap().collect();
             let verbose = sub_matches.get_flag("verbose");
             let remove_unowned = sub_matches.get_flag("remove-unowned");
-            app.uninstall_cmd(pkg_name, verbose, remove_unowned)?;
+            let force = sub_matches.get_flag("force");
+            let cascade = sub_matches.get_flag("cascade");
+            let dry_run = sub_matches.get_flag("dry-run");
+            let json = sub_matches.get_flag("json");
+            let yes = sub_matches.get_flag("yes");
+            app.uninstall_cmd(&pkg_names, verbose, remove_unowned, force, cascade, dry_run, json, yes)?;
+        }
+        Some(("autoremove", sub_matches)) => {
+            let yes = sub_matches.get_flag("yes");
+            let verbose = sub_matches.get_flag("verbose");
+            app.autoremove_cmd(yes, verbose)?;
         }
         Some(("update", sub_matches)) => {
 
@@ -234,17 +258,34 @@ fn main() -> AResult<()> {
 
             app.setup_arch_filter(None);
 
-            app.update_packages_cmd(&pkg_names)?;
+            let dry_run = sub_matches.get_flag("dry-run");
+            let json = sub_matches.get_flag("json");
+            let yes = sub_matches.get_flag("yes");
+            app.update_packages_cmd(&pkg_names, dry_run, json, yes)?;
+        }
+        Some(("rollback", sub_matches)) => {
+            let pkg_name = sub_matches.get_one::<String>("pkg").unwrap();
+            app.rollback_cmd(pkg_name)?;
         }
         Some(("pin", sub_matches)) => {
             let pkg_name = sub_matches.get_one::<String>("pkg").unwrap();
             let channel = sub_matches.get_one::<String>("channel").map(String::as_str);
-            app.pin(pkg_name, channel)?;
+            let range = sub_matches.get_one::<String>("range").map(String::as_str);
+            app.pin(pkg_name, channel, range)?;
         }
         Some(("unpin", sub_matches)) => {
             let pkg_name = sub_matches.get_one::<String>("pkg").unwrap();
             app.unpin(pkg_name)?;
         }
+        Some(("history", sub_matches)) => {
+            let pkg_name = sub_matches.get_one::<String>("pkg");
+            let limit = *sub_matches.get_one::<usize>("limit").unwrap();
+            app.history_cmd(pkg_name, limit)?;
+        }
+        Some(("undo", sub_matches)) => {
+            let id = *sub_matches.get_one::<u64>("id").unwrap();
+            app.undo_cmd(id)?;
+        }
         Some(("verify", sub_matches)) => {
 
             // -q quiet option, don't print file status, just return good or bad exit code
@@ -278,6 +319,11 @@ fn main() -> AResult<()> {
                     let file = sub_matches.get_one::<String>("file").unwrap();
                     app.query_owner(file)?;
                 }
+                Some(("rdeps", sub_matches)) => {
+                    let pkg = sub_matches.get_one::<String>("pkg").unwrap();
+                    let recursive = sub_matches.get_flag("recursive");
+                    app.query_rdeps(pkg, recursive)?;
+                }
                 Some(("list-files", sub_matches)) => {
                     let pkg = sub_matches.get_one::<String>("pkg").unwrap();
                     let depth = sub_matches.get_one::<u32>("depth");
@@ -7,36 +7,146 @@ use serde::Deserialize;
 use serde::Serialize;
 use tracing::trace;
 
-pub trait Provide: scan_result::Scan + Fetch + std::fmt::Debug {}
+pub trait Provide: scan_result::Scan + Fetch + std::fmt::Debug {
+
+    /// Scan, reusing what has not changed since the previous scan.
+    /// Returns the pages to keep for the next scan, if the provider remembers any.
+    fn scan_cached(&self, arch_filter: Option<&[&str]>, _previous: Option<httpsearch::PageCache>) -> Result<(scan_result::ScanResult, Option<httpsearch::PageCache>)> {
+        Ok((self.scan(arch_filter)?, None))
+    }
+
+    /// Where a delta from version `from` to the package file `info` may be, if the provider does not list it.
+    /// By default a `.bpmdelta` file next to the package file.
+    fn delta_uri(&self, info: &scan_result::VersionInfo, pkg_name: &str, from: &str, to: &str) -> Option<String> {
+        let base = info.uri.strip_suffix(&info.filename)?;
+        Some(format!("{base}{}", package::make_delta_file_name(pkg_name, from, to, info.arch.as_deref())))
+    }
+
+    /// Upload a package file, optionally adding it to a channel
+    fn publish(&self, _path: &Utf8Path, _channel: Option<&str>, _token: Option<&str>) -> Result<()> {
+        anyhow::bail!("provider does not accept uploads")
+    }
+    /// Check that the source can be reached, cheaply. Used to time mirrors.
+    fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The start of every file uri from this source, used to find the same file on a mirror.
+    fn root_uri(&self) -> Option<String> {
+        self.file_uri("").ok()
+    }
+
+    /// The uris to try for a file, best first. A provider with mirrors has the file on each mirror.
+    fn file_uris(&self, uri: &str) -> Vec<String> {
+        vec![uri.to_string()]
+    }
+
+    /// A file from `uri` was downloaded, but was bad. The next download prefers another mirror.
+    fn report_bad_file(&self, _uri: &str) {}
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Provider {
     pub name: String,
-    pub uri: String,
+
+    /// the provider's uri, or the uris of its mirrors
+    pub uris: Vec<String>,
+
     pub cache_file: Utf8PathBuf,
     pub inner: Box<dyn Provide + Sync + Send>,
+
+    /// keys that packages from this provider must be signed with
+    pub trust: package::sign::TrustPolicy,
+
+    /// only accept the provider's package list from an index signed by one of the trusted keys
+    pub signed_index: bool,
+
+    /// bearer token for `bpm publish`
+    pub token: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ProviderFile {
     pub scan_time: chrono::DateTime<chrono::Utc>,
 
+    /// sequence number of the signed index the packages were read from
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_sequence: Option<u64>,
+
+    /// http pages from the last scan, for conditional requests
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_pages: Option<httpsearch::PageCache>,
+
+    /// when single packages were last rescanned, since the full scan at `scan_time`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub package_scan_times: std::collections::BTreeMap<String, chrono::DateTime<chrono::Utc>>,
+
     #[serde(flatten)]
     pub packages: scan_result::ScanResult,
 }
 
+impl ProviderFile {
+
+    /// when a package was last scanned for, by a full scan or on its own
+    pub fn package_scan_time(&self, pkg_name: &str) -> chrono::DateTime<chrono::Utc> {
+        self.package_scan_times.get(pkg_name).map_or(self.scan_time, |t| self.scan_time.max(*t))
+    }
+}
+
+/// the lock file held while a provider's cache files are written
+pub fn lock_path(cache_file: &Utf8Path) -> Utf8PathBuf {
+    match cache_file.extension() {
+        Some(e) => cache_file.with_extension(format!("{e}.lock")),
+        None => cache_file.with_extension(".lock"),
+    }
+}
+
 impl Provider {
-    pub fn new(name: String, uri: String, cache_dir: &Utf8Path) -> Result<Self> {
 
-        trace!(name, uri, "Provider::new");
+    /// A provider with more than one uri is a group of mirrors, see `source::mirrors`
+    pub fn new(name: String, uris: Vec<String>, cache_dir: &Utf8Path, http: &httpsearch::HttpOptions) -> Result<Self> {
+
+        trace!(name, ?uris, "Provider::new");
 
         let mut cache_file = join_path_utf8!(cache_dir, "provider", &name);
         cache_file.set_extension("json");
 
+        let inner: Box<dyn Provide + Sync + Send> = match uris.as_slice() {
+            [] => anyhow::bail!("provider '{}' has no uri", name),
+            [uri] => Self::source(uri, http)?,
+            _ => {
+                let mut mirrors = Vec::new();
+                for uri in &uris {
+                    mirrors.push((uri.clone(), Self::source(uri, http)?));
+                }
+                Box::new(source::mirrors::Mirrors::new(mirrors, cache_file.with_extension("mirrors.json"), lock_path(&cache_file)))
+            }
+        };
+
+        Ok(Self {
+            name,
+            uris,
+            cache_file,
+            inner,
+            trust: package::sign::TrustPolicy::default(),
+            signed_index: false,
+            token: None,
+        })
+    }
+
+    /// the source for one uri
+    fn source(uri: &str, http: &httpsearch::HttpOptions) -> Result<Box<dyn Provide + Sync + Send>> {
+
         const FILE_PRE: &str = "file://";
         const HTTP_PRE: &str = "http://";
         const HTTPS_PRE: &str = "https://";
+        const BPMD_PRE: &str = "bpmd://";
+        const HTTP_BPMD_PRE: &str = "http+bpmd://";
+        const HTTPS_BPMD_PRE: &str = "https+bpmd://";
 
         let inner: Box<dyn Provide + Sync + Send>;
 
@@ -44,17 +154,16 @@ impl Provider {
             let uri = uri.strip_prefix(FILE_PRE).unwrap();
             inner = Box::new(source::filesystem::FileSystem::new(uri));
         } else if uri.starts_with(HTTP_PRE) || uri.starts_with(HTTPS_PRE) {
-            inner = Box::new(source::http::Http::new(uri.clone()));
+            inner = Box::new(source::http::Http::new(uri.to_string(), http.clone()));
+        } else if let Some(rest) = uri.strip_prefix(BPMD_PRE).or_else(|| uri.strip_prefix(HTTP_BPMD_PRE)) {
+            inner = Box::new(source::bpmd::Bpmd::new(format!("{HTTP_PRE}{rest}"), http.clone()));
+        } else if let Some(rest) = uri.strip_prefix(HTTPS_BPMD_PRE) {
+            inner = Box::new(source::bpmd::Bpmd::new(format!("{HTTPS_PRE}{rest}"), http.clone()));
         } else {
             return Err(anyhow::anyhow!("invalid provider '{}'", uri))
         }
 
-        Ok(Self {
-            name,
-            uri,
-            cache_file,
-            inner,
-        })
+        Ok(inner)
     }
 
     pub fn load_file(&self) -> Result<ProviderFile> {
@@ -70,6 +179,14 @@ impl Provider {
     //    Ok(())
     //}
 
+    /// true if this provider has not been scanned for a package within `max_age`
+    pub fn is_stale(&self, pkg_name: &str, max_age: std::time::Duration, now: chrono::DateTime<chrono::Utc>) -> bool {
+        match self.load_file() {
+            Ok(data) => data.package_scan_time(pkg_name) + max_age < now,
+            Err(_) => true,
+        }
+    }
+
     pub fn as_provide(&self) -> &dyn Provide {
         &*self.inner
     }
@@ -221,7 +338,13 @@ mod test {
     struct FakeProvider {}
     impl Provide for FakeProvider {}
     impl Fetch for FakeProvider {
-        fn fetch(&self, _write: &mut dyn std::io::Write, _pkg: &package::PackageID, _url: &str) -> Result<u64> {
+        fn fetch(&self, _write: &mut dyn std::io::Write, _pkg: &package::PackageID, _url: &str, _size: Option<u64>) -> Result<u64> {
+            unreachable!()
+        }
+        fn file_uri(&self, _path: &str) -> Result<String> {
+            unreachable!()
+        }
+        fn read_file(&self, _path: &str) -> Result<Option<Vec<u8>>> {
             unreachable!()
         }
     }
@@ -229,14 +352,20 @@ mod test {
         fn scan(&self, _arch: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
             unreachable!()
         }
+        fn scan_package(&self, _pkg_name: &str, _arch: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+            unreachable!()
+        }
     }
     impl FakeProvider {
         fn make(name: &str) -> Provider {
             Provider {
                 name: name.into(),
-                uri: "".into(),
+                uris: vec![],
                 cache_file: "".into(),
                 inner: Box::new(FakeProvider{}),
+                trust: package::sign::TrustPolicy::default(),
+                signed_index: false,
+                token: None,
             }
         }
     }
@@ -377,4 +506,42 @@ mod test {
         assert_eq!(iter.next(), None);
 
     }
+
+    #[test]
+    fn mirrors() -> Result<()> {
+
+        let temp = tempfile::tempdir()?;
+        let temp = Utf8Path::from_path(temp.path()).unwrap();
+        let cache_dir = temp.join("cache");
+        let down = temp.join("down");
+        let up = temp.join("up");
+        std::fs::create_dir(&up)?;
+        std::fs::write(up.join("foo_1.0.0.bpm"), b"")?;
+
+        let uris = vec![format!("file://{down}/"), format!("file://{up}/")];
+        let provider = Provider::new("group".into(), uris.clone(), &cache_dir, &Default::default())?;
+
+        // the first mirror is not there, the scan fails over to the second
+        let (list, _) = provider.as_provide().scan_cached(None, None)?;
+        let uri = &list.packages["foo"].versions[&"1.0.0".into()][0].uri;
+        assert_eq!(*uri, format!("{up}/foo_1.0.0.bpm"));
+
+        // the same file on every mirror, the failed one last
+        let down_uri = format!("{down}/foo_1.0.0.bpm");
+        assert_eq!(provider.as_provide().file_uris(uri), [uri.clone(), down_uri.clone()]);
+        assert_eq!(provider.as_provide().file_uris("elsewhere"), ["elsewhere"]);
+
+        // a later run remembers which mirror failed
+        let provider = Provider::new("group".into(), uris.clone(), &cache_dir, &Default::default())?;
+        assert_eq!(provider.as_provide().file_uris(&down_uri), [uri.clone(), down_uri.clone()]);
+        assert!(cache_dir.join("provider/group.mirrors.json").exists());
+
+        // both failed now, the one that failed less is tried first
+        provider.as_provide().report_bad_file(uri);
+        assert_eq!(provider.as_provide().file_uris(uri), [uri.clone(), down_uri.clone()]);
+        provider.as_provide().report_bad_file(uri);
+        assert_eq!(provider.as_provide().file_uris(uri), [down_uri.clone(), uri.clone()]);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,118 @@
+use anyhow::Context;
+use crate::AResult;
+use crate::fetch::*;
+use crate::provider::Provide;
+use crate::source::http::Http;
+use package::PackageID;
+use std::fs::File;
+use std::io::Write;
+
+/// A bpmd server, scanned through its json api. Package files are downloaded over http.
+#[derive(Debug)]
+pub struct Bpmd {
+    url: String,
+    http: Http,
+}
+
+impl Bpmd {
+    pub fn new(mut url: String, options: httpsearch::HttpOptions) -> Self {
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        Bpmd {
+            http: Http::new(url.clone(), options),
+            url,
+        }
+    }
+
+    /// get and parse a json api endpoint, None if it does not exist
+    fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> AResult<Option<T>> {
+        let url = httpsearch::join_url(&self.url, path)?;
+        let Some(body) = httpsearch::get_optional(self.http.options(), &url)? else {
+            return Ok(None);
+        };
+        let value = serde_json::from_slice(&body).with_context(|| format!("invalid response from {url}"))?;
+        Ok(Some(value))
+    }
+
+    /// make download and delta urls absolute, and apply the arch filter
+    fn finish(&self, mut list: scan_result::ScanResult, arch_filter: Option<&[&str]>) -> AResult<scan_result::ScanResult> {
+        for vinfo in list.packages.values_mut()
+            .flat_map(|pinfo| pinfo.versions.values_mut())
+            .flat_map(|vlist| vlist.iter_mut())
+        {
+            vinfo.uri = httpsearch::join_url(&self.url, &vinfo.uri)?;
+            for delta in vinfo.deltas.values_mut() {
+                delta.uri = httpsearch::join_url(&self.url, &delta.uri)?;
+            }
+        }
+        if let Some(arch_filter) = arch_filter {
+            list.filter_arch(arch_filter);
+        }
+        Ok(list)
+    }
+}
+
+impl scan_result::Scan for Bpmd {
+    fn scan(&self, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+        let list = self.get("api/v1/packages")?.context("not a bpmd server")?;
+        self.finish(list, arch_filter)
+    }
+
+    fn scan_package(&self, pkg_name: &str, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+        let mut list = scan_result::ScanResult::default();
+        if let Some(pinfo) = self.get(&format!("api/v1/packages/{pkg_name}"))? {
+            list.packages.insert(pkg_name.to_string(), pinfo);
+        }
+        self.finish(list, arch_filter)
+    }
+}
+
+impl Fetch for Bpmd {
+    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str, size: Option<u64>) -> AResult<u64> {
+        self.http.fetch(write, pkg, url, size)
+    }
+
+    fn fetch_resume(&self, file: &mut File, pkg: &PackageID, url: &str, size: Option<u64>) -> AResult<u64> {
+        self.http.fetch_resume(file, pkg, url, size)
+    }
+
+    fn file_uri(&self, path: &str) -> AResult<String> {
+        self.http.file_uri(&format!("files/{path}"))
+    }
+
+    fn read_file(&self, path: &str) -> AResult<Option<Vec<u8>>> {
+        self.http.read_file(&format!("files/{path}"))
+    }
+}
+
+impl Provide for Bpmd {
+    /// the server makes any delta on request
+    fn delta_uri(&self, info: &scan_result::VersionInfo, pkg_name: &str, from: &str, to: &str) -> Option<String> {
+        let path = match &info.arch {
+            Some(arch) => format!("pkg/{pkg_name}/{from}/{to}.delta?arch={arch}"),
+            None => format!("pkg/{pkg_name}/{from}/{to}.delta"),
+        };
+        httpsearch::join_url(&self.url, &path).ok()
+    }
+
+    fn ping(&self) -> AResult<()> {
+        self.http.ping()
+    }
+
+    /// package files are under `files/`, deltas under `pkg/`
+    fn root_uri(&self) -> Option<String> {
+        httpsearch::join_url(&self.url, "").ok()
+    }
+
+    fn publish(&self, path: &camino::Utf8Path, channel: Option<&str>, token: Option<&str>) -> AResult<()> {
+        let filename = path.file_name().context("invalid package path")?;
+        let mut url = httpsearch::join_url(&self.url, &format!("api/v1/upload/{filename}"))?;
+        if let Some(channel) = channel {
+            url.push_str(&format!("?channel={channel}"));
+        }
+        let file = std::fs::File::open(path).with_context(|| format!("failed to open {path}"))?;
+        httpsearch::upload(self.http.options(), &url, token, file)?;
+        Ok(())
+    }
+}
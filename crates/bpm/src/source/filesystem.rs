@@ -30,11 +30,15 @@ impl scan_result::Scan for FileSystem {
     fn scan(&self, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
         fssearch::full_scan(&self.root, None, arch_filter)
     }
+
+    fn scan_package(&self, pkg_name: &str, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+        fssearch::full_scan(&self.root, Some(pkg_name), arch_filter)
+    }
 }
 
 impl Fetch for FileSystem {
 
-    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str) -> AResult<u64> {
+    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str, _size: Option<u64>) -> AResult<u64> {
 
         tracing::trace!(pkg=?pkg, url, "FileSystem::fetch()");
 
@@ -91,6 +95,27 @@ impl Fetch for FileSystem {
 
         ret
     }
+
+    fn file_uri(&self, path: &str) -> AResult<String> {
+        let path = self.root.join(path);
+        Ok(path.to_str().context("path is not utf8")?.to_string())
+    }
+
+    fn read_file(&self, path: &str) -> AResult<Option<Vec<u8>>> {
+        let path = self.root.join(path);
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
 }
 
-impl Provide for FileSystem {}
+impl Provide for FileSystem {
+    fn ping(&self) -> AResult<()> {
+        if !self.root.is_dir() {
+            anyhow::bail!("{} is not a directory", self.root.display());
+        }
+        Ok(())
+    }
+}
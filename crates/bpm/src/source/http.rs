@@ -5,35 +5,33 @@ use httpsearch;
 use indicatif::ProgressStyle;
 use package::PackageID;
 use serde_derive::Serialize;
+use std::fs::File;
 use std::io::Write;
 
 #[derive(Debug, Serialize)]
 pub struct Http {
     url: String,
+
+    #[serde(skip)]
+    options: httpsearch::HttpOptions,
 }
 
 impl Http {
-    pub fn new(url: String) -> Self {
-        Http { url }
+    pub fn new(url: String, options: httpsearch::HttpOptions) -> Self {
+        Http { url, options }
     }
-}
 
-impl scan_result::Scan for Http {
-    fn scan(&self, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
-        httpsearch::full_scan(None, &self.url, None, arch_filter)
+    pub fn options(&self) -> &httpsearch::HttpOptions {
+        &self.options
     }
-}
 
-impl Fetch for Http {
-    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str) -> AResult<u64> {
-
-        tracing::trace!(pkg=?pkg, url, "Http::fetch()");
+    /// a progress bar for downloading a package
+    fn start_bar(pkg: &PackageID, size: Option<u64>) -> bpmutil::status::Task {
 
         let msg = format!("{} {}", pkg.name, pkg.version);
-        let size = httpsearch::get_size(None, url).ok();
 
         let status_mgr = bpmutil::status::global();
-        let mut bar = status_mgr.add_task(Some("download"), Some(pkg.name.as_str()), size);
+        let bar = status_mgr.add_task(Some("download"), Some(pkg.name.as_str()), size);
         bar.set_message(msg);
         bar.set_prefix("✓");
         if size.is_some() {
@@ -47,19 +45,16 @@ impl Fetch for Http {
                 " {spinner:.green} downloading {msg:.cyan} {bytes_per_sec} {bytes} "
             ).unwrap());
         }
+        bar
+    }
 
-        let mut write = bar.wrap_write(write);
-
-        // [debug] slow
-        #[cfg(feature="dev-debug-slow")]
-        let mut write = bpmutil::SlowWriter::new(&mut write, std::time::Duration::from_millis(1));
-
-        let ret = httpsearch::download(None, url, &mut write);
+    fn finish_bar(mut bar: bpmutil::status::Task, ok: bool) {
 
+        let status_mgr = bpmutil::status::global();
         status_mgr.remove(&bar);
         status_mgr.insert(0, &mut bar);
 
-        if ret.is_ok() {
+        if ok {
             bar.set_style(ProgressStyle::with_template(
                 #[allow(clippy::literal_string_with_formatting_args)]
                 " {prefix:.green} downloaded  {msg:.cyan} {total_bytes} in {elapsed}").unwrap()
@@ -71,9 +66,68 @@ impl Fetch for Http {
             );
         }
         bar.finish();
+    }
+}
+
+impl scan_result::Scan for Http {
+    fn scan(&self, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+        httpsearch::full_scan(&self.options, &self.url, None, arch_filter)
+    }
+
+    fn scan_package(&self, pkg_name: &str, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+        httpsearch::full_scan(&self.options, &self.url, Some(pkg_name), arch_filter)
+    }
+}
+
+impl Fetch for Http {
+    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str, size: Option<u64>) -> AResult<u64> {
+
+        tracing::trace!(pkg=?pkg, url, "Http::fetch()");
+
+        let size = size.or_else(|| httpsearch::get_size(&self.options, url).ok());
+        let bar = Self::start_bar(pkg, size);
+
+        let mut write = bar.wrap_write(write);
+
+        // [debug] slow
+        #[cfg(feature="dev-debug-slow")]
+        let mut write = bpmutil::SlowWriter::new(&mut write, std::time::Duration::from_millis(1));
+
+        let ret = httpsearch::download(&self.options, url, &mut write);
+
+        Self::finish_bar(bar, ret.is_ok());
+        ret
+    }
+
+    fn fetch_resume(&self, file: &mut File, pkg: &PackageID, url: &str, size: Option<u64>) -> AResult<u64> {
+
+        tracing::trace!(pkg=?pkg, url, "Http::fetch_resume()");
+
+        let size = size.or_else(|| httpsearch::get_size(&self.options, url).ok());
+        let bar = Self::start_bar(pkg, size);
 
+        let ret = httpsearch::download_resume(&self.options, url, file, &|pos| bar.set_position(pos));
+
+        Self::finish_bar(bar, ret.is_ok());
         ret
     }
+
+    fn file_uri(&self, path: &str) -> AResult<String> {
+        httpsearch::join_url(&self.url, path)
+    }
+
+    fn read_file(&self, path: &str) -> AResult<Option<Vec<u8>>> {
+        httpsearch::get_optional(&self.options, &self.file_uri(path)?)
+    }
 }
 
-impl Provide for Http {}
+impl Provide for Http {
+    fn ping(&self) -> AResult<()> {
+        httpsearch::ping(&self.options, &self.url)
+    }
+
+    fn scan_cached(&self, arch_filter: Option<&[&str]>, previous: Option<httpsearch::PageCache>) -> AResult<(scan_result::ScanResult, Option<httpsearch::PageCache>)> {
+        let (list, pages) = httpsearch::full_scan_cached(&self.options, &self.url, None, arch_filter, previous.unwrap_or_default())?;
+        Ok((list, Some(pages).filter(|pages| !pages.is_empty())))
+    }
+}
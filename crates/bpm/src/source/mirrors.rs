@@ -0,0 +1,361 @@
+//! A provider served by several mirrors
+//!
+//! Scans and small reads go to the best mirror, and fail over to the next one on error.
+//! A download goes to the mirror its uri is on, `file_uris` gives the same file on every mirror for
+//! the caller to try in turn.
+//!
+//! Mirrors are ranked healthy first, then fastest first. A full scan pings every mirror to time it.
+//! A mirror is unhealthy after a failure, until it succeeds again or `RETRY_FAILED_AFTER` has passed.
+//! The health of each mirror is kept in `<provider>.mirrors.json`, next to the provider's cache file,
+//! so that later runs start with the best mirror.
+//! It is written under the provider's lock file, merging in what other processes saved. While the lock is
+//! held elsewhere, by another process or by this one scanning the provider, changes are saved later.
+
+use camino::Utf8PathBuf;
+use chrono::{DateTime, SubsecRound, Utc};
+use crate::AResult;
+use crate::fetch::Fetch;
+use crate::provider::Provide;
+use package::PackageID;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// an unhealthy mirror is tried first again this long after its last failure
+const RETRY_FAILED_AFTER: chrono::TimeDelta = chrono::TimeDelta::hours(1);
+
+pub type Source = Box<dyn Provide + Sync + Send>;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MirrorHealth {
+
+    /// round trip of the last ping, in milliseconds
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+
+    /// failures since the last success
+    #[serde(default)]
+    pub failures: u32,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+impl MirrorHealth {
+    fn healthy(&self, now: DateTime<Utc>) -> bool {
+        self.failures == 0 || self.last_failure.is_none_or(|t| now - t > RETRY_FAILED_AFTER)
+    }
+}
+
+#[derive(Debug)]
+struct Mirror {
+    uri: String,
+
+    /// see `Provide::root_uri`
+    root: Option<String>,
+
+    source: Source,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// mirror uri -> health
+    mirrors: BTreeMap<String, MirrorHealth>,
+
+    /// mirrors whose health changed since the health file was last written
+    unsaved: BTreeSet<String>,
+}
+
+#[derive(Debug)]
+pub struct Mirrors {
+    mirrors: Vec<Mirror>,
+
+    health_file: Utf8PathBuf,
+
+    /// the provider's lock file, held while writing the health file
+    lock_file: Utf8PathBuf,
+
+    health: Mutex<Health>,
+}
+
+impl Mirrors {
+
+    /// `mirrors` are (uri, source) in the configured order, `health_file` need not exist
+    pub fn new(mirrors: Vec<(String, Source)>, health_file: Utf8PathBuf, lock_file: Utf8PathBuf) -> Self {
+
+        let health = Health {
+            mirrors: read_health(&health_file),
+            unsaved: BTreeSet::new(),
+        };
+
+        let mirrors = mirrors.into_iter()
+            .map(|(uri, source)| Mirror { uri, root: source.root_uri(), source })
+            .collect();
+
+        Mirrors {
+            mirrors,
+            health_file,
+            lock_file,
+            health: Mutex::new(health),
+        }
+    }
+
+    /// indexes of the mirrors, best first: healthy ones by latency, then failed ones by fewest failures.
+    /// Ties keep the configured order.
+    fn ranked(&self) -> Vec<usize> {
+        let health = self.health.lock().unwrap();
+        let now = Utc::now();
+        let mut order : Vec<usize> = (0..self.mirrors.len()).collect();
+        order.sort_by_key(|&index| {
+            let health = health.mirrors.get(&self.mirrors[index].uri);
+            let healthy = health.is_none_or(|health| health.healthy(now));
+            let failures = if healthy { 0 } else { health.map_or(0, |health| health.failures) };
+            let latency = health.and_then(|health| health.latency_ms).unwrap_or(u64::MAX);
+            (!healthy, failures, latency)
+        });
+        order
+    }
+
+    /// the best mirror
+    fn best(&self) -> &Mirror {
+        &self.mirrors[self.ranked()[0]]
+    }
+
+    /// the mirror a uri is on, and the rest of the uri after that mirror's root
+    fn owner<'a>(&self, uri: &'a str) -> Option<(usize, &'a str)> {
+        self.mirrors.iter()
+            .enumerate()
+            .filter_map(|(index, mirror)| Some((index, uri.strip_prefix(mirror.root.as_deref()?)?)))
+            .min_by_key(|(_index, rest)| rest.len())
+    }
+
+    /// change the health of a mirror, and save the health file if that changed anything
+    fn update(&self, index: usize, f: impl FnOnce(&mut MirrorHealth)) {
+
+        let mut health = self.health.lock().unwrap();
+        let uri = &self.mirrors[index].uri;
+        let entry = health.mirrors.entry(uri.clone()).or_default();
+        let before = entry.clone();
+        f(entry);
+
+        if *entry != before {
+            health.unsaved.insert(uri.clone());
+            self.try_save(&mut health);
+        }
+    }
+
+    /// save the health file if there are unsaved changes, unless the provider is locked
+    fn try_save(&self, health: &mut Health) {
+        if health.unsaved.is_empty() {
+            return;
+        }
+        match self.save(health) {
+            Ok(true) => {},
+            Ok(false) => tracing::debug!("{} is locked, saving mirror health later", self.lock_file),
+            Err(e) => tracing::warn!("failed to save {}: {e:#}", self.health_file),
+        }
+    }
+
+    /// remember that a request to a mirror succeeded or failed
+    fn record(&self, index: usize, ok: bool) {
+        self.update(index, |health| {
+            if ok {
+                health.failures = 0;
+            } else {
+                health.failures += 1;
+                health.last_failure = Some(Utc::now().round_subsecs(0));
+            }
+        });
+    }
+
+    /// Write the changed mirrors into the health file, keeping what other processes saved for the rest.
+    /// False if the provider's lock file is held, nothing is written then.
+    fn save(&self, health: &mut Health) -> AResult<bool> {
+
+        use bpmutil::MyLockFns;
+
+        let lock = bpmutil::open_lockfile(&self.lock_file)?;
+        if !lock.my_try_lock()? {
+            return Ok(false);
+        }
+
+        let mut merged = read_health(&self.health_file);
+        for uri in std::mem::take(&mut health.unsaved) {
+            if let Some(entry) = health.mirrors.get(&uri) {
+                merged.insert(uri, entry.clone());
+            }
+        }
+
+        let dir = self.health_file.parent().unwrap_or(camino::Utf8Path::new("."));
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        temp.write_all(serde_json::to_string_pretty(&merged)?.as_bytes())?;
+        // readable like the provider's cache file, a temp file is private to its owner
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            temp.as_file().set_permissions(std::fs::Permissions::from_mode(0o644))?;
+        }
+        temp.persist(&self.health_file)?;
+
+        health.mirrors = merged;
+        Ok(true)
+    }
+
+    /// Ping every mirror at once, remembering how long each took.
+    /// Answering a ping does not make a mirror healthy again, it may still send bad files.
+    fn probe(&self) {
+
+        let results : Vec<AResult<Duration>> = std::thread::scope(|s| {
+            let threads : Vec<_> = self.mirrors.iter()
+                .map(|mirror| s.spawn(|| {
+                    let start = Instant::now();
+                    mirror.source.ping().map(|_| start.elapsed())
+                }))
+                .collect();
+            threads.into_iter()
+                .map(|thread| thread.join().unwrap_or_else(|_| Err(anyhow::anyhow!("ping panicked"))))
+                .collect()
+        });
+
+        for (index, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(latency) => {
+                    tracing::debug!("mirror {} answered in {latency:?}", self.mirrors[index].uri);
+                    self.update(index, |health| health.latency_ms = Some(latency.as_millis() as u64));
+                }
+                Err(e) => {
+                    tracing::warn!("mirror {} is unreachable: {e:#}", self.mirrors[index].uri);
+                    self.record(index, false);
+                }
+            }
+        }
+    }
+
+    /// run `f` on each mirror, best first, until it succeeds
+    fn failover<T>(&self, mut f: impl FnMut(&dyn Provide) -> AResult<T>) -> AResult<T> {
+        let mut last_err = None;
+        for index in self.ranked() {
+            let mirror = &self.mirrors[index];
+            match f(&*mirror.source) {
+                Ok(ret) => {
+                    self.record(index, true);
+                    return Ok(ret);
+                }
+                Err(e) => {
+                    tracing::warn!("mirror {} failed: {e:#}", mirror.uri);
+                    self.record(index, false);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("provider has no mirrors")))
+    }
+
+    /// run `f` on the mirror `uri` is on, or the best mirror if it is on none of them
+    fn on_owner<T>(&self, uri: &str, f: impl FnOnce(&dyn Provide) -> AResult<T>) -> AResult<T> {
+        let Some((index, _rest)) = self.owner(uri) else {
+            return f(&*self.best().source);
+        };
+        let ret = f(&*self.mirrors[index].source);
+        self.record(index, ret.is_ok());
+        ret
+    }
+}
+
+/// save any changes that could not be saved while the provider was locked
+impl Drop for Mirrors {
+    fn drop(&mut self) {
+        let mut health = self.health.lock().unwrap();
+        self.try_save(&mut health);
+    }
+}
+
+/// the mirror health saved in a file, empty if there is none
+fn read_health(path: &Utf8PathBuf) -> BTreeMap<String, MirrorHealth> {
+    std::fs::read_to_string(path).ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+impl scan_result::Scan for Mirrors {
+    fn scan(&self, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+        self.failover(|source| source.scan(arch_filter))
+    }
+
+    fn scan_package(&self, pkg_name: &str, arch_filter: Option<&[&str]>) -> anyhow::Result<scan_result::ScanResult> {
+        self.failover(|source| source.scan_package(pkg_name, arch_filter))
+    }
+}
+
+impl Fetch for Mirrors {
+    fn fetch(&self, write: &mut dyn Write, pkg: &PackageID, url: &str, size: Option<u64>) -> AResult<u64> {
+        self.on_owner(url, |source| source.fetch(write, pkg, url, size))
+    }
+
+    fn fetch_resume(&self, file: &mut File, pkg: &PackageID, url: &str, size: Option<u64>) -> AResult<u64> {
+        self.on_owner(url, |source| source.fetch_resume(file, pkg, url, size))
+    }
+
+    fn file_uri(&self, path: &str) -> AResult<String> {
+        self.best().source.file_uri(path)
+    }
+
+    fn read_file(&self, path: &str) -> AResult<Option<Vec<u8>>> {
+        self.failover(|source| source.read_file(path))
+    }
+}
+
+impl Provide for Mirrors {
+
+    /// pings every mirror first, so that the scan goes to the best one
+    fn scan_cached(&self, arch_filter: Option<&[&str]>, mut previous: Option<httpsearch::PageCache>) -> AResult<(scan_result::ScanResult, Option<httpsearch::PageCache>)> {
+        self.probe();
+        self.failover(|source| source.scan_cached(arch_filter, previous.take()))
+    }
+
+    fn delta_uri(&self, info: &scan_result::VersionInfo, pkg_name: &str, from: &str, to: &str) -> Option<String> {
+        let mirror = match self.owner(&info.uri) {
+            Some((index, _rest)) => &self.mirrors[index],
+            None => self.best(),
+        };
+        mirror.source.delta_uri(info, pkg_name, from, to)
+    }
+
+    /// uploads to the best mirror, the mirrors are expected to sync between themselves
+    fn publish(&self, path: &camino::Utf8Path, channel: Option<&str>, token: Option<&str>) -> AResult<()> {
+        self.best().source.publish(path, channel, token)
+    }
+
+    fn ping(&self) -> AResult<()> {
+        self.failover(|source| source.ping())
+    }
+
+    fn root_uri(&self) -> Option<String> {
+        None
+    }
+
+    fn file_uris(&self, uri: &str) -> Vec<String> {
+        let Some((owner, rest)) = self.owner(uri) else {
+            return vec![uri.to_string()];
+        };
+        self.ranked().into_iter()
+            .filter_map(|index| if index == owner {
+                Some(uri.to_string())
+            } else {
+                self.mirrors[index].root.as_ref().map(|root| format!("{root}{rest}"))
+            })
+            .collect()
+    }
+
+    fn report_bad_file(&self, uri: &str) {
+        if let Some((index, _rest)) = self.owner(uri) {
+            tracing::warn!("mirror {} sent a bad file {uri}", self.mirrors[index].uri);
+            self.record(index, false);
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod bpmd;
 pub mod filesystem;
 pub mod http;
+pub mod mirrors;
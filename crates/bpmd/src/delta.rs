@@ -0,0 +1,102 @@
+//! Package deltas
+//!
+//! A delta rebuilds a package file from an older version of the same package and arch, see `package::delta`.
+//! Deltas are made when first requested and kept in the cache dir as `<cache>/<target filename>/<from version>.delta`.
+
+use anyhow::Context;
+use scan_result::{DeltaInfo, ScanResult};
+use std::path::{Path, PathBuf};
+
+const DELTA_EXTENSION: &str = "delta";
+
+/// where the delta to `target_filename` from version `from` is cached
+pub fn cache_path(cache_dir: &Path, target_filename: &str, from: &str) -> PathBuf {
+    cache_dir.join(target_filename).join(format!("{from}.{DELTA_EXTENSION}"))
+}
+
+/// url of a delta, relative to the server root
+pub fn route(name: &str, from: &str, to: &str, arch: Option<&str>) -> String {
+    match arch {
+        Some(arch) => format!("pkg/{name}/{from}/{to}.{DELTA_EXTENSION}?arch={arch}"),
+        None => format!("pkg/{name}/{from}/{to}.{DELTA_EXTENSION}"),
+    }
+}
+
+fn mtime(path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// a cached delta is usable if it was made after both package files were last changed
+pub fn is_fresh(delta: &Path, old: &Path, new: &Path) -> bool {
+    match (mtime(delta), mtime(old), mtime(new)) {
+        (Some(delta), Some(old), Some(new)) => delta >= old && delta >= new,
+        _ => false,
+    }
+}
+
+/// make the delta from `old` to `new`, replacing `dest` once it is complete
+pub fn generate(old: &Path, new: &Path, dest: &Path) -> anyhow::Result<()> {
+
+    let dest_dir = dest.parent().context("invalid delta path")?;
+    std::fs::create_dir_all(dest_dir)?;
+
+    let file_name = |path: &Path| path.file_name().and_then(|name| name.to_str()).map(String::from).context("invalid package path");
+    let old_name = file_name(old)?;
+    let new_name = file_name(new)?;
+
+    let mut old_file = std::fs::File::open(old).with_context(|| format!("failed to open {}", old.display()))?;
+    let mut new_file = std::fs::File::open(new).with_context(|| format!("failed to open {}", new.display()))?;
+
+    let mut temp = tempfile::NamedTempFile::new_in(dest_dir)?;
+    package::delta::make_delta(&mut old_file, &old_name, &mut new_file, &new_name, &mut temp)?;
+    temp.persist(dest)?;
+
+    tracing::debug!("made delta {}", dest.display());
+    Ok(())
+}
+
+/// Add the cached deltas to a scan. `file_path` maps a package uri to its file.
+pub fn add_cached(cache_dir: &Path, packages: &mut ScanResult, file_path: impl Fn(&str) -> PathBuf) {
+
+    for (name, pinfo) in packages.packages.iter_mut() {
+
+        // (version, arch) -> package file, to find the source of each delta
+        let files: Vec<(String, Option<String>, PathBuf)> = pinfo.versions.iter()
+            .flat_map(|(v, vlist)| vlist.iter().map(move |vinfo| (v, vinfo)))
+            .map(|(v, vinfo)| (v.to_string(), vinfo.arch.clone(), file_path(&vinfo.uri)))
+            .collect();
+
+        for (to, vlist) in pinfo.versions.iter_mut() {
+            for vinfo in vlist.iter_mut() {
+
+                let Ok(entries) = std::fs::read_dir(cache_dir.join(&vinfo.filename)) else {
+                    continue;
+                };
+                let new = file_path(&vinfo.uri);
+
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path.extension().is_none_or(|ext| ext != DELTA_EXTENSION) {
+                        continue;
+                    }
+                    let Some(from) = path.file_stem().and_then(|s| s.to_str()) else {
+                        continue;
+                    };
+                    let Some((_, _, old)) = files.iter().find(|(v, arch, _)| v == from && *arch == vinfo.arch) else {
+                        continue;
+                    };
+                    if !is_fresh(&path, old, &new) {
+                        continue;
+                    }
+                    let Some(size) = entry.metadata().ok().map(|m| m.len()) else {
+                        continue;
+                    };
+                    vinfo.deltas.insert(from.to_string(), DeltaInfo {
+                        uri: route(name, from, to.as_str(), vinfo.arch.as_deref()),
+                        size,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -1,49 +1,337 @@
-#![allow(unused_imports)]
+//! bpmd -- serve a directory of packages to bpm
+//!
+//! The directory may use any layout fssearch understands.
+//! Package files are served under `/files/`, and package info is served as json:
+//!
+//! ```ignore
+//! GET /api/v1/packages                         all packages, same form as a bpm scan result
+//! GET /api/v1/packages/:name                   one package, its versions and kv
+//! GET /api/v1/packages/:name/versions          version strings, greatest first
+//! GET /api/v1/packages/:name/channels          channel name -> version strings
+//! GET /api/v1/packages/:name/kv                the package's kv.json
+//! GET /api/v1/packages/:name/:version          each arch of one version, with its download url
+//! PUT /api/v1/upload/:filename[?channel=name]   publish a package file, POST works too
+//! GET /pkg/:name/:from/:to.delta[?arch=arch]    a patch that rebuilds version `to` from version `from`
+//! ```
+//!
+//! Download urls (`uri`) are relative to the server root.
+//! Each version lists its file's size and blake3 hash. Hashes are kept until a file's size or mtime changes.
+//! Deltas are made on first request and cached, a version lists the cached deltas to it with their sizes.
+//!
+//! Uploads need a bearer token from the server config file, and are disabled without one:
+//!
+//! ```ignore
+//! [tokens]
+//! ci = "a long random string"
+//! ```
+//!
+//! An uploaded package is verified, then placed in `<dir>/<name>/`.
+//! With a channel, the version is added to that package's `channels.json`.
 
-use tower_http::services::ServeDir;
+use anyhow::Context;
+use axum::body::HttpBody;
+use axum::extract::{Path, Query, State};
+use axum::handler::HandlerWithoutStateExt;
+use axum::http::{header, HeaderMap};
 use axum::response::Html;
-use std::fmt::Write;
-use std::sync::Arc;
-use axum::extract::Path;
-use axum::extract::State;
 use axum::{
-    routing::{get, post},
+    routing::{get, put},
     http::StatusCode,
     response::IntoResponse,
     Json, Router,
 };
-use serde::{Deserialize, Serialize};
+use clap::arg;
+use scan_result::{PackageInfo, ScanResult, VersionInfo};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tower_http::services::ServeDir;
 
-use axum::handler::HandlerWithoutStateExt;
+mod delta;
+
+const FILES_ROUTE: &str = "files";
+const CHANNELS_FILE: &str = "channels.json";
+
+/// an http status and a message for the client
+type ApiError = (StatusCode, String);
+
+fn internal_error<E: std::fmt::Display>(e: E) -> ApiError {
+    tracing::error!("{e:#}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "internal error".into())
+}
 
-type PkgName = String;
-type Version = String;
-type VersionList = Vec<Version>;
-type PackageList = Vec<(PkgName, VersionList)>;
+/// the server config file
+#[derive(Default, Deserialize)]
+struct ServerConfig {
 
+    /// tokens allowed to upload, name -> token
+    #[serde(default)]
+    tokens: BTreeMap<String, String>,
+}
+
+impl ServerConfig {
+    fn from_path(path: &std::path::Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))?;
+        if let Some((name, _)) = config.tokens.iter().find(|(_, token)| token.trim().is_empty()) {
+            anyhow::bail!("token '{name}' is empty");
+        }
+        Ok(config)
+    }
+}
+
+// never log the tokens themselves
+impl std::fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerConfig").field("tokens", &self.tokens.keys().collect::<Vec<_>>()).finish()
+    }
+}
+
+/// compare without returning early, so the time taken does not reveal how much of a token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// the package dir and its last scan
 #[derive(Debug)]
-struct PkgList {
-    pkgs: PackageList,
+struct Repo {
+    dir: PathBuf,
+
+    /// rescan the dir when the last scan is older than this
+    max_age: Duration,
+
+    last_scan: Mutex<Option<(Instant, Arc<ScanResult>)>>,
+
+    config: ServerConfig,
+
+    /// held while a package is placed in the dir
+    publish_lock: Mutex<()>,
+
+    delta_cache: PathBuf,
+
+    /// held while a delta is made
+    delta_lock: tokio::sync::Mutex<()>,
+
+    /// package file -> (size, mtime, blake3 hash)
+    hashes: Mutex<HashMap<PathBuf, (u64, SystemTime, String)>>,
 }
 
-fn fake_package_list() -> PkgList {
-    PkgList {
-        pkgs: vec![
-            ("foo".into(), vec![
-             "1.0.0".into(),
-             "1.1.0".into(),
-             "1.2.1".into(),
-             "2.0.0".into(),
-            ]),
-            ("bar".into(), vec![
-             "0.1.0".into(),
-             "3.1.4".into(),
-            ]),
-        ],
+impl Repo {
+
+    /// the packages in the dir, rescanning if the last scan is too old
+    async fn packages(self: &Arc<Self>) -> Result<Arc<ScanResult>, StatusCode> {
+
+        if let Some((time, packages)) = &*self.last_scan.lock().unwrap()
+            && time.elapsed() < self.max_age
+        {
+            return Ok(Arc::clone(packages));
+        }
+
+        let repo = Arc::clone(self);
+        let packages = tokio::task::spawn_blocking(move || repo.scan())
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|e| {
+                tracing::error!("scan failed: {e:#}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        let packages = Arc::new(packages);
+        *self.last_scan.lock().unwrap() = Some((Instant::now(), Arc::clone(&packages)));
+        Ok(packages)
+    }
+
+    /// scan the dir, making uris into download urls relative to the server root
+    fn scan(&self) -> anyhow::Result<ScanResult> {
+
+        let mut packages = fssearch::full_scan(&self.dir, None, None)?;
+
+        for vinfo in packages.packages.values_mut()
+            .flat_map(|pinfo| pinfo.versions.values_mut())
+            .flat_map(|vlist| vlist.iter_mut())
+        {
+            let rel = std::path::Path::new(&vinfo.uri).strip_prefix(&self.dir)?;
+            let rel: Vec<_> = rel.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+            vinfo.uri = format!("{FILES_ROUTE}/{}", rel.join("/"));
+
+            if vinfo.blake3.is_none() {
+                match self.file_hash(&self.file_path(&vinfo.uri)) {
+                    Ok(hash) => vinfo.blake3 = Some(hash),
+                    Err(e) => tracing::warn!("failed to hash {}: {e:#}", vinfo.filename),
+                }
+            }
+        }
+
+        delta::add_cached(&self.delta_cache, &mut packages, |uri| self.file_path(uri));
+
+        tracing::debug!("scanned {}: {} packages", self.dir.display(), packages.package_count());
+        Ok(packages)
+    }
+
+    /// the blake3 hash of a file, hashed again only if its size or mtime changed
+    fn file_hash(&self, path: &std::path::Path) -> anyhow::Result<String> {
+
+        let md = std::fs::metadata(path)?;
+        let (size, mtime) = (md.len(), md.modified()?);
+
+        if let Some((cached_size, cached_mtime, hash)) = self.hashes.lock().unwrap().get(path)
+            && *cached_size == size
+            && *cached_mtime == mtime
+        {
+            return Ok(hash.clone());
+        }
+
+        let hash = bpmutil::blake3_hash_reader(std::fs::File::open(path)?)?;
+        self.hashes.lock().unwrap().insert(path.to_path_buf(), (size, mtime, hash.clone()));
+        Ok(hash)
+    }
+
+    /// the file behind a download url
+    fn file_path(&self, uri: &str) -> PathBuf {
+        let rel = uri.strip_prefix(FILES_ROUTE).unwrap_or(uri).trim_start_matches('/');
+        self.dir.join(rel)
+    }
+
+    /// the cached delta between two package files, made if it is missing or stale
+    async fn delta(self: &Arc<Self>, old: &VersionInfo, new: &VersionInfo, from: &str) -> Result<PathBuf, StatusCode> {
+
+        let path = delta::cache_path(&self.delta_cache, &new.filename, from);
+        let old = self.file_path(&old.uri);
+        let new = self.file_path(&new.uri);
+
+        if delta::is_fresh(&path, &old, &new) {
+            return Ok(path);
+        }
+
+        let _lock = self.delta_lock.lock().await;
+
+        // made while waiting for the lock
+        if delta::is_fresh(&path, &old, &new) {
+            return Ok(path);
+        }
+
+        let dest = path.clone();
+        tokio::task::spawn_blocking(move || delta::generate(&old, &new, &dest))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|e| {
+                tracing::error!("failed to make delta: {e:#}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        // the next scan lists the new delta
+        *self.last_scan.lock().unwrap() = None;
+
+        Ok(path)
+    }
+
+    async fn package(self: &Arc<Self>, name: &str) -> Result<PackageInfo, StatusCode> {
+        self.packages().await?.packages.get(name).cloned().ok_or(StatusCode::NOT_FOUND)
+    }
+
+    /// check the bearer token of a request, returning the token's name
+    fn authorize(&self, headers: &HeaderMap) -> Result<&str, ApiError> {
+
+        if self.config.tokens.is_empty() {
+            return Err((StatusCode::FORBIDDEN, "uploads are not enabled".into()));
+        }
+
+        let token = headers.get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or((StatusCode::UNAUTHORIZED, "missing bearer token".into()))?;
+
+        self.config.tokens.iter()
+            .find(|(_name, t)| constant_time_eq(t.as_bytes(), token.trim().as_bytes()))
+            .map(|(name, _t)| name.as_str())
+            .ok_or((StatusCode::UNAUTHORIZED, "invalid token".into()))
+    }
+
+    /// verify an uploaded package and move it into place
+    fn publish(&self, mut temp: tempfile::NamedTempFile, filename: &str, channel: Option<&str>) -> Result<VersionInfo, ApiError> {
+
+        let check = package::package_integrity_check_full(temp.as_file_mut(), Some(filename), None)
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("invalid package: {e:#}")))?;
+        if !check.good() {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, "package failed integrity check".into()));
+        }
+
+        let (name, version, arch) = package::split_parts(filename).expect("filename checked before upload");
+
+        let _lock = self.publish_lock.lock().unwrap();
+
+        // the version may already be anywhere in the dir, not only where it would be placed
+        let existing = fssearch::crawl(&self.dir, Some(name), None).map_err(internal_error)?;
+        let exists = existing.packages.get(name).is_some_and(|pinfo| {
+            pinfo.versions.iter()
+                .filter(|(v, _vlist)| v.as_str() == version)
+                .flat_map(|(_v, vlist)| vlist.iter())
+                .any(|vinfo| vinfo.arch.as_deref() == arch)
+        });
+        if exists {
+            return Err((StatusCode::CONFLICT, format!("{filename} is already published")));
+        }
+
+        let md = temp.as_file().metadata().map_err(internal_error)?;
+        let mtime = md.modified().map_err(internal_error)?;
+
+        let pkg_dir = self.dir.join(name);
+        let path = pkg_dir.join(filename);
+        std::fs::create_dir_all(&pkg_dir).map_err(internal_error)?;
+        temp.persist_noclobber(&path).map_err(|e| {
+            if e.error.kind() == std::io::ErrorKind::AlreadyExists {
+                (StatusCode::CONFLICT, format!("{filename} is already published"))
+            } else {
+                internal_error(e)
+            }
+        })?;
+
+        if let Some(channel) = channel {
+            add_to_channel(&pkg_dir, channel, version).map_err(internal_error)?;
+        }
+
+        *self.last_scan.lock().unwrap() = None;
+        self.hashes.lock().unwrap().insert(path, (md.len(), mtime, check.file_hash.clone()));
+
+        Ok(VersionInfo {
+            uri: format!("{FILES_ROUTE}/{name}/{filename}"),
+            filename: filename.to_string(),
+            channels: channel.into_iter().map(String::from).collect(),
+            arch: arch.map(String::from),
+            deltas: BTreeMap::new(),
+            size: Some(md.len()),
+            mtime: Some(mtime.into()),
+            blake3: Some(check.file_hash),
+        })
     }
 }
 
+/// add a version to a channel in a package's channels.json, replacing the file in one rename
+fn add_to_channel(pkg_dir: &std::path::Path, channel: &str, version: &str) -> anyhow::Result<()> {
+
+    let path = pkg_dir.join(CHANNELS_FILE);
+
+    let mut channels : BTreeMap<String, Vec<String>> = match std::fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).with_context(|| format!("invalid {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let versions = channels.entry(channel.to_string()).or_default();
+    if !versions.iter().any(|v| v == version) {
+        versions.push(version.to_string());
+    }
+
+    let mut temp = tempfile::NamedTempFile::new_in(pkg_dir)?;
+    serde_json::to_writer_pretty(&mut temp, &channels)?;
+    temp.persist(&path)?;
+    Ok(())
+}
+
 async fn handle404() -> (StatusCode, &'static str) {
     (StatusCode::NOT_FOUND, "Not Found")
 }
@@ -54,23 +342,61 @@ async fn main() {
     // initialize tracing
     tracing_subscriber::fmt::init();
 
-    let pkg_list = Arc::new(fake_package_list());
+    let matches = clap::Command::new("bpmd")
+        .about("Serve a directory of packages to bpm")
+        .arg(arg!(--dir <dir> "package directory").default_value("files"))
+        .arg(arg!(--bind <addr> "address to listen on").default_value("127.0.0.1:3006"))
+        .arg(arg!(--rescan <seconds> "rescan the package directory when the last scan is older than this")
+            .default_value("10")
+            .value_parser(clap::value_parser!(u64))
+        )
+        .arg(arg!(--config <file> "server config file, holding the tokens allowed to upload"))
+        .arg(arg!(--"delta-cache" <dir> "directory to keep deltas in").default_value("delta_cache"))
+        .get_matches();
+
+    let dir = PathBuf::from(matches.get_one::<String>("dir").unwrap());
+    let addr : SocketAddr = matches.get_one::<String>("bind").unwrap().parse().expect("invalid bind address");
+    let max_age = Duration::from_secs(*matches.get_one::<u64>("rescan").unwrap());
+    let delta_cache = PathBuf::from(matches.get_one::<String>("delta-cache").unwrap());
+
+    let config = match matches.get_one::<String>("config") {
+        Some(path) => ServerConfig::from_path(std::path::Path::new(path)).expect("failed to load config"),
+        None => ServerConfig::default(),
+    };
+    if config.tokens.is_empty() {
+        tracing::info!("no upload tokens configured, uploads are disabled");
+    }
+
+    let repo = Arc::new(Repo {
+        dir: dir.clone(),
+        max_age,
+        last_scan: Mutex::new(None),
+        config,
+        publish_lock: Mutex::new(()),
+        delta_cache,
+        delta_lock: tokio::sync::Mutex::new(()),
+        hashes: Mutex::new(HashMap::new()),
+    });
 
-    let serve_files = ServeDir::new("files").not_found_service(handle404.into_service());
+    let serve_files = ServeDir::new(&dir).not_found_service(handle404.into_service());
 
     let routes = Router::new()
         .route("/", get(root))
-        .route("/pkg", get(pkg))
-        .route("/pkg/:name", get(pkg_name))
-        .route("/pkg/:name/:version", get(pkg_name_version))
-        .with_state(pkg_list)
-        .nest_service("/files", serve_files)
+        .route("/api/v1/packages", get(packages))
+        .route("/api/v1/packages/:name", get(package))
+        .route("/api/v1/packages/:name/versions", get(package_versions))
+        .route("/api/v1/packages/:name/channels", get(package_channels))
+        .route("/api/v1/packages/:name/kv", get(package_kv))
+        .route("/api/v1/packages/:name/:version", get(package_version))
+        .route("/api/v1/upload/:filename", put(upload).post(upload))
+        .route("/pkg/:name/:from/:to", get(package_delta))
+        .with_state(repo)
+        .nest_service(&format!("/{FILES_ROUTE}"), serve_files)
     ;
 
     // run our app with hyper
     // `axum::Server` is a re-export of `hyper::Server`
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3006));
-    tracing::debug!("listening on {}", addr);
+    tracing::info!("serving {} on {}", dir.display(), addr);
     axum::Server::bind(&addr)
         .serve(routes.into_make_service())
         .await
@@ -80,40 +406,130 @@ async fn main() {
 // basic handler that responds with a static string
 #[tracing::instrument]
 async fn root() -> impl IntoResponse {
-    Html("<h1><a href=\"/pkg\">packages</a></h1>")
+    Html("<h1><a href=\"/api/v1/packages\">packages</a></h1>")
 }
 
 #[tracing::instrument]
-async fn pkg(state: State<Arc<PkgList>>) -> impl IntoResponse {
-    let mut body = String::new();
-    for (name, _versions) in &state.pkgs {
-        let _ = body.write_fmt(format_args!("<a href=\'/pkg/{name}\'>{name}</a><br>\n"));
-        //let _ = body.write_fmt(format_args!("<a href=\'{name}\'>{name}</a><br>\n"));
-    }
-    Html(body)
+async fn packages(State(repo): State<Arc<Repo>>) -> Result<Json<ScanResult>, StatusCode> {
+    let packages = repo.packages().await?;
+    Ok(Json(ScanResult::clone(&packages)))
+}
+
+#[tracing::instrument]
+async fn package(State(repo): State<Arc<Repo>>, Path(name): Path<String>) -> Result<Json<PackageInfo>, StatusCode> {
+    Ok(Json(repo.package(&name).await?))
 }
 
 #[tracing::instrument]
-async fn pkg_name(state: State<Arc<PkgList>>, Path(name): Path<String>) -> impl IntoResponse {
-    let mut body = String::new();
-    for (n, versions) in &state.pkgs {
-        if &name == n {
-            for v in versions {
-                let _ = body.write_fmt(format_args!("<a href=\'/files/{n}-{v}.bpm\'>{n}-{v}.bpm</a><br>"));
-                //let _ = body.write_fmt(format_args!("<a href=\'/pkg/{n}/{v}\'>info</a><br>\n"));
+async fn package_versions(State(repo): State<Arc<Repo>>, Path(name): Path<String>) -> Result<Json<Vec<String>>, StatusCode> {
+    let pinfo = repo.package(&name).await?;
+    Ok(Json(pinfo.versions.keys().rev().map(|v| v.to_string()).collect()))
+}
+
+#[tracing::instrument]
+async fn package_channels(State(repo): State<Arc<Repo>>, Path(name): Path<String>) -> Result<Json<BTreeMap<String, Vec<String>>>, StatusCode> {
+    let pinfo = repo.package(&name).await?;
+    let mut channels : BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (version, vlist) in pinfo.versions.iter().rev() {
+        for channel in vlist.iter().flat_map(|vinfo| vinfo.channels.iter()) {
+            let versions = channels.entry(channel.clone()).or_default();
+            if !versions.iter().any(|v| v == version.as_str()) {
+                versions.push(version.to_string());
             }
         }
     }
-    Html(body)
+    Ok(Json(channels))
+}
+
+#[tracing::instrument]
+async fn package_kv(State(repo): State<Arc<Repo>>, Path(name): Path<String>) -> Result<Json<scan_result::Kv>, StatusCode> {
+    let pinfo = repo.package(&name).await?;
+    Ok(Json(pinfo.kv.unwrap_or_default()))
+}
+
+#[tracing::instrument]
+async fn package_version(State(repo): State<Arc<Repo>>, Path((name, version)): Path<(String, String)>) -> Result<Json<Vec<VersionInfo>>, StatusCode> {
+    let pinfo = repo.package(&name).await?;
+    let vlist = pinfo.versions.into_iter()
+        .find(|(v, _vlist)| v.as_str() == version)
+        .map(|(_v, vlist)| vlist)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(vlist))
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadParams {
+    channel: Option<String>,
+}
+
+#[tracing::instrument(skip(repo, headers, request))]
+async fn upload(
+    State(repo): State<Arc<Repo>>,
+    Path(filename): Path<String>,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
+    request: axum::http::Request<axum::body::Body>,
+) -> Result<(StatusCode, Json<VersionInfo>), ApiError> {
+
+    let user = repo.authorize(&headers)?.to_string();
+
+    if !package::is_packagefile_name(&filename) || package::split_parts(&filename).is_none() {
+        return Err((StatusCode::BAD_REQUEST, format!("invalid package file name '{filename}'")));
+    }
+    if let Some(channel) = &params.channel && !package::is_valid_package_name(channel) {
+        return Err((StatusCode::BAD_REQUEST, format!("invalid channel name '{channel}'")));
+    }
+
+    // receive into a temp file in the dir, it is moved into place once verified
+    let mut temp = tempfile::NamedTempFile::new_in(&repo.dir).map_err(internal_error)?;
+    let mut body = request.into_body();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, format!("upload failed: {e}")))?;
+        temp.write_all(&chunk).map_err(internal_error)?;
+    }
+
+    let vinfo = {
+        let repo = Arc::clone(&repo);
+        let filename = filename.clone();
+        let channel = params.channel.clone();
+        tokio::task::spawn_blocking(move || repo.publish(temp, &filename, channel.as_deref()))
+            .await
+            .map_err(internal_error)??
+    };
+
+    tracing::info!(user, channel = params.channel, "published {filename}");
+    Ok((StatusCode::CREATED, Json(vinfo)))
 }
 
 #[derive(Debug, Deserialize)]
-struct PkgVersionPath {
-    name: String,
-    version: String,
+struct DeltaParams {
+    arch: Option<String>,
 }
 
 #[tracing::instrument]
-async fn pkg_name_version(state: State<Arc<PkgList>>, Path(p): Path<PkgVersionPath>) -> impl IntoResponse {
-    format!("Package: {}\nVersion: {}\n", p.name, p.version)
+async fn package_delta(
+    State(repo): State<Arc<Repo>>,
+    Path((name, from, to)): Path<(String, String, String)>,
+    Query(params): Query<DeltaParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+
+    let to = to.strip_suffix(".delta").ok_or(StatusCode::NOT_FOUND)?;
+    if from == to {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let pinfo = repo.package(&name).await?;
+    let find = |version: &str| {
+        pinfo.versions.iter()
+            .filter(|(v, _vlist)| v.as_str() == version)
+            .flat_map(|(_v, vlist)| vlist.iter())
+            .find(|vinfo| vinfo.arch == params.arch)
+            .ok_or(StatusCode::NOT_FOUND)
+    };
+    let old = find(&from)?;
+    let new = find(to)?;
+
+    let path = repo.delta(old, new, &from).await?;
+    let data = tokio::fs::read(&path).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], data))
 }
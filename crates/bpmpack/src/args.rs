@@ -25,11 +25,65 @@ pub fn build_cli(cmd: clap::Command) -> clap::Command {
                 )
                 .arg(arg!(--arch <arch> "Architecture of this package"))
                 .arg(arg!(--semver "Require the version to be a valid semver [see http://semver.org]"))
+                .arg(sign_key_arg())
         )
         .subcommand(
             Command::new("verify")
-                .about("Verify package integrity")
+                .about("Verify package integrity and signature")
                 .arg(arg!(<pkgfile> "package file to inspect"))
+                .arg(arg!(--trust <key> "Require the package to be signed by this public key")
+                    .action(ArgAction::Append)
+                    .required(false)
+                )
+        )
+        .subcommand(
+            Command::new("sign")
+                .about("Sign a package file in place, replacing any existing signature")
+                .arg(arg!(<pkgfile> "package file to sign"))
+                .arg(arg!(--key <keyfile> "Secret key file")
+                    .required(true)
+                    .value_hint(clap::ValueHint::FilePath)
+                )
+        )
+        .subcommand(
+            Command::new("keygen")
+                .about("Create a new ed25519 signing key and print its public key")
+                .arg(arg!(<keyfile> "Where to write the secret key"))
+        )
+        .subcommand(
+            Command::new("delta")
+                .about("Make a delta that rebuilds a package file from an older version of it")
+                .arg(arg!(<old> "older package file"))
+                .arg(arg!(<new> "newer package file"))
+                .arg(arg!(-o --output <file> "Where to write the delta, default is <name>_<from>_to_<to>.bpmdelta next to the newer package")
+                    .value_hint(clap::ValueHint::FilePath)
+                )
+        )
+        .subcommand(
+            Command::new("apply-delta")
+                .about("Rebuild a package file from an older version of it and a delta")
+                .arg(arg!(<old> "older package file, the one the delta was made from"))
+                .arg(arg!(<delta> "delta file"))
+                .arg(arg!(-o --output <file> "Where to write the package file, default is its name in the current directory")
+                    .value_hint(clap::ValueHint::FilePath)
+                )
+        )
+        .subcommand(
+            Command::new("repo")
+                .about("Manage a repository, a directory of packages served by a provider")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("index")
+                        .about("Write an index.json listing the packages in a repository, replacing any existing index")
+                        .arg(arg!(<dir> "repository directory"))
+                        .arg(arg!(--"sign-key" <keyfile> "Sign the index with the secret key in this file")
+                            .required(false)
+                            .requires("expires")
+                            .value_hint(clap::ValueHint::FilePath)
+                        )
+                        .arg(arg!(--expires <duration> "Clients reject the index after this long, ex: \"7d\". Required with --sign-key, and for http scans to use the index"))
+                        .arg(arg!(--zstd "Compress the index with zstd"))
+                )
         )
         .subcommand(
             Command::new("list-files")
@@ -106,6 +160,7 @@ pub fn build_cli(cmd: clap::Command) -> clap::Command {
              // negative values are not actually accepted, but this gives a better error message
              .allow_negative_numbers(true)
         )
+        .arg(sign_key_arg())
         .arg(arg!(--description <description> "Provide a brief description of the package"))
         .arg(arg!(--kv <keyvalue> "Key-Value")
              .value_name("key=value")
@@ -118,6 +173,12 @@ pub fn build_cli(cmd: clap::Command) -> clap::Command {
                 .long("depend")
                 .action(clap::ArgAction::Append)
                 .value_name("pkg[@version]")
-                .help("Add a dependency")
+                .help("Add a dependency. The version may be a range such as \"^1.4\" or \">=1.2, <2\"")
         )
 }
+
+fn sign_key_arg() -> Arg {
+    arg!(--"sign-key" <keyfile> "Sign the package with the secret key in this file")
+        .required(false)
+        .value_hint(clap::ValueHint::FilePath)
+}
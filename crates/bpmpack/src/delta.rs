@@ -0,0 +1,83 @@
+//! Package deltas, see `package::delta`
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::fs::File;
+use std::io::Seek;
+
+/// the file name of a package file path, checked to be a package file name
+fn package_file_name(path: &Utf8Path) -> Result<&str> {
+    let name = path.file_name().with_context(|| format!("{path} has no file name"))?;
+    if !package::is_packagefile_name(name) {
+        anyhow::bail!("{name} is not a package file name");
+    }
+    Ok(name)
+}
+
+/// write to a temp file next to `path`, moving it into place once `f` succeeds
+fn write_file<T, F: FnOnce(&mut File) -> Result<T>>(path: &Utf8Path, f: F) -> Result<T> {
+    let dir = path.parent().filter(|dir| !dir.as_str().is_empty()).unwrap_or(Utf8Path::new("."));
+    let mut temp = tempfile::NamedTempFile::new_in(dir).with_context(|| format!("failed to create a file in {dir}"))?;
+    let ret = f(temp.as_file_mut())?;
+    temp.persist(path).with_context(|| format!("failed to write {path}"))?;
+    Ok(ret)
+}
+
+/// `bpmpack delta`
+/// Write a delta that rebuilds `new` from `old`.
+/// By default it is written next to `new`, named so that bpm finds it there.
+pub fn subcmd_delta(old: &Utf8Path, new: &Utf8Path, output: Option<&Utf8Path>) -> Result<()> {
+
+    let old_name = package_file_name(old)?;
+    let new_name = package_file_name(new)?;
+
+    let (name, from, old_arch) = package::split_parts(old_name).context("invalid package file name")?;
+    let (new_pkg_name, to, new_arch) = package::split_parts(new_name).context("invalid package file name")?;
+    if name != new_pkg_name || old_arch != new_arch {
+        anyhow::bail!("{old_name} and {new_name} are not versions of the same package");
+    }
+
+    let output = match output {
+        Some(path) => path.to_path_buf(),
+        None => new.with_file_name(package::make_delta_file_name(name, from, to, new_arch)),
+    };
+
+    let mut old_file = File::open(old).with_context(|| format!("failed to open {old}"))?;
+    let mut new_file = File::open(new).with_context(|| format!("failed to open {new}"))?;
+
+    write_file(&output, |out| package::delta::make_delta(&mut old_file, old_name, &mut new_file, new_name, out))?;
+
+    let delta_size = output.metadata()?.len();
+    let new_size = new_file.metadata()?.len();
+    println!("wrote {output}");
+    println!("delta size:   {:-10} ({})", humansize::format_size(delta_size, humansize::BINARY), delta_size);
+    println!("package size: {:-10} ({})", humansize::format_size(new_size, humansize::BINARY), new_size);
+    Ok(())
+}
+
+/// `bpmpack apply-delta`
+/// Rebuild a package file from `old` and a delta made from it.
+/// By default it is written to the current directory, with the name of the package the delta was made to.
+pub fn subcmd_apply_delta(old: &Utf8Path, delta: &Utf8Path, output: Option<&Utf8Path>) -> Result<()> {
+
+    let mut delta_file = File::open(delta).with_context(|| format!("failed to open {delta}"))?;
+    let header = package::delta::read_header(&mut delta_file).with_context(|| format!("failed to read {delta}"))?;
+
+    let output = match output {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let name = Utf8Path::new(&header.target).file_name().context("invalid delta target name")?;
+            Utf8PathBuf::from(name)
+        }
+    };
+
+    let mut old_file = File::open(old).with_context(|| format!("failed to open {old}"))?;
+
+    // apply_delta reads the header again
+    delta_file.rewind()?;
+    write_file(&output, |out| package::delta::apply_delta(&mut old_file, &mut delta_file, out))
+        .with_context(|| format!("failed to apply {delta} to {old}"))?;
+
+    println!("wrote {output}");
+    Ok(())
+}
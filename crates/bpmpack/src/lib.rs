@@ -40,6 +40,8 @@ use std::time::Duration;
 use version::Version;
 
 pub mod args;
+pub mod delta;
+pub mod repo;
 
 const DEFAULT_ZSTD_LEVEL : i32 = 15;
 const PKG_FORMAT_VERSION : &str = "1.0.0";
@@ -320,13 +322,13 @@ fn file_discovery(paths: Vec<String>) -> Result<FileListing> {
     for entry in walker.build() {
         let entry = entry.unwrap();
         let full_path = PathBuf::from(entry.path());
-        let full_path = Utf8PathBuf::from_path_buf(full_path);
-        if full_path.is_err() {
-            let _ = write!(std::io::stderr(), "warning: non-utf8 path, file not added. {}", full_path.unwrap_err().display());
-            continue;
-        }
-
-        let full_path = full_path.unwrap();
+        let full_path = match Utf8PathBuf::from_path_buf(full_path) {
+            Ok(full_path) => full_path,
+            Err(full_path) => {
+                let _ = write!(std::io::stderr(), "warning: non-utf8 path, file not added. {}", full_path.display());
+                continue;
+            }
+        };
 
         let pkg_path = full_path.strip_prefix(root_parent).unwrap().to_path_buf();
         let ent_file_type = entry.file_type().expect("can't determine file type");
@@ -341,12 +343,13 @@ fn file_discovery(paths: Vec<String>) -> Result<FileListing> {
                 FileType::File
             } else if ent_file_type.is_symlink() {
                 let link_to = std::fs::read_link(&full_path).unwrap();
-                let link_to = Utf8PathBuf::from_path_buf(link_to);
-                if link_to.is_err() {
-                    let _ = write!(std::io::stderr(), "warning: non-utf8 symlink, file not added. {} -> {}", full_path, link_to.unwrap_err().display());
-                    continue;
-                }
-                let link_to = link_to.unwrap();
+                let link_to = match Utf8PathBuf::from_path_buf(link_to) {
+                    Ok(link_to) => link_to,
+                    Err(link_to) => {
+                        let _ = write!(std::io::stderr(), "warning: non-utf8 symlink, file not added. {} -> {}", full_path, link_to.display());
+                        continue;
+                    }
+                };
                 FileType::Link(link_to)
             } else {
                 unreachable!("path is not a dir, file, or link")
@@ -454,8 +457,8 @@ pub fn subcmd_set_version(matches: &clap::ArgMatches) -> Result<()> {
 
     if let Some((name, _version, arch)) = package::split_parts(package_filename) {
 
-        if arch.is_some() && arch != package_arch {
-            println!("warning: arch changing from '{}' to '{}'.", arch.unwrap(), package_arch.unwrap_or(""));
+        if let Some(old_arch) = arch && arch != package_arch {
+            println!("warning: arch changing from '{}' to '{}'.", old_arch, package_arch.unwrap_or(""));
         }
 
         let package_arch = package_arch.or(arch);
@@ -470,52 +473,150 @@ pub fn subcmd_set_version(matches: &clap::ArgMatches) -> Result<()> {
         let new_filename = package::make_packagefile_name(name, &package_version, package_arch);
         let new_filepath = package_filepath.with_file_name(new_filename);
 
-        let out_file = std::fs::File::create(&new_filepath).context("failed to open file for writing")?;
-        let mut out_tar = tar::Builder::new(out_file);
+        let sign_key = matches.get_one::<String>("sign-key")
+            .map(|path| package::sign::read_secret_key(Utf8Path::new(path)))
+            .transpose()?;
+
+        let set_version = |md: &mut package::MetaData| {
+            // update the version, arch, and create a new uuid
+            md.version = package_version.to_string();
+            md.arch = package_arch.map(String::from);
+            md.uuid = uuid::Uuid::new_v4().to_string();
+        };
 
-        let in_file = std::fs::File::open(&package_filepath).context("failed to open file for reading")?;
-        let mut tar = tar::Archive::new(in_file);
+        // the metadata changes, so any existing signature no longer applies
+        let signed = rewrite_package(&package_filepath, &new_filepath, Some(&set_version), sign_key.as_ref())?;
+        if !signed && package::sign::read_signature(&mut File::open(&package_filepath)?)?.is_some() {
+            println!("warning: the signature was removed, pass --sign-key to sign the new package");
+        }
 
-        for mut entry in tar.entries().context("failed to read tar")?.flatten() {
-            let path = entry.path()?.into_owned();
+        println!("Package created: {}", new_filepath);
+    }
 
-            let path_str = path.to_str().context("failed to stringify path")?;
-            if path_str == package::META_FILE_NAME {
+    Ok(())
+}
 
-                // extract the MetaData struct
-                let mut md = package::MetaData::from_reader(&mut entry).context("failed to extra metadata")?;
+/// append a `signature.json` entry to a package tar
+fn append_signature<W: Write>(tar: &mut tar::Builder<W>, signature: &package::sign::Signature) -> Result<()> {
+    let data = signature.to_vec()?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o644);
+    header.set_mtime(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+    tar.append_data(&mut header, package::sign::SIGNATURE_FILE_NAME, data.as_slice()).context("appending signature file")?;
+    Ok(())
+}
 
-                // update the version, arch, and create a new uuid
-                md.version = package_version.to_string();
-                md.arch = package_arch.map(String::from);
-                md.uuid = uuid::Uuid::new_v4().to_string();
+/// Copy a package file to `out_path`, optionally changing the metadata, and dropping any existing signature.
+/// With a `sign_key` the new package is signed.
+///
+/// returns true if the new package is signed
+fn rewrite_package(in_path: &Utf8Path, out_path: &Utf8Path, update_metadata: Option<&dyn Fn(&mut package::MetaData)>, sign_key: Option<&package::sign::SigningKey>) -> Result<bool> {
+
+    let out_file = std::fs::File::create(out_path).context("failed to open file for writing")?;
+    let mut out_tar = tar::Builder::new(out_file);
+
+    let in_file = std::fs::File::open(in_path).context("failed to open file for reading")?;
+    let mut tar = tar::Archive::new(in_file);
+
+    let mut signed = false;
+
+    for mut entry in tar.entries().context("failed to read tar")?.flatten() {
+        let path = entry.path()?.into_owned();
+
+        let path_str = path.to_str().context("failed to stringify path")?;
+        if path_str == package::sign::SIGNATURE_FILE_NAME {
+            continue;
+        } else if path_str == package::META_FILE_NAME {
+
+            let mut md_file = Vec::new();
+            entry.read_to_end(&mut md_file).context("failed to read metadata")?;
+
+            if let Some(update_metadata) = update_metadata {
+
+                // extract the MetaData struct
+                let mut md = package::MetaData::from_reader(&mut md_file.as_slice()).context("failed to extra metadata")?;
+                update_metadata(&mut md);
 
                 // re-serialize the struct and write that to the tar
-                let mut md_file = Vec::new();
+                md_file.clear();
                 md.to_writer(&mut md_file).context("failed to serialize metadata")?;
+            }
 
-                let mut header = entry.header().clone();
-                header.set_size(md_file.len() as u64);
+            let mut header = entry.header().clone();
+            header.set_size(md_file.len() as u64);
 
-                out_tar.append_data(&mut header, path_str, std::io::Cursor::new(&md_file)).context("failed to write to tar")?;
-            } else {
-                let header = entry.header().clone();
-                out_tar.append(&header, &mut entry).context("failed to write to tar tar")?;
+            out_tar.append_data(&mut header, path_str, std::io::Cursor::new(&md_file)).context("failed to write to tar")?;
+
+            if let Some(key) = sign_key {
+                append_signature(&mut out_tar, &package::sign::sign(key, &md_file))?;
+                signed = true;
             }
+        } else {
+            let header = entry.header().clone();
+            out_tar.append(&header, &mut entry).context("failed to write to tar tar")?;
         }
+    }
 
-        // finish the output file
-        let mut out_file = out_tar.into_inner()?;
-        out_file.flush()?;
-        drop(out_file);
+    // finish the output file
+    let mut out_file = out_tar.into_inner()?;
+    out_file.flush()?;
+    drop(out_file);
 
-        println!("Package created: {}", new_filepath);
+    Ok(signed)
+}
+
+/// `bpmpack keygen`
+/// create a new signing key, print the public key
+pub fn subcmd_keygen(path: &Utf8Path) -> Result<()> {
+
+    if path.try_exists().unwrap_or(true) {
+        anyhow::bail!("refusing to overwrite {path}");
+    }
+
+    let key = package::sign::generate_key();
+
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).open(path).context("failed to create key file")?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600)).context("failed to set key file permissions")?;
+    }
+    writeln!(file, "{}", package::sign::secret_key_string(&key))?;
+
+    println!("secret key written to {path}");
+    println!("public key: {}", package::sign::public_key_string(&key.verifying_key()));
+    Ok(())
+}
+
+/// `bpmpack sign`
+/// sign an existing package file in place, replacing any existing signature
+pub fn subcmd_sign(path: &Utf8Path, key_path: &Utf8Path) -> Result<()> {
+
+    let key = package::sign::read_secret_key(key_path)?;
+
+    let filename = path.file_name().context("path has no filename")?;
+    let check = package::package_integrity_check_full(&mut File::open(path).context("failed to open package file")?, Some(filename), None)?;
+    if !check.good() {
+        anyhow::bail!("package corrupt, refusing to sign");
+    }
+
+    let temp_path = path.with_file_name(format!(".{filename}.sign"));
+    if let Err(e) = rewrite_package(path, &temp_path, None, Some(&key)) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
     }
+    std::fs::rename(&temp_path, path).context("failed to replace package file")?;
 
+    println!("signed {} with key {}", path, package::sign::public_key_string(&key.verifying_key()));
     Ok(())
 }
 
-pub fn subcmd_verify(path: &Path) -> Result<()> {
+/// `bpmpack verify`
+/// check package integrity, and the signature if there is one.
+/// With `trusted` keys, the package must be signed by one of them.
+pub fn subcmd_verify(path: &Path, trusted: &[String]) -> Result<()> {
 
     if let Ok(true) = std::fs::exists(path) {
     } else {
@@ -530,6 +631,22 @@ pub fn subcmd_verify(path: &Path) -> Result<()> {
     if !check.good() {
         anyhow::bail!("package corrupt");
     }
+
+    match package::sign::read_signature(&mut file)? {
+        Some(signature) => {
+            let metadata_raw = package::read_metadata(&mut file)?;
+            signature.verify(&metadata_raw).context("package signature is invalid")?;
+            println!("signed by {}", signature.key);
+        }
+        None => println!("not signed"),
+    }
+
+    let policy = package::sign::TrustPolicy {
+        keys: trusted.iter().map(|key| package::sign::parse_public_key(key)).collect::<Result<_>>()?,
+        allow_unsigned: false,
+    };
+    policy.check(&mut file)?;
+
     Ok(())
 }
 
@@ -605,7 +722,7 @@ pub fn subcmd_test_ignore(matches: &clap::ArgMatches) -> Result<()> {
                     if file.is_symlink()   { "s" } else { "" },
                     if file.modes.volatile { "v" } else { "" },
                     if file.modes.weak     { "w" } else { "" },
-                    &file.pkg_path,
+                    file.pkg_path,
                     w_reason.as_deref().unwrap_or("")
                 )?;
             }
@@ -665,6 +782,10 @@ pub fn make_package(matches: &clap::ArgMatches) -> Result<()> {
         })
         .collect();
 
+    let sign_key = matches.get_one::<String>("sign-key")
+        .map(|path| package::sign::read_secret_key(Utf8Path::new(path)))
+        .transpose()?;
+
     let output_dir = matches.get_one::<String>("output-dir").map_or(cwd(), PathBuf::from);
     if !output_dir.try_exists().ok().unwrap_or(false) {
         anyhow::bail!("output-dir path does not exist");
@@ -799,6 +920,11 @@ pub fn make_package(matches: &clap::ArgMatches) -> Result<()> {
         .with_uuid(uuid::Uuid::new_v4().to_string());
 
     // insert dependencies
+    for (name, version) in &deps {
+        if let Some(v) = version && let Err(e) = version::VersionReq::parse(v) {
+            anyhow::bail!("invalid version for dependency '{name}': {e}");
+        }
+    }
     for pair in &deps {
         meta.add_dependency(package::DependencyID{
             name: pair.0.clone(),
@@ -880,7 +1006,7 @@ pub fn make_package(matches: &clap::ArgMatches) -> Result<()> {
                     if entry.is_symlink()   { "s" } else { "" },
                     if entry.modes.volatile { "v" } else { "" },
                     if entry.modes.weak     { "w" } else { "" },
-                    &entry.pkg_path)
+                    entry.pkg_path)
             });
         }
 
@@ -961,6 +1087,18 @@ pub fn make_package(matches: &clap::ArgMatches) -> Result<()> {
     //package_tar.append_data(&mut header, package::META_FILE_NAME, &mut metafile)?;
     package_tar.append_data(&mut header, package::META_FILE_NAME, &mut finish_bar.wrap_read(&mut metafile))?;
 
+    // signature file, next to the metadata
+    let signer = if let Some(key) = &sign_key {
+        let mut metadata_raw = Vec::new();
+        metafile.seek(std::io::SeekFrom::Start(0)).context("seeking in metadata")?;
+        metafile.read_to_end(&mut metadata_raw).context("reading metadata")?;
+        let signature = package::sign::sign(key, &metadata_raw);
+        append_signature(&mut package_tar, &signature)?;
+        Some(signature.key)
+    } else {
+        None
+    };
+
     // data file
     let mut header = tar::Header::new_gnu();
     header.set_metadata_in_mode(
@@ -1002,6 +1140,9 @@ pub fn make_package(matches: &clap::ArgMatches) -> Result<()> {
     println!("data size compressed:   {:-10} ({})", humansize::format_size(compressed_size, humansize::BINARY), compressed_size);
     println!("data compression:       {:.4}, {:0.3} %", uncompressed_size as f64 / compressed_size as f64, 100.0 * compressed_size as f64 / uncompressed_size as f64);
     println!("data hash [blake3]:     {}", data_tar_hash);
+    if let Some(key) = signer {
+        println!("signed by [ed25519]:    {}", key);
+    }
     println!("package created at:     {}", std::fs::canonicalize(package_file_path.as_path())?.display());
 
     Ok(())
@@ -1267,7 +1408,46 @@ pub fn main_cli(matches: &clap::ArgMatches) -> Result<()> {
         },
         Some(("verify", matches)) => {
             let file = matches.get_one::<String>("pkgfile").unwrap();
-            subcmd_verify(Path::new(file))?;
+            let trusted = matches.get_many::<String>("trust").map_or(Vec::new(), |keys| keys.cloned().collect());
+            subcmd_verify(Path::new(file), &trusted)?;
+            std::process::exit(0);
+        },
+        Some(("sign", matches)) => {
+            let file = matches.get_one::<String>("pkgfile").unwrap();
+            let key = matches.get_one::<String>("key").unwrap();
+            subcmd_sign(Utf8Path::new(file), Utf8Path::new(key))?;
+            std::process::exit(0);
+        },
+        Some(("keygen", matches)) => {
+            let file = matches.get_one::<String>("keyfile").unwrap();
+            subcmd_keygen(Utf8Path::new(file))?;
+            std::process::exit(0);
+        },
+        Some(("delta", matches)) => {
+            let old = matches.get_one::<String>("old").unwrap();
+            let new = matches.get_one::<String>("new").unwrap();
+            let output = matches.get_one::<String>("output").map(Utf8Path::new);
+            delta::subcmd_delta(Utf8Path::new(old), Utf8Path::new(new), output)?;
+            std::process::exit(0);
+        },
+        Some(("apply-delta", matches)) => {
+            let old = matches.get_one::<String>("old").unwrap();
+            let delta = matches.get_one::<String>("delta").unwrap();
+            let output = matches.get_one::<String>("output").map(Utf8Path::new);
+            delta::subcmd_apply_delta(Utf8Path::new(old), Utf8Path::new(delta), output)?;
+            std::process::exit(0);
+        },
+        Some(("repo", matches)) => {
+            match matches.subcommand() {
+                Some(("index", matches)) => {
+                    let dir = matches.get_one::<String>("dir").unwrap();
+                    let key = matches.get_one::<String>("sign-key").map(Utf8Path::new);
+                    let expires = matches.get_one::<String>("expires").map(|s| bpmutil::parse_duration(s)).transpose()?;
+                    let compress = matches.get_flag("zstd");
+                    repo::subcmd_repo_index(Utf8Path::new(dir), key, expires, compress)?;
+                }
+                _ => unreachable!(),
+            }
             std::process::exit(0);
         },
         Some(("test-ignore", matches)) => {
@@ -0,0 +1,183 @@
+//! Tools for a repository, the directory a provider serves packages from
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use scan_result::index::{Index, INDEX_FILE_NAME, INDEX_SIGNATURE_FILE_NAME};
+use scan_result::{DeltaInfo, ScanResult, VersionInfo};
+use std::collections::HashMap;
+use std::fs::File;
+use std::time::Duration;
+
+/// write a file next to its final path, then move it into place
+fn replace_file(path: &Utf8Path, data: &[u8]) -> Result<()> {
+    let temp_path = path.with_file_name(format!(".{}.tmp", path.file_name().context("path has no filename")?));
+    std::fs::write(&temp_path, data).with_context(|| format!("failed to write {temp_path}"))?;
+    std::fs::rename(&temp_path, path).with_context(|| format!("failed to replace {path}"))?;
+    Ok(())
+}
+
+/// List the `.bpmdelta` files in a repository dir under the versions they rebuild.
+/// Returns how many were listed.
+fn add_deltas(dir: &Utf8Path, packages: &mut ScanResult) -> usize {
+
+    let mut count = 0;
+
+    let walker = ignore::WalkBuilder::new(dir)
+        .standard_filters(false)
+        .max_depth(Some(3))
+        .build();
+
+    for entry in walker.flatten() {
+
+        let Ok(path) = Utf8PathBuf::from_path_buf(entry.into_path()) else { continue };
+        if path.extension() != Some(package::DELTA_FILE_EXTENSION) {
+            continue;
+        }
+
+        let header = File::open(&path).map_err(anyhow::Error::from).and_then(|mut file| package::delta::read_header(&mut file));
+        let header = match header {
+            Ok(header) => header,
+            Err(e) => {
+                eprintln!("warning: skipping {path}: {e:#}");
+                continue;
+            }
+        };
+
+        let Some((name, from, _arch)) = package::split_parts(&header.source) else { continue };
+        let Some(size) = path.metadata().ok().map(|m| m.len()) else { continue };
+
+        let target = packages.packages.get_mut(name)
+            .into_iter()
+            .flat_map(|pinfo| pinfo.versions.values_mut())
+            .flat_map(|vlist| vlist.iter_mut())
+            .find(|vinfo| vinfo.filename == header.target);
+
+        match target {
+            Some(vinfo) => {
+                vinfo.deltas.insert(from.to_string(), DeltaInfo { uri: path.to_string(), size });
+                count += 1;
+            }
+            None => eprintln!("warning: skipping {path}, {} is not in the repository", header.target),
+        }
+    }
+
+    count
+}
+
+/// Record the blake3 hash of each package file, uris must be relative to `dir`.
+/// Files with the same size and mtime as in the previous index keep their hash from it.
+/// Returns how many files were hashed.
+fn add_hashes(dir: &Utf8Path, packages: &mut ScanResult, previous: Option<&ScanResult>) -> Result<usize> {
+
+    let previous: HashMap<&str, &VersionInfo> = previous.into_iter()
+        .flat_map(|packages| packages.packages.values())
+        .flat_map(|pinfo| pinfo.versions.values())
+        .flatten()
+        .map(|vinfo| (vinfo.uri.as_str(), vinfo))
+        .collect();
+
+    let mut count = 0;
+
+    for vinfo in packages.packages.values_mut()
+        .flat_map(|pinfo| pinfo.versions.values_mut())
+        .flat_map(|vlist| vlist.iter_mut())
+    {
+        let unchanged = previous.get(vinfo.uri.as_str())
+            .filter(|old| old.size.is_some() && old.size == vinfo.size && old.mtime.is_some() && old.mtime == vinfo.mtime)
+            .and_then(|old| old.blake3.clone());
+
+        vinfo.blake3 = match unchanged {
+            Some(hash) => Some(hash),
+            None => {
+                let path = dir.join(&vinfo.uri);
+                let file = File::open(&path).with_context(|| format!("failed to open {path}"))?;
+                count += 1;
+                Some(bpmutil::blake3_hash_reader(file).with_context(|| format!("failed to read {path}"))?)
+            }
+        };
+    }
+
+    Ok(count)
+}
+
+/// `bpmpack repo index`
+/// Write an index.json listing the packages in a repository dir, and sign it with `sign_key`.
+/// The sequence number continues from the existing index, if there is one.
+/// Package files are hashed, unless they are unchanged since the existing index.
+/// A signature covers the index as written, compressed or not.
+/// A signed index must expire, otherwise an old copy could be replayed to clients forever.
+pub fn subcmd_repo_index(dir: &Utf8Path, sign_key: Option<&Utf8Path>, expires: Option<Duration>, compress: bool) -> Result<()> {
+
+    if sign_key.is_some() && expires.is_none() {
+        anyhow::bail!("a signed index needs an expiry time");
+    }
+
+    let key = sign_key.map(package::sign::read_secret_key).transpose()?;
+
+    let index_path = dir.join(INDEX_FILE_NAME);
+    let signature_path = dir.join(INDEX_SIGNATURE_FILE_NAME);
+
+    let previous = match std::fs::read(&index_path) {
+        Ok(data) => Some(Index::from_slice(&data).with_context(|| format!("failed to read existing {index_path}"))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("failed to read existing {index_path}")),
+    };
+    let sequence = previous.as_ref().map_or(1, |index| index.sequence + 1);
+
+    let mut packages = fssearch::crawl(dir.as_std_path(), None, None)?;
+    let delta_count = add_deltas(dir, &mut packages);
+
+    let mut index = Index::new(sequence, packages);
+    if let Some(expires) = expires {
+        index.expires = Some(index.created + chrono::Duration::from_std(expires).context("invalid expiry")?);
+    }
+
+    // uris are relative to the repository dir, with forward slashes
+    index.map_uris(|uri| {
+        let rel = Utf8Path::new(uri).strip_prefix(dir).with_context(|| format!("{uri} is outside of {dir}"))?;
+        Ok(rel.components().map(|c| c.as_str()).collect::<Vec<_>>().join("/"))
+    })?;
+
+    let hash_count = add_hashes(dir, &mut index.packages, previous.as_ref().map(|index| &index.packages))?;
+
+    let data = if compress {
+        index.to_vec_compressed(crate::DEFAULT_ZSTD_LEVEL)?
+    } else {
+        index.to_vec()?
+    };
+    // The signature goes first, so that a client never sees the new index next to the old signature.
+    // A client that reads both while they are being replaced re-reads them.
+    // An old signature would not match the new index.
+    let signer = if let Some(key) = &key {
+        let signature = package::sign::sign(key, &data);
+        replace_file(&signature_path, &signature.to_vec()?)?;
+        Some(signature.key)
+    } else {
+        if signature_path.exists() {
+            std::fs::remove_file(&signature_path).with_context(|| format!("failed to remove {signature_path}"))?;
+        }
+        None
+    };
+
+    replace_file(&index_path, &data)?;
+
+    // Writing into the dir changed its mtime. A scan only trusts the index if it is newer than the
+    // dirs it covers, so touch the index last.
+    File::options().write(true).open(&index_path)
+        .and_then(|file| file.set_modified(std::time::SystemTime::now()))
+        .with_context(|| format!("failed to touch {index_path}"))?;
+
+    println!("index written to {index_path} ({}{})", humansize::format_size(data.len(), humansize::BINARY), if compress { ", zstd" } else { "" });
+    println!("sequence: {}", index.sequence);
+    println!("packages: {}, versions: {}, deltas: {}", index.packages.package_count(), index.packages.version_count(), delta_count);
+    println!("hashed:   {hash_count} package files");
+    if let Some(expires) = index.expires {
+        println!("expires:  {expires}");
+    }
+    match signer {
+        Some(key) => println!("signed by [ed25519]: {key}"),
+        None => println!("not signed"),
+    }
+
+    Ok(())
+}
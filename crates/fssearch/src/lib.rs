@@ -1,7 +1,8 @@
 //#![feature(extract_if)]
 
 use anyhow::Result;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
+use scan_result::index::{Index, INDEX_FILE_NAME};
 use std::collections::HashMap;
 use std::path::Path;
 use tracing::trace;
@@ -37,11 +38,32 @@ type ChannelList = HashMap<ChannelName, Vec<PackageVersion>>;
 ///                 foo-3.0.0.bpm
 ///         bar/
 ///             bar-0.1.0.bpm
+///
+/// If the directory has a fresh `index.json` (see `bpmpack repo index`), the packages are read
+/// from it instead of walking the directory.
 /// ```
 pub fn full_scan(dir: &Path, filter_name: Option<&str>, archs: Option<&[&str]>)-> Result<scan_result::ScanResult> {
 
     trace!(filter_name, dir=?dir, "full_scan");
 
+    if let Some(mut report) = read_index(dir) {
+        if let Some(filter_name) = filter_name {
+            report.filter_package(&[filter_name]);
+        }
+        if let Some(archs) = archs {
+            report.filter_arch(archs);
+        }
+        return Ok(report);
+    }
+
+    crawl(dir, filter_name, archs)
+}
+
+/// Scan a directory for packages by walking it, ignoring any index
+pub fn crawl(dir: &Path, filter_name: Option<&str>, archs: Option<&[&str]>)-> Result<scan_result::ScanResult> {
+
+    trace!(filter_name, dir=?dir, "crawl");
+
     let mut report = scan_result::ScanResult::default();
 
     let mut overrides = ignore::overrides::OverrideBuilder::new(dir);
@@ -128,7 +150,8 @@ pub fn full_scan(dir: &Path, filter_name: Option<&str>, archs: Option<&[&str]>)-
                 // pkg/foo-1.2.3.bpm
 
                 if arch_filter(arch, archs) {
-                    report.add_version(pkg_name, pkg_version, arch, None, filename, full_path.as_str());
+                    let vinfo = report.add_version(pkg_name, pkg_version, arch, None, filename, full_path.as_str());
+                    stat(vinfo, &full_path);
                     tracing::debug!("[f] found {}", rel_path);
                 }
 
@@ -140,7 +163,8 @@ pub fn full_scan(dir: &Path, filter_name: Option<&str>, archs: Option<&[&str]>)-
 
                 if parent_dir_name == Some(pkg_name) {
                     if arch_filter(arch, archs) {
-                        report.add_version(pkg_name, pkg_version, arch, None, filename, full_path.as_str());
+                        let vinfo = report.add_version(pkg_name, pkg_version, arch, None, filename, full_path.as_str());
+                        stat(vinfo, &full_path);
                         tracing::trace!("[n] found {}", rel_path);
                     }
                 } else {
@@ -159,7 +183,8 @@ pub fn full_scan(dir: &Path, filter_name: Option<&str>, archs: Option<&[&str]>)-
                 if let (Some(pkg_dir_name), Some(channel_name)) = (pkg_dir_name, channel_name) {
                     if pkg_dir_name == pkg_name {
                         if arch_filter(arch, archs) {
-                            report.add_version(pkg_name, pkg_version, arch, Some(channel_name), filename, full_path.as_str());
+                            let vinfo = report.add_version(pkg_name, pkg_version, arch, Some(channel_name), filename, full_path.as_str());
+                            stat(vinfo, &full_path);
                             tracing::trace!("[c] found {}", rel_path);
                         }
                     } else {
@@ -200,6 +225,113 @@ pub fn full_scan(dir: &Path, filter_name: Option<&str>, archs: Option<&[&str]>)-
     Ok(report)
 }
 
+/// Read the packages from a directory's `index.json`, with uris made absolute.
+/// None if there is no index, or it is invalid, expired, or stale.
+fn read_index(dir: &Path) -> Option<scan_result::ScanResult> {
+
+    let index_path = dir.join(INDEX_FILE_NAME);
+
+    let data = match std::fs::read(&index_path) {
+        Ok(data) => data,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("failed to read {}: {}", index_path.display(), e);
+            }
+            return None;
+        }
+    };
+
+    let mut index = match Index::from_slice(&data) {
+        Ok(index) => index,
+        Err(e) => {
+            tracing::warn!("ignoring index {}: {:#}", index_path.display(), e);
+            return None;
+        }
+    };
+
+    if index.is_expired(chrono::Utc::now()) {
+        tracing::debug!("ignoring expired index {}", index_path.display());
+        return None;
+    }
+
+    let mapped = index.map_uris(|uri| {
+        let path = Utf8PathBuf::from_path_buf(dir.join(uri)).map_err(|_| anyhow::anyhow!("path is not utf8"))?;
+        Ok(path.into_string())
+    });
+    if let Err(e) = mapped {
+        tracing::warn!("ignoring index {}: {:#}", index_path.display(), e);
+        return None;
+    }
+
+    let index_mtime = std::fs::metadata(&index_path).and_then(|m| m.modified()).ok()?;
+    if !index_is_fresh(dir, &index.packages, index_mtime) {
+        tracing::debug!("ignoring stale index {}", index_path.display());
+        return None;
+    }
+
+    tracing::debug!("using index {}, sequence {}", index_path.display(), index.sequence);
+    Some(index.packages)
+}
+
+/// An index is fresh if nothing a crawl would read changed after it was written:
+/// the directory, package dirs, channel dirs, channels.json, and kv.json files,
+/// and the package and delta files it lists, which can be overwritten without touching their dir.
+fn index_is_fresh(dir: &Path, packages: &scan_result::ScanResult, index_mtime: std::time::SystemTime) -> bool {
+
+    let older = |path: &Path| {
+        std::fs::metadata(path).and_then(|m| m.modified()).is_ok_and(|t| t <= index_mtime)
+    };
+
+    if !older(dir) {
+        return false;
+    }
+
+    for vinfo in packages.packages.values().flat_map(|pinfo| pinfo.versions.values()).flatten() {
+        let uris = std::iter::once(&vinfo.uri).chain(vinfo.deltas.values().map(|delta| &delta.uri));
+        if !uris.into_iter().all(|uri| older(Path::new(uri))) {
+            return false;
+        }
+    }
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+
+    for pkg_dir in entries.flatten().filter(|e| e.path().is_dir()) {
+
+        if !older(&pkg_dir.path()) {
+            return false;
+        }
+
+        let Ok(entries) = std::fs::read_dir(pkg_dir.path()) else {
+            return false;
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let is_channel_dir = name.starts_with(CHANNEL_DIR_PREFIX) && entry.path().is_dir();
+            if (is_channel_dir || name == CHANNELS_FILE || name == KV_FILE) && !older(&entry.path()) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// record the size and mtime of a package file
+/// a version found more than once keeps the uri of the first file, and so its size
+fn stat(vinfo: &mut scan_result::VersionInfo, path: &Utf8Path) {
+    if vinfo.size.is_some() {
+        return;
+    }
+    if let Ok(md) = std::fs::metadata(path) {
+        vinfo.size = Some(md.len());
+        vinfo.mtime = md.modified().ok().map(chrono::DateTime::<chrono::Utc>::from);
+    }
+}
+
 /// return true if arch passes the arch filters
 fn arch_filter(arch: Option<&str>, filters: Option<&[&str]>) -> bool {
 
@@ -7,11 +7,10 @@ use std::path::Path;
 use std::path::PathBuf;
 
 fn remove_path_prefix<'a>(prefix: &Path, path: &'a Path) -> &'a Path {
-    let path = match path.strip_prefix(prefix) {
+    match path.strip_prefix(prefix) {
         Ok(path) => path,
         Err(_) => path,
-    };
-    path
+    }
 }
 
 fn get_file_hash(path: &Path) -> Result<String> {
@@ -44,7 +43,7 @@ fn get_dir_hash(dir: &Path, show_files: bool) -> Result<String> {
 
     for (path, hash) in files {
         if show_files {
-            println!("{} {}", &hash.as_ref().unwrap(), path.display())
+            println!("{} {}", hash.as_ref().unwrap(), path.display())
         }
         write!(&mut final_hasher, "{}", path.display())?;
         final_hasher.write_all(hash.unwrap().as_bytes())?;
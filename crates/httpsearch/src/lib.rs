@@ -19,20 +19,39 @@
 //!             bar-0.1.0.bpm
 //! ```
 //!
-//! env vars:
+//! Pages that the server sends an ETag or Last-Modified header for are remembered, see `PageCache`.
+//! The next scan sends If-None-Match / If-Modified-Since and reuses a page that was not modified.
+//!
+//! Directory pages served as json, by nginx with `autoindex_format json` or in the bpm listing format,
+//! are read as listings instead of scraping links, see `listing`. They give each file's size and mtime.
+//!
+//! If the server has an `index.json` at the root (see `bpmpack repo index`) that has not expired,
+//! the packages are read from it in one request instead of crawling.
+//!
+//! Proxies, certificates, headers, credentials and timeouts are set with `HttpOptions`.
+//!
+//! env vars, overriding the scan threads and jobs of `HttpOptions`:
 //!   BPM_HTTP_THREADS = T
 //!   BPM_HTTP_JOBS = J
 //!
 //!   T threads running J concurrent http requests
 
+mod listing;
 pub mod masync;
+pub mod netrc;
+mod options;
+
+pub use options::{Auth, HttpOptions};
 
 #[cfg(all(feature="rustls", feature="nativessl"))]
 std::compile_error!("Use either rustls or nativessl feature, not both.");
 
 use anyhow::Context;
 use anyhow::Result;
-use reqwest::blocking::Client;
+use chrono::{DateTime, Utc};
+use reqwest::blocking::{Client, Response};
+use scan_result::index::{Index, INDEX_FILE_NAME};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Semaphore;
@@ -40,7 +59,6 @@ use tracing::trace;
 use url::Url;
 use version::VersionString;
 
-const DEFAULT_TIMEOUT: u64 = 5;
 const CHANNEL_DIR_PREFIX : &str = "channel_";
 const CHANNELS_FILE : &str = "channels.json";
 const KV_FILE : &str = "kv.json";
@@ -61,12 +79,66 @@ pub struct VersionInfo<'a> {
     pub filename: &'a str,
     pub channel: Option<&'a str>,
     pub arch: Option<&'a str>,
+    pub size: Option<u64>,
+    pub mtime: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Link {
     text: LinkText,
-    url: LinkUrlStr
+    url: LinkUrlStr,
+
+    /// from a json directory listing
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+
+    /// from a json directory listing
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<DateTime<Utc>>,
+}
+
+/// What a page looked like the last time it was fetched.
+/// Only pages that the server sent an ETag or Last-Modified for are kept.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedPage {
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+
+    /// links scraped from an html page
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    links: Vec<Link>,
+
+    /// body of a json file
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+}
+
+/// Pages from a previous scan, used to make conditional requests on the next scan.
+/// A page that has not changed (304 Not Modified) is not downloaded or parsed again.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PageCache {
+    pages: HashMap<String, CachedPage>,
+}
+
+impl PageCache {
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
 }
 
 impl Link {
@@ -79,6 +151,8 @@ impl Link {
                 filename: &self.text,
                 channel: None,
                 arch,
+                size: self.size,
+                mtime: self.mtime,
             });
         }
         None
@@ -148,6 +222,8 @@ fn scrape_links(origin_url: &Url, body: &str) -> Vec<Link> {
                 links.push(Link {
                     text: link_text,
                     url: url.to_string(),
+                    size: None,
+                    mtime: None,
                 })
             }
         }
@@ -155,19 +231,28 @@ fn scrape_links(origin_url: &Url, body: &str) -> Vec<Link> {
     links
 }
 
-pub fn full_scan(timeout: Option<u64>, root_url: &str, pkg_name: Option<&str>, archs: Option<&[&str]>)-> Result<scan_result::ScanResult> {
+pub fn full_scan(options: &HttpOptions, root_url: &str, pkg_name: Option<&str>, archs: Option<&[&str]>)-> Result<scan_result::ScanResult> {
+    let (report, _pages) = full_scan_cached(options, root_url, pkg_name, archs, PageCache::default())?;
+    Ok(report)
+}
+
+/// Like `full_scan`, sending conditional requests for the pages in `previous`.
+/// Returns the pages to pass to the next scan.
+pub fn full_scan_cached(options: &HttpOptions, root_url: &str, pkg_name: Option<&str>, archs: Option<&[&str]>, previous: PageCache)-> Result<(scan_result::ScanResult, PageCache)> {
 
     let threads = std::env::var("BPM_HTTP_THREADS")
         .ok()
         .and_then(|s| s.parse::<u32>().ok())
         .filter(|&n| n != 0)
-        .unwrap_or(1) as usize;
+        .map_or(options.scan_threads, |n| n as usize)
+        .max(1);
 
     let jobs = std::env::var("BPM_HTTP_JOBS")
         .ok()
         .and_then(|s| s.parse::<u32>().ok())
         .filter(|&n| n != 0)
-        .unwrap_or(1) as usize;
+        .map_or(options.scan_jobs, |n| n as usize)
+        .max(1);
 
     tracing::trace!("http scan with {threads} threads, {jobs} jobs");
 
@@ -188,20 +273,16 @@ pub fn full_scan(timeout: Option<u64>, root_url: &str, pkg_name: Option<&str>, a
         }
     );
 
-    runtime.block_on(masync::full_scan(semaphore, timeout, root_url, pkg_name, archs))
+    runtime.block_on(masync::full_scan(semaphore, options, root_url, pkg_name, archs, previous))
 }
 
-pub fn get_size(timeout: Option<u64>, url: &str) -> Result<u64> {
-
-    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+pub fn get_size(options: &HttpOptions, url: &str) -> Result<u64> {
 
     let url = Url::parse(url)?;
 
-    let client = reqwest::blocking::Client::builder()
-        .connect_timeout(std::time::Duration::from_secs(timeout))
-        .build()?;
+    let client = options.blocking_client()?;
 
-    let resp = client.head(url).send()?;
+    let resp = options.authorize(client.head(url.as_str()), &url).send()?;
 
     let size = resp.headers()
         .get("content-length")
@@ -211,30 +292,194 @@ pub fn get_size(timeout: Option<u64>, url: &str) -> Result<u64> {
     Ok(size.parse::<u64>()?)
 }
 
-pub fn download(timeout: Option<u64>, url: &str, write: &mut dyn std::io::Write) -> Result<u64> {
+/// Send a HEAD request, Ok if the server answers with anything but a server error
+pub fn ping(options: &HttpOptions, url: &str) -> Result<()> {
+
+    let url = Url::parse(url)?;
+
+    let client = options.blocking_client()?;
+
+    let resp = options.authorize(client.head(url.as_str()), &url).send()?;
+    if resp.status().is_server_error() {
+        anyhow::bail!("{url}: {}", resp.status());
+    }
+    Ok(())
+}
+
+/// url of a file relative to a root url, the root is treated as a directory
+pub fn join_url(root_url: &str, path: &str) -> Result<String> {
+    let mut root_url = std::borrow::Cow::Borrowed(root_url);
+    if !root_url.ends_with('/') {
+        root_url.to_mut().push('/');
+    }
+    Ok(Url::parse(&root_url)?.join(path)?.to_string())
+}
+
+/// download a small file into memory, None if the server says it does not exist
+pub fn get_optional(options: &HttpOptions, url: &str) -> Result<Option<Vec<u8>>> {
+
+    let url = Url::parse(url)?;
+
+    let client = options.blocking_client()?;
 
-    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    tracing::trace!("downloading {url}");
+
+    let resp = options.authorize(client.get(url.as_str()), &url).send()?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let resp = resp.error_for_status()?;
+    Ok(Some(resp.bytes()?.to_vec()))
+}
+
+/// PUT a file, with an optional bearer token, returning the response body
+pub fn upload(options: &HttpOptions, url: &str, token: Option<&str>, file: std::fs::File) -> Result<Vec<u8>> {
 
     let url = Url::parse(url)?;
 
-    let client = reqwest::blocking::Client::builder()
-        .connect_timeout(std::time::Duration::from_secs(timeout))
+    let client = options.blocking_builder()?
+        .timeout(None)
         .build()?;
 
-    client_download(&client, &url, write)
+    tracing::trace!("uploading to {url}");
+
+    // the publish token replaces any configured credentials
+    let req = client.put(url.as_str()).body(file);
+    let req = match token {
+        Some(token) => req.bearer_auth(token),
+        None => options.authorize(req, &url),
+    };
+
+    let resp = req.send()?;
+    let status = resp.status();
+    let body = resp.bytes()?.to_vec();
+    if !status.is_success() {
+        let msg = String::from_utf8_lossy(&body);
+        anyhow::bail!("upload failed: {status} {}", msg.trim());
+    }
+    Ok(body)
 }
 
-pub fn client_download(client: &Client, url: &Url, write: &mut dyn std::io::Write) -> Result<u64> {
+/// Download into a writer, retrying when the request fails before any of the body is written.
+pub fn download(options: &HttpOptions, url: &str, write: &mut dyn std::io::Write) -> Result<u64> {
+
+    let url = Url::parse(url)?;
+    let client = options.blocking_client()?;
 
     tracing::trace!("downloading {url}");
 
-    let resp = client.get(url.as_str()).send()?;
-    match resp.error_for_status() {
-        Err(e) => {
-            anyhow::bail!(e)
-        }
-        Ok(mut resp) => {
-            Ok(resp.copy_to(write)?)
+    let mut resp = options.retry(&url, || {
+        Ok(options.authorize(client.get(url.as_str()), &url).send()?.error_for_status()?)
+    })?;
+
+    Ok(resp.copy_to(write)?)
+}
+
+/// Download into a file, continuing from the end of what the file already holds.
+///
+/// If the server answers a `Range` request with the rest of the file, it is appended,
+/// otherwise the file is truncated and downloaded from the start.
+/// Failed attempts are retried, each resuming from wherever the last one stopped.
+/// The file is left as it is on error, for a later call to resume.
+/// `progress` is called with the length of the file as it grows.
+///
+/// Nothing checks that the part already in the file is from the same version of the remote file,
+/// the caller is expected to check the whole file afterwards.
+pub fn download_resume(options: &HttpOptions, url: &str, file: &mut std::fs::File, progress: &dyn Fn(u64)) -> Result<u64> {
+
+    let url = Url::parse(url)?;
+    let client = options.blocking_client()?;
+
+    options.retry(&url, || download_resume_once(options, &client, &url, file, progress))
+}
+
+fn download_resume_once(options: &HttpOptions, client: &Client, url: &Url, file: &mut std::fs::File, progress: &dyn Fn(u64)) -> Result<u64> {
+
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let mut offset = file.seek(SeekFrom::End(0))?;
+
+    let (mut resp, start) = match request_from(options, client, url, offset)? {
+        Some(found) => found,
+        None => {
+            // the partial file can not be a part of the remote file, start over once
+            file.set_len(0)?;
+            file.rewind()?;
+            offset = 0;
+            match request_from(options, client, url, offset)? {
+                Some(found) => found,
+                None => anyhow::bail!("{url}: unexpected partial response"),
+            }
         }
+    };
+
+    if start < offset {
+        tracing::debug!("{url}: server does not support ranges, downloading from the start");
+        file.set_len(0)?;
+        file.rewind()?;
+    }
+
+    let expected = resp.content_length().map(|len| start + len);
+
+    let mut pos = start;
+    progress(pos);
+
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match resp.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(anyhow::Error::new(e).context("download interrupted")),
+        };
+        file.write_all(&buf[..n]).context("failed to write download")?;
+        pos += n as u64;
+        progress(pos);
+    }
+    file.flush()?;
+
+    if let Some(expected) = expected && pos != expected {
+        return Err(anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+            .context(format!("download ended at {pos} of {expected} bytes")));
+    }
+
+    Ok(pos)
+}
+
+/// Request a file from an offset, returning the response and the offset its body starts at,
+/// which is 0 if the server sent the whole file.
+/// None if the server says the offset is past the end of the file, or answers with a different range.
+fn request_from(options: &HttpOptions, client: &Client, url: &Url, offset: u64) -> Result<Option<(Response, u64)>> {
+
+    use reqwest::StatusCode;
+
+    let mut req = options.authorize(client.get(url.as_str()), url);
+    if offset > 0 {
+        tracing::debug!("resuming {url} at {offset} bytes");
+        req = req.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+    }
+    let resp = req.send()?;
+
+    if offset > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        tracing::debug!("{url}: range not satisfiable");
+        return Ok(None);
+    }
+
+    let resp = resp.error_for_status()?;
+
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return Ok(Some((resp, 0)));
+    }
+
+    let range_start = resp.headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes "))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start, _)| start.parse::<u64>().ok());
+    if range_start != Some(offset) {
+        tracing::debug!("{url}: unexpected Content-Range {range_start:?}");
+        return Ok(None);
     }
+    Ok(Some((resp, offset)))
 }
@@ -0,0 +1,157 @@
+//! Machine readable directory listings
+//!
+//! A page served with a json Content-Type is read as a directory listing instead of scraping its links.
+//!
+//! nginx with `autoindex_format json;`
+//! ```ignore
+//! [
+//!   { "name": "channel_stable", "type": "directory", "mtime": "Wed, 01 Jan 2025 10:00:00 GMT" },
+//!   { "name": "foo_1.0.0.bpm", "type": "file", "mtime": "Wed, 01 Jan 2025 10:00:00 GMT", "size": 1234 }
+//! ]
+//! ```
+//!
+//! bpm listing, the same entries in an object, mtimes may be RFC 3339
+//! ```ignore
+//! { "entries": [
+//!   { "name": "channel_stable", "type": "directory" },
+//!   { "name": "foo_1.0.0.bpm", "type": "file", "mtime": "2025-01-01T10:00:00Z", "size": 1234 }
+//! ] }
+//! ```
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use url::Url;
+
+use crate::Link;
+
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+
+    #[serde(rename = "type")]
+    kind: String,
+
+    #[serde(default)]
+    size: Option<u64>,
+
+    #[serde(default)]
+    mtime: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Listing {
+    Nginx(Vec<Entry>),
+    Bpm { entries: Vec<Entry> },
+}
+
+/// true for `application/json`, `text/json` and `+json` types
+pub(crate) fn is_json(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime == "application/json" || mime == "text/json" || mime.ends_with("+json")
+}
+
+fn parse_mtime(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .or_else(|_| DateTime::parse_from_rfc2822(text))
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// the links of a json directory listing, made absolute
+pub(crate) fn parse_json_listing(origin_url: &Url, body: &str) -> Result<Vec<Link>> {
+
+    let entries = match serde_json::from_str::<Listing>(body)? {
+        Listing::Nginx(entries) => entries,
+        Listing::Bpm { entries } => entries,
+    };
+
+    let mut links = Vec::new();
+    for entry in entries {
+
+        if entry.name.is_empty() || entry.name == "." || entry.name == ".." || entry.name.contains('/') {
+            continue;
+        }
+
+        let dir = match entry.kind.as_str() {
+            "directory" | "dir" => true,
+            "file" => false,
+            _ => continue,
+        };
+
+        let mut url = origin_url.clone();
+        let Ok(mut segments) = url.path_segments_mut() else {
+            anyhow::bail!("{origin_url} can not hold a directory");
+        };
+        segments.pop_if_empty().push(&entry.name);
+        if dir {
+            segments.push("");
+        }
+        drop(segments);
+
+        links.push(Link {
+            // directories look like they do in an html listing
+            text: if dir { format!("{}/", entry.name) } else { entry.name },
+            url: url.to_string(),
+            size: if dir { None } else { entry.size },
+            mtime: entry.mtime.as_deref().and_then(parse_mtime),
+        });
+    }
+    Ok(links)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn content_type() {
+        assert!(is_json("application/json"));
+        assert!(is_json("application/json; charset=utf-8"));
+        assert!(is_json("Application/vnd.bpm.listing+json"));
+        assert!(!is_json("text/html"));
+        assert!(!is_json(""));
+    }
+
+    #[test]
+    fn nginx() -> Result<()> {
+        let url = Url::parse("http://host/pkg/foo/")?;
+        let links = parse_json_listing(&url, r#"[
+            { "name": "channel_stable", "type": "directory", "mtime": "Wed, 01 Jan 2025 10:00:00 GMT" },
+            { "name": "foo_1.0.0.bpm", "type": "file", "mtime": "Wed, 01 Jan 2025 10:00:00 GMT", "size": 1234 },
+            { "name": "a b&c.txt", "type": "file", "size": 1 },
+            { "name": "link", "type": "other" }
+        ]"#)?;
+
+        assert_eq!(links.len(), 3);
+        assert_eq!(links[0].text, "channel_stable/");
+        assert_eq!(links[0].url, "http://host/pkg/foo/channel_stable/");
+        assert_eq!(links[0].size, None);
+        assert_eq!(links[1].text, "foo_1.0.0.bpm");
+        assert_eq!(links[1].url, "http://host/pkg/foo/foo_1.0.0.bpm");
+        assert_eq!(links[1].size, Some(1234));
+        assert_eq!(links[1].mtime, Some("2025-01-01T10:00:00Z".parse()?));
+        assert_eq!(links[2].text, "a b&c.txt");
+        assert_eq!(links[2].url, "http://host/pkg/foo/a%20b&c.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn bpm() -> Result<()> {
+        let url = Url::parse("http://host/pkg")?;
+        let links = parse_json_listing(&url, r#"{ "entries": [
+            { "name": "foo", "type": "dir" },
+            { "name": "bar_2.0.0_x86_64.bpm", "type": "file", "mtime": "2025-01-01T10:00:00+01:00", "size": 5 },
+            { "name": "..", "type": "dir" }
+        ] }"#)?;
+
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].url, "http://host/pkg/foo/");
+        assert_eq!(links[1].url, "http://host/pkg/bar_2.0.0_x86_64.bpm");
+        assert_eq!(links[1].mtime, Some("2025-01-01T09:00:00Z".parse()?));
+
+        assert!(parse_json_listing(&url, r#"{ "kv": 1 }"#).is_err());
+        Ok(())
+    }
+}
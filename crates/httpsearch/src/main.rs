@@ -36,7 +36,7 @@ fn main() -> Result<()> {
         }
     }
 
-    let packages = full_scan(None, &url, pkg, arch)?;
+    let packages = full_scan(&HttpOptions::default(), &url, pkg, arch)?;
     packages.print();
 
     Ok(())
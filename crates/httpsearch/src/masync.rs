@@ -1,31 +1,135 @@
 use crate::*;
 pub use reqwest::Client;
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
 use std::sync::Arc;
 use std::sync::Mutex;
 use tokio::sync::Semaphore;
 
-async fn fetch_page(semaphore: &Semaphore, client: &Arc<Client>, url: &Url) -> Result<String> {
+/// the scan's client, adding the configured credentials to each request
+pub(crate) struct ScanClient {
+    client: Client,
+    options: HttpOptions,
+}
+
+impl ScanClient {
+    fn get(&self, url: &Url) -> reqwest::RequestBuilder {
+        self.options.authorize(self.client.get(url.as_str()), url)
+    }
+}
+
+/// pages from the previous scan, and the pages to keep for the next one
+struct Pages {
+    previous: PageCache,
+    current: Mutex<PageCache>,
+}
+
+enum Fetched {
+    NotModified(CachedPage),
+    Body {
+        body: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        content_type: Option<String>,
+    },
+}
+
+impl Pages {
+    fn keep(&self, url: &Url, page: CachedPage) {
+        self.current.lock().unwrap().pages.insert(url.to_string(), page);
+    }
+}
+
+/// get a page, conditionally if it was cached by the previous scan
+async fn fetch(semaphore: &Semaphore, client: &Arc<ScanClient>, pages: &Pages, url: &Url) -> Result<Fetched> {
 
     let _permit = semaphore.acquire().await.unwrap();
 
-    let resp = client.get(url.as_str()).send().await?;
+    let previous = pages.previous.pages.get(url.as_str());
+
+    let mut req = client.get(url);
+    if let Some(previous) = previous {
+        if let Some(etag) = &previous.etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &previous.last_modified {
+            req = req.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let resp = req.send().await?;
     //println!("[get] headers {:?}", resp.headers());
+
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED && let Some(previous) = previous {
+        tracing::trace!("not modified {url}");
+        return Ok(Fetched::NotModified(previous.clone()));
+    }
+
+    // only a successful response is a page worth parsing or keeping for the next scan
+    let resp = resp.error_for_status()?;
+
+    let header = |name| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let content_type = header(CONTENT_TYPE);
+
     let body = resp.text().await?;
     //println!("fetch_page body: {}", body);
     //tracing::trace!(url=url.as_str(), "fetch, body size {}", body.len());
-    Ok(body)
+    Ok(Fetched::Body { body, etag, last_modified, content_type })
 }
 
-async fn scrape_links_from(semaphore: &Semaphore, client: &Arc<Client>, url: &Url) -> Result<Vec<Link>> {
+/// get the body of a file
+async fn fetch_page(semaphore: &Semaphore, client: &Arc<ScanClient>, pages: &Pages, url: &Url) -> Result<String> {
 
-    let body = fetch_page(semaphore, client, url).await?;
-    let links = scrape_links(url, &body);
-    Ok(links)
+    match fetch(semaphore, client, pages, url).await? {
+        Fetched::NotModified(page) => {
+            let body = page.body.clone().unwrap_or_default();
+            pages.keep(url, page);
+            Ok(body)
+        }
+        Fetched::Body { body, etag, last_modified, .. } => {
+            if etag.is_some() || last_modified.is_some() {
+                pages.keep(url, CachedPage { etag, last_modified, links: Vec::new(), body: Some(body.clone()) });
+            }
+            Ok(body)
+        }
+    }
+}
+
+async fn scrape_links_from(semaphore: &Semaphore, client: &Arc<ScanClient>, pages: &Pages, url: &Url) -> Result<Vec<Link>> {
+
+    match fetch(semaphore, client, pages, url).await? {
+        Fetched::NotModified(page) => {
+            let links = page.links.clone();
+            pages.keep(url, page);
+            Ok(links)
+        }
+        Fetched::Body { body, etag, last_modified, content_type } => {
+            let links = match content_type {
+                Some(content_type) if listing::is_json(&content_type) => {
+                    listing::parse_json_listing(url, &body).with_context(|| format!("invalid directory listing {url}"))?
+                }
+                _ => scrape_links(url, &body),
+            };
+            if etag.is_some() || last_modified.is_some() {
+                pages.keep(url, CachedPage { etag, last_modified, links: links.clone(), body: None });
+            }
+            Ok(links)
+        }
+    }
+}
+
+/// add a package file to the report, with its size and mtime if the listing had them
+fn add_to_report(report: &mut scan_result::ScanResult, pkg_name: &str, info: &VersionInfo, channel: Option<&str>) {
+    let vinfo = report.add_version(pkg_name, info.version, info.arch, channel, info.filename, info.url);
+    vinfo.size = vinfo.size.or(info.size);
+    vinfo.mtime = vinfo.mtime.or(info.mtime);
 }
 
 async fn scan_package_dir(
     semaphore: Arc<Semaphore>,
-    client: Arc<Client>,
+    client: Arc<ScanClient>,
+    pages: Arc<Pages>,
     report: Arc<Mutex<scan_result::ScanResult>>,
     pkg_name: String,
     archs: Arc<Vec<String>>,
@@ -37,7 +141,7 @@ async fn scan_package_dir(
     // gather all links from this package dir
     let mut links = Vec::new();
     if let Ok(url) = Url::parse(&dir_link.url) {
-        match scrape_links_from(&semaphore, &client, &url).await {
+        match scrape_links_from(&semaphore, &client, &pages, &url).await {
             Ok(l) => { links = l; },
             Err(_) => { return; }
         }
@@ -56,7 +160,7 @@ async fn scan_package_dir(
             } else if let Some(info) = pkg_link.version_info() {
                 if arch_filter(info.arch, &archs) {
                     let mut report = report.lock().unwrap();
-                    report.add_version(&pkg_name, info.version, info.arch, info.channel, info.filename, info.url);
+                    add_to_report(&mut report, &pkg_name, &info, info.channel);
                 }
             }
         }
@@ -80,12 +184,13 @@ async fn scan_package_dir(
             if let Ok(url) = Url::parse(&channel.url) {
                 let semaphore = Arc::clone(&semaphore);
                 let client = Arc::clone(&client);
+                let pages = Arc::clone(&pages);
                 let report = Arc::clone(&report);
                 let pkg_name = pkg_name.clone();
                 let channel_name = channel_name.to_string();
                 let archs = Arc::clone(&archs);
                 joinset.spawn(async move {
-                    let links = scrape_links_from(&semaphore, &client, &url).await.unwrap_or_default();
+                    let links = scrape_links_from(&semaphore, &client, &pages, &url).await.unwrap_or_default();
 
                     // add the package files found in this dir to the overall packages list
                     let (channel_pkg_files, _) = split_links(links, is_pkg_link);
@@ -95,7 +200,7 @@ async fn scan_package_dir(
                             if pkg_name == link_pkg_name {
                                 if let Some(info) = link.version_info() {
                                     if arch_filter(info.arch, &archs) {
-                                        report.add_version(&pkg_name, info.version, info.arch, Some(channel_name.as_str()), info.filename, info.url);
+                                        add_to_report(&mut report, &pkg_name, &info, Some(channel_name.as_str()));
                                     }
                                 }
                             } else {
@@ -118,9 +223,10 @@ async fn scan_package_dir(
         if let Ok(url) = Url::parse(&kv_url.url) {
             let semaphore = Arc::clone(&semaphore);
             let client = Arc::clone(&client);
+            let pages = Arc::clone(&pages);
             let kv = Arc::clone(&kv);
             joinset.spawn(async move {
-                if let Ok(body) = fetch_page(&semaphore, &client, &url).await {
+                if let Ok(body) = fetch_page(&semaphore, &client, &pages, &url).await {
                     match serde_json::from_str(&body) {
                         Ok(val) => {
                             *kv.lock().unwrap() = Some(val);
@@ -139,7 +245,7 @@ async fn scan_package_dir(
         if let Ok(url) = Url::parse(&channels_json.url) {
             let channels = Arc::clone(&channels);
             joinset.spawn(async move {
-                if let Ok(body) = fetch_page(&semaphore, &client, &url).await {
+                if let Ok(body) = fetch_page(&semaphore, &client, &pages, &url).await {
                     match serde_json::from_str::<ChannelList>(&body) {
                         Ok(val) => {
                             //println!("channels json: {:?}", val);
@@ -205,16 +311,75 @@ fn arch_filter(arch: Option<&str>, filters: &Vec<String>) -> bool {
     false
 }
 
+/// Read the packages from the server's `index.json`, with uris made absolute.
+/// None if there is no index, or it is invalid or expired.
+/// Unlike a local directory, there is no reliable way to tell whether the server's files changed after the
+/// index was written, so an index without an expiry time is never trusted to be fresh.
+async fn read_index(semaphore: &Semaphore, client: &Arc<ScanClient>, root_url: &Url) -> Option<scan_result::ScanResult> {
+
+    let url = root_url.join(INDEX_FILE_NAME).ok()?;
+
+    let data = {
+        let _permit = semaphore.acquire().await.unwrap();
+        let resp = match client.get(&url).send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                tracing::debug!("failed to fetch {url}: {e}");
+                return None;
+            }
+        };
+        if !resp.status().is_success() {
+            tracing::trace!("no index at {url}: {}", resp.status());
+            return None;
+        }
+        resp.bytes().await.ok()?
+    };
+
+    let mut index = match Index::from_slice(&data) {
+        Ok(index) => index,
+        Err(e) => {
+            tracing::warn!("ignoring index {url}: {e:#}");
+            return None;
+        }
+    };
+
+    if index.expires.is_none() {
+        tracing::debug!("ignoring index {url} without an expiry time");
+        return None;
+    }
+
+    if index.is_expired(chrono::Utc::now()) {
+        tracing::debug!("ignoring expired index {url}");
+        return None;
+    }
+
+    if let Err(e) = index.map_uris(|uri| Ok(root_url.join(uri)?.to_string())) {
+        tracing::warn!("ignoring index {url}: {e:#}");
+        return None;
+    }
+
+    tracing::debug!("using index {url}, sequence {}", index.sequence);
+    Some(index.packages)
+}
+
 pub async fn full_scan(
     semaphore: Arc<Semaphore>,
-    _timeout: Option<u64>,
+    options: &HttpOptions,
     root_url: &str,
     filter_name: Option<&str>,
     archs: Arc<Vec<String>>,
-) -> Result<scan_result::ScanResult> {
+    previous: PageCache,
+) -> Result<(scan_result::ScanResult, PageCache)> {
+
+    let client = Arc::new(ScanClient {
+        client: options.async_client()?,
+        options: options.clone(),
+    });
 
-    let client = reqwest::Client::new();
-    let client = Arc::new(client);
+    let pages = Arc::new(Pages {
+        previous,
+        current: Mutex::new(PageCache::default()),
+    });
 
     let mut report = scan_result::ScanResult::default();
 
@@ -226,17 +391,26 @@ pub async fn full_scan(
 
     trace!(url=?root_url, "full_scan");
 
-    let links = scrape_links_from(&semaphore, &client, &root_url).await?;
+    if let Some(mut report) = read_index(&semaphore, &client, &root_url).await {
+        if let Some(filter_name) = filter_name {
+            report.filter_package(&[filter_name]);
+        }
+        let archs: Vec<&str> = archs.iter().map(String::as_str).collect();
+        report.filter_arch(&archs);
+        return Ok((report, PageCache::default()));
+    }
+
+    let links = scrape_links_from(&semaphore, &client, &pages, &root_url).await?;
 
     // split off any packages that are at the toplevel
     let (flat_package_links, links) = split_links(links, is_pkg_link);
 
     for link in flat_package_links {
         tracing::debug!("found at toplevel: {}", &link.text);
-        if let Some((pkg_name, version, arch)) = package::split_parts(&link.text) {
+        if let Some((pkg_name, _version, arch)) = package::split_parts(&link.text) {
             if let Some(info) = link.version_info() {
                 if name_filter(pkg_name, filter_name) && arch_filter(arch, &archs) {
-                    report.add_version(pkg_name, version, info.arch, None, info.filename, info.url);
+                    add_to_report(&mut report, pkg_name, &info, None);
                 }
             }
         }
@@ -262,11 +436,12 @@ pub async fn full_scan(
 
         let pkg_name = strip_slash(&link.text).to_string();
         let client = Arc::clone(&client);
+        let pages = Arc::clone(&pages);
         let report = Arc::clone(&report);
         let semaphore = Arc::clone(&semaphore);
         let archs = Arc::clone(&archs);
         let _handle = joinset.spawn(async move {
-            scan_package_dir(semaphore, client, report, pkg_name, archs, link).await
+            scan_package_dir(semaphore, client, pages, report, pkg_name, archs, link).await
         });
     }
 
@@ -274,5 +449,7 @@ pub async fn full_scan(
     joinset.join_all().await;
 
     let report = Arc::into_inner(report).unwrap().into_inner()?;
-    Ok(report)
+    let pages = Arc::into_inner(pages).unwrap().current.into_inner()?;
+    tracing::debug!("{} pages cached", pages.len());
+    Ok((report, pages))
 }
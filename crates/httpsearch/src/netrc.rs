@@ -0,0 +1,162 @@
+//! Credentials from a `.netrc` file
+//!
+//! ```ignore
+//! machine mirror.example.com
+//!     login alice
+//!     password secret
+//! default login anonymous password guest
+//! ```
+//!
+//! `macdef` macros are skipped, as are lines starting with `#`.
+
+use anyhow::Context;
+use anyhow::Result;
+use std::path::Path;
+
+#[derive(Clone, Default, PartialEq)]
+pub struct Credentials {
+    pub login: String,
+    pub password: Option<String>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("login", &self.login)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Netrc {
+    machines: Vec<(String, Credentials)>,
+    default: Option<Credentials>,
+}
+
+impl Netrc {
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid netrc file {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+
+        let mut tokens = Vec::new();
+        let mut in_macdef = false;
+        for line in text.lines() {
+            if in_macdef {
+                // a macro runs until a blank line
+                in_macdef = !line.trim().is_empty();
+                continue;
+            }
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            while let Some(word) = words.next() {
+                if word == "macdef" {
+                    words.next();
+                    in_macdef = true;
+                    break;
+                }
+                tokens.push(word);
+            }
+        }
+
+        let mut netrc = Netrc::default();
+
+        // the entry being read, None for the default entry
+        let mut current : Option<(Option<String>, Credentials)> = None;
+
+        let finish = |netrc: &mut Netrc, entry: Option<(Option<String>, Credentials)>| {
+            match entry {
+                Some((Some(machine), creds)) => netrc.machines.push((machine, creds)),
+                Some((None, creds)) => netrc.default = Some(creds),
+                None => {}
+            }
+        };
+
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => {
+                    finish(&mut netrc, current.take());
+                    let name = tokens.next().context("machine without a name")?;
+                    current = Some((Some(name.to_string()), Credentials::default()));
+                }
+                "default" => {
+                    finish(&mut netrc, current.take());
+                    current = Some((None, Credentials::default()));
+                }
+                "login" | "password" | "account" => {
+                    let value = tokens.next().with_context(|| format!("{token} without a value"))?;
+                    let Some((_, creds)) = current.as_mut() else {
+                        anyhow::bail!("{token} before any machine");
+                    };
+                    match token {
+                        "login" => creds.login = value.to_string(),
+                        "password" => creds.password = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+                other => anyhow::bail!("unexpected '{other}'"),
+            }
+        }
+        finish(&mut netrc, current.take());
+
+        Ok(netrc)
+    }
+
+    /// the credentials for a host, or the default ones
+    pub fn get(&self, host: &str) -> Option<&Credentials> {
+        self.machines.iter()
+            .find(|(machine, _)| machine.eq_ignore_ascii_case(host))
+            .map(|(_, creds)| creds)
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let netrc = Netrc::parse("
+            # mirrors
+            machine mirror.example.com
+                login alice
+                password secret
+            machine other.example.com login bob account x
+
+            macdef init
+            machine not.a.machine login eve
+
+            default login anonymous password guest
+        ")?;
+
+        let creds = netrc.get("mirror.example.com").unwrap();
+        assert_eq!(creds.login, "alice");
+        assert_eq!(creds.password.as_deref(), Some("secret"));
+
+        let creds = netrc.get("OTHER.example.com").unwrap();
+        assert_eq!(creds.login, "bob");
+        assert_eq!(creds.password, None);
+
+        assert_eq!(netrc.get("not.a.machine").unwrap().login, "anonymous");
+        assert_eq!(netrc.get("unknown.example.com").unwrap().login, "anonymous");
+        assert!(!format!("{netrc:?}").contains("secret"));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Netrc::parse("login alice").is_err());
+        assert!(Netrc::parse("machine").is_err());
+        assert!(Netrc::parse("machine a login").is_err());
+        assert!(Netrc::parse("machine a user alice").is_err());
+        assert!(Netrc::parse("").is_ok_and(|netrc| netrc.get("a").is_none()));
+    }
+}
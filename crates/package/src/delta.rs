@@ -0,0 +1,472 @@
+//! Package deltas
+//!
+//! A delta rebuilds a package file from an older version of it.
+//! The data in a package file is compressed, so a small change to it changes most of the bytes after it.
+//! A delta is instead made between the package files with their data decompressed, and when it is applied
+//! the rebuilt data is compressed again with the zstd settings that give the same bytes as in the target package.
+//! A package whose data can not be compressed to the same bytes again, by another zstd version or with
+//! other settings than `bpm pack` uses, gets no delta.
+//!
+//! The file starts with a magic string and a json header, followed by a zstd patch (see `zstd_patch`).
+//! The header holds the blake3 hashes of both package files,
+//! so a delta is only applied to the file it was made from, and the result is known to be right.
+//!
+//! ```ignore
+//! BPMDELTA <u32 header length, little endian> <header json> <patch>
+//! ```
+//!
+//! The decompressed source package is held in memory, see `zstd_patch::MAX_OLD_SIZE`.
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const DELTA_MAGIC: &[u8; 8] = b"BPMDELTA";
+
+/// largest header accepted, a header is far smaller
+const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+/// zstd levels to try when looking for the settings that compressed a package's data,
+/// `bpm pack`'s default and zstd's default first
+fn compression_candidates() -> impl Iterator<Item = (i32, bool)> {
+    const LIKELY: [i32; 2] = [15, 3];
+    LIKELY.into_iter()
+        .chain((1..=22).filter(|level| !LIKELY.contains(level)))
+        .flat_map(|level| [(level, true), (level, false)])
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeltaHeader {
+
+    /// file name of the package the delta applies to
+    pub source: String,
+
+    /// blake3 hash of the package file the delta applies to
+    pub source_hash: String,
+
+    /// file name of the package the delta rebuilds
+    pub target: String,
+
+    /// blake3 hash of the package file the delta rebuilds
+    pub target_hash: String,
+
+    /// where the data is in the package the delta rebuilds, and how to compress it
+    pub data: DataLayout,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DataLayout {
+
+    /// bytes of the package file before the compressed data
+    pub offset: u64,
+
+    /// size of the data decompressed
+    pub size: u64,
+
+    /// zstd level that compresses the data to the same bytes again
+    pub level: i32,
+
+    /// compressed with zstd's multithreaded mode, which gives other bytes than the single threaded one
+    pub multithread: bool,
+}
+
+/// passes writes through, hashing them
+struct HashingWriter<'a> {
+    inner: &'a mut dyn Write,
+    hasher: blake3::Hasher,
+}
+
+impl Write for HashingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads a range of a file.
+/// It seeks before every read, so that readers of the same file can be used in turns.
+struct RangeReader {
+    file: File,
+    pos: u64,
+    end: u64,
+}
+
+impl RangeReader {
+    fn new(file: &File, start: u64, end: u64) -> Result<Self> {
+        Ok(Self { file: file.try_clone()?, pos: start, end })
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let max = usize::try_from(self.end.saturating_sub(self.pos)).unwrap_or(usize::MAX).min(buf.len());
+        if max == 0 {
+            return Ok(0);
+        }
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let n = self.file.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// where the compressed data is in a package file
+struct Layout {
+    data_start: u64,
+    data_end: u64,
+    len: u64,
+}
+
+impl Layout {
+    fn read(file: &mut File) -> Result<Self> {
+        let len = file.metadata()?.len();
+        file.rewind()?;
+        let mut tar = tar::Archive::new(&mut *file);
+        let (entry, size) = crate::seek_to_tar_entry(crate::DATA_FILE_NAME, &mut tar)?;
+        let data_start = entry.raw_file_position();
+        Ok(Self { data_start, data_end: data_start + size, len })
+    }
+
+    /// the package file with its data decompressed
+    fn expanded(&self, file: &File) -> Result<impl Read> {
+        let prefix = RangeReader::new(file, 0, self.data_start)?;
+        let data = zstd::Decoder::new(RangeReader::new(file, self.data_start, self.data_end)?)?;
+        let suffix = RangeReader::new(file, self.data_end, self.len)?;
+        Ok(prefix.chain(data).chain(suffix))
+    }
+
+    /// The package file with its data decompressed, in memory.
+    /// Fails if that is too large to make or apply a delta with.
+    fn read_expanded(&self, file: &File) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.expanded(file)?.take(zstd_patch::MAX_OLD_SIZE + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > zstd_patch::MAX_OLD_SIZE {
+            anyhow::bail!("package is too large for a delta, more than {} bytes decompressed", zstd_patch::MAX_OLD_SIZE);
+        }
+        Ok(data)
+    }
+}
+
+fn compressor<W: Write>(level: i32, multithread: bool, out: W) -> std::io::Result<zstd::stream::write::Encoder<'static, W>> {
+    let mut encoder = zstd::stream::write::Encoder::new(out, level)?;
+    if multithread {
+        // the output is the same with any number of threads
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        encoder.multithread(threads)?;
+    }
+    Ok(encoder)
+}
+
+/// compares what is written to what a reader holds, failing at the first difference
+struct CompareWriter {
+    expected: RangeReader,
+    buf: Vec<u8>,
+    mismatch: bool,
+}
+
+impl Write for CompareWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.resize(buf.len(), 0);
+        if self.expected.read_exact(&mut self.buf).is_err() || self.buf != buf {
+            self.mismatch = true;
+            return Err(std::io::Error::other("compressed data differs"));
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The size of a package's data decompressed, if compressing it again with these settings gives the same bytes.
+fn reproduces(file: &File, layout: &Layout, level: i32, multithread: bool) -> Result<Option<u64>> {
+
+    let compare = CompareWriter {
+        expected: RangeReader::new(file, layout.data_start, layout.data_end)?,
+        buf: Vec::new(),
+        mismatch: false,
+    };
+    let mut encoder = compressor(level, multithread, compare)?;
+    let mut decoder = zstd::Decoder::new(RangeReader::new(file, layout.data_start, layout.data_end)?)?;
+
+    let finished = match std::io::copy(&mut decoder, &mut encoder) {
+        Ok(size) => encoder.try_finish().map(|compare| (size, compare)).map_err(|(encoder, e)| (encoder.get_ref().mismatch, e)),
+        Err(e) => Err((encoder.get_ref().mismatch, e)),
+    };
+    match finished {
+        Ok((size, compare)) => Ok((compare.expected.pos == layout.data_end).then_some(size)),
+        Err((true, _)) => Ok(None),
+        Err((false, e)) => Err(anyhow::Error::new(e).context("failed to read package data")),
+    }
+}
+
+/// the zstd settings that compressed a package's data, and its size decompressed
+fn find_compression(file: &File, layout: &Layout) -> Result<(i32, bool, u64)> {
+    for (level, multithread) in compression_candidates() {
+        if let Some(size) = reproduces(file, layout, level, multithread)? {
+            tracing::debug!("package data is compressed at level {level}, multithreaded: {multithread}");
+            return Ok((level, multithread, size));
+        }
+    }
+    anyhow::bail!("can't compress the package's data to the same bytes again, it was not made by this version of bpm pack")
+}
+
+/// Passes through the package file with its data decompressed, compressing the data again.
+/// The writer is only flushed outside of the data, a flush changes the compressed bytes.
+enum Repack<'a> {
+    Prefix { out: &'a mut dyn Write, remaining: u64 },
+    Data { encoder: zstd::stream::write::Encoder<'static, &'a mut dyn Write>, remaining: u64 },
+    Suffix { out: &'a mut dyn Write },
+    Failed,
+}
+
+struct Repacker<'a> {
+    stage: Repack<'a>,
+    data: DataLayout,
+}
+
+impl<'a> Repacker<'a> {
+    fn new(out: &'a mut dyn Write, data: DataLayout) -> Self {
+        Self { stage: Repack::Prefix { out, remaining: data.offset }, data }
+    }
+
+    /// move on from a stage that has all its bytes
+    fn advance(&mut self) -> std::io::Result<()> {
+        loop {
+            self.stage = match std::mem::replace(&mut self.stage, Repack::Failed) {
+                Repack::Prefix { out, remaining: 0 } => {
+                    Repack::Data { encoder: compressor(self.data.level, self.data.multithread, out)?, remaining: self.data.size }
+                }
+                Repack::Data { encoder, remaining: 0 } => {
+                    Repack::Suffix { out: encoder.finish()? }
+                }
+                stage => {
+                    self.stage = stage;
+                    return Ok(());
+                }
+            };
+        }
+    }
+
+    /// finish the data, failing if the delta did not rebuild all of it
+    fn finish(mut self) -> Result<()> {
+        self.advance()?;
+        match self.stage {
+            Repack::Suffix { out } => Ok(out.flush()?),
+            _ => anyhow::bail!("delta ended before the package data"),
+        }
+    }
+}
+
+impl Write for Repacker<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.advance()?;
+        match &mut self.stage {
+            Repack::Prefix { out, remaining } => {
+                let n = buf.len().min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                out.write_all(&buf[..n])?;
+                *remaining -= n as u64;
+                Ok(n)
+            }
+            Repack::Data { encoder, remaining } => {
+                let n = buf.len().min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                encoder.write_all(&buf[..n])?;
+                *remaining -= n as u64;
+                Ok(n)
+            }
+            Repack::Suffix { out } => out.write(buf),
+            Repack::Failed => Err(std::io::Error::other("failed to compress package data")),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.stage {
+            Repack::Prefix { out, .. } | Repack::Suffix { out } => out.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn hash_file(file: &mut File) -> Result<String> {
+    file.rewind()?;
+    Ok(bpmutil::blake3_hash_reader(&mut *file)?)
+}
+
+/// Write a delta that rebuilds the package file `new` from the package file `old`
+pub fn make_delta(old: &mut File, old_name: &str, new: &mut File, new_name: &str, out: &mut dyn Write) -> Result<DeltaHeader> {
+
+    let source_hash = hash_file(old).context("failed to read source package")?;
+    let target_hash = hash_file(new).context("failed to read target package")?;
+
+    let new_layout = Layout::read(new).context("failed to read target package")?;
+    let (level, multithread, size) = find_compression(new, &new_layout).with_context(|| format!("can't make a delta to {new_name}"))?;
+
+    let old_layout = Layout::read(old).context("failed to read source package")?;
+    let old_data = old_layout.read_expanded(old).with_context(|| format!("can't make a delta from {old_name}"))?;
+
+    let header = DeltaHeader {
+        source: old_name.to_string(),
+        source_hash,
+        target: new_name.to_string(),
+        target_hash,
+        data: DataLayout {
+            offset: new_layout.data_start,
+            size,
+            level,
+            multithread,
+        },
+    };
+    write_header(&header, out)?;
+
+    let new_size = new_layout.data_start + size + (new_layout.len - new_layout.data_end);
+    zstd_patch::make_patch(&old_data, &mut new_layout.expanded(new)?, Some(new_size), out)?;
+    Ok(header)
+}
+
+fn write_header(header: &DeltaHeader, out: &mut dyn Write) -> Result<()> {
+    let json = serde_json::to_vec(header)?;
+    out.write_all(DELTA_MAGIC)?;
+    out.write_all(&u32::try_from(json.len())?.to_le_bytes())?;
+    out.write_all(&json)?;
+    Ok(())
+}
+
+/// read the header from the start of a delta, leaving the reader at the patch
+pub fn read_header(r: &mut dyn Read) -> Result<DeltaHeader> {
+
+    let mut magic = [0u8; DELTA_MAGIC.len()];
+    r.read_exact(&mut magic).context("not a delta file")?;
+    if &magic != DELTA_MAGIC {
+        anyhow::bail!("not a delta file");
+    }
+
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_HEADER_LEN {
+        anyhow::bail!("invalid delta header");
+    }
+
+    let mut json = vec![0u8; len as usize];
+    r.read_exact(&mut json)?;
+    serde_json::from_slice(&json).context("invalid delta header")
+}
+
+/// Apply a delta to the package file `old`, writing the rebuilt package file.
+/// Fails if `old` is not the delta's source, or the result is not the delta's target.
+pub fn apply_delta(old: &mut File, delta: &mut dyn Read, out: &mut dyn Write) -> Result<DeltaHeader> {
+
+    let header = read_header(delta)?;
+
+    if hash_file(old).context("failed to read source package")? != header.source_hash {
+        anyhow::bail!("delta is for a different source package, expected {}", header.source);
+    }
+    let old_layout = Layout::read(old).context("failed to read source package")?;
+    let old_data = old_layout.read_expanded(old)?;
+
+    let mut out = HashingWriter { inner: out, hasher: blake3::Hasher::new() };
+    let mut repacker = Repacker::new(&mut out, header.data);
+    zstd_patch::apply_patch(&old_data, delta, &mut repacker)?;
+    repacker.finish()?;
+
+    if out.hasher.finalize().to_hex().as_str() != header.target_hash {
+        anyhow::bail!("rebuilt package does not match {}", header.target);
+    }
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// a package file with `data` compressed as its data file
+    fn package(meta: &[u8], data: &[u8], level: i32, multithread: bool) -> Result<File> {
+        let mut compressed = compressor(level, multithread, Vec::new())?;
+        compressed.write_all(data)?;
+        let compressed = compressed.finish()?;
+
+        let mut tar = tar::Builder::new(tempfile::tempfile()?);
+        for (name, contents) in [("CONTROL", b"version = 1".as_slice()), (crate::META_FILE_NAME, meta), (crate::DATA_FILE_NAME, &compressed)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_entry_type(tar::EntryType::Regular);
+            tar.append_data(&mut header, name, contents)?;
+        }
+        let mut file = tar.into_inner()?;
+        file.rewind()?;
+        Ok(file)
+    }
+
+    fn read_all(file: &mut File) -> Result<Vec<u8>> {
+        file.rewind()?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let old_data: Vec<u8> = (0..500_000u32).flat_map(|i| (i * 7919 % 65_521).to_le_bytes()).collect();
+        let mut new_data = old_data.clone();
+        new_data[100..104].copy_from_slice(b"abcd");
+
+        for (level, multithread) in [(15, true), (3, false)] {
+            let mut old = package(b"{\"version\": \"1.0.0\"}", &old_data, level, multithread)?;
+            let mut new = package(b"{\"version\": \"1.1.0\"}", &new_data, level, multithread)?;
+
+            let mut delta = Vec::new();
+            let header = make_delta(&mut old, "foo_1.0.0.bpm", &mut new, "foo_1.1.0.bpm", &mut delta)?;
+            assert_eq!(read_header(&mut delta.as_slice())?, header);
+            assert_eq!(header.data.level, level);
+            assert_eq!(header.data.size, new_data.len() as u64);
+
+            // a change in the data is small in the delta, even though it changes the compressed data after it
+            assert!(delta.len() < 2000, "delta is {} bytes", delta.len());
+
+            let mut rebuilt = Vec::new();
+            apply_delta(&mut old, &mut delta.as_slice(), &mut rebuilt)?;
+            assert_eq!(rebuilt, read_all(&mut new)?);
+
+            // refuse the wrong source
+            let mut other = package(b"{\"version\": \"1.0.1\"}", &old_data, level, multithread)?;
+            let mut rebuilt = Vec::new();
+            assert!(apply_delta(&mut other, &mut delta.as_slice(), &mut rebuilt).is_err());
+            assert!(rebuilt.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn not_reproducible() -> Result<()> {
+        let data: Vec<u8> = (0..100_000u32).flat_map(|i| (i % 1000).to_le_bytes()).collect();
+        let mut old = package(b"{}", &data, 3, false)?;
+
+        // compressed with a checksum, which no candidate adds
+        let mut compressed = zstd::stream::write::Encoder::new(Vec::new(), 3)?;
+        compressed.include_checksum(true)?;
+        compressed.write_all(&data)?;
+        let compressed = compressed.finish()?;
+        let mut tar = tar::Builder::new(tempfile::tempfile()?);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(compressed.len() as u64);
+        header.set_entry_type(tar::EntryType::Regular);
+        tar.append_data(&mut header, crate::DATA_FILE_NAME, compressed.as_slice())?;
+        let mut new = tar.into_inner()?;
+
+        let mut delta = Vec::new();
+        assert!(make_delta(&mut old, "foo_1.0.0.bpm", &mut new, "foo_1.1.0.bpm", &mut delta).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn not_a_delta() {
+        assert!(read_header(&mut &b"BPMDELTX\x02\x00\x00\x00{}"[..]).is_err());
+        assert!(read_header(&mut &b"BPM"[..]).is_err());
+    }
+}
@@ -17,9 +17,14 @@ use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Read, Write, Seek};
 
+pub mod delta;
+pub mod sign;
+
 pub const PKG_FILE_EXTENSION: &str = "bpm";
 pub const DOTTED_PKG_FILE_EXTENSION: &str = ".bpm";
 
+pub const DELTA_FILE_EXTENSION: &str = "bpmdelta";
+
 pub type PkgName = String;
 pub type Version = String;
 pub type FilePath = Utf8PathBuf;
@@ -553,6 +558,18 @@ pub fn make_packagefile_name(pkg_name: &str, version: &str, arch: Option<&str>)
     }
 }
 
+/// Name of a delta file that rebuilds a package file from an older version of it,
+/// like "foo_1.0.0_to_1.1.0.bpmdelta" or "baz_1.0.0_to_1.1.0_linux64.bpmdelta"
+pub fn make_delta_file_name(pkg_name: &str, from: &str, to: &str, arch: Option<&str>) -> String {
+
+    match arch {
+        Some(arch) if !arch.is_empty() =>
+            format!("{pkg_name}_{from}_to_{to}_{arch}.{DELTA_FILE_EXTENSION}"),
+        _ =>
+            format!("{pkg_name}_{from}_to_{to}.{DELTA_FILE_EXTENSION}")
+    }
+}
+
 /// Names like "foo_1.0.0.bpm", "bar_0.2.1.bpm", "baz_1.2.3_linux64.bpm" are packagefile names
 pub fn is_packagefile_name(text: &str) -> bool {
     if text.ends_with(DOTTED_PKG_FILE_EXTENSION) {
@@ -749,6 +766,10 @@ mod test {
 
         assert!(is_packagefile_name(&make_packagefile_name("foo", "1.2.3", None)));
         assert_eq!(split_parts(&make_packagefile_name("foo", "1.2.3", None)), Some(("foo", "1.2.3", None)));
+
+        assert_eq!(make_delta_file_name("foo", "1.0.0", "1.1.0", None), "foo_1.0.0_to_1.1.0.bpmdelta");
+        assert_eq!(make_delta_file_name("foo", "1.0.0", "1.1.0", Some("linux64")), "foo_1.0.0_to_1.1.0_linux64.bpmdelta");
+        assert!(!is_packagefile_name(&make_delta_file_name("foo", "1.0.0", "1.1.0", None)));
     }
 
     fn get_instance() -> MetaData {
@@ -0,0 +1,192 @@
+//! Package signing
+//!
+//! A package is signed by signing the raw bytes of its `meta.json` with an ed25519 key.
+//! The metadata holds the hash of the data file, so the signature covers the whole package.
+//! The signature is stored as `signature.json` in the outer tar, next to `meta.json`.
+//!
+//! A repository index is signed the same way, with the signature in a detached file next to it.
+//!
+//! Keys are hex strings. A secret key file holds the 32 byte secret seed.
+
+use anyhow::Context;
+use anyhow::Result;
+use camino::Utf8Path;
+use ed25519_dalek::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+pub const SIGNATURE_FILE_NAME: &str = "signature.json";
+
+/// contents of `signature.json`, or of a detached signature file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Signature {
+    /// public key of the signer, hex
+    pub key: String,
+
+    /// ed25519 signature of the raw signed bytes, hex
+    pub signature: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex<const N: usize>(text: &str) -> Result<[u8; N]> {
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        anyhow::bail!("expected {} hex characters", N * 2);
+    }
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).context("invalid hex")?;
+    }
+    Ok(out)
+}
+
+/// create a new random signing key
+pub fn generate_key() -> SigningKey {
+    SigningKey::from_bytes(&rand::random::<[u8; 32]>())
+}
+
+/// the contents of a secret key file for a key
+pub fn secret_key_string(key: &SigningKey) -> String {
+    to_hex(key.as_bytes())
+}
+
+pub fn public_key_string(key: &VerifyingKey) -> String {
+    to_hex(key.as_bytes())
+}
+
+pub fn parse_public_key(text: &str) -> Result<VerifyingKey> {
+    let bytes = from_hex::<32>(text).with_context(|| format!("invalid public key '{text}'"))?;
+    VerifyingKey::from_bytes(&bytes).with_context(|| format!("invalid public key '{text}'"))
+}
+
+pub fn read_secret_key(path: &Utf8Path) -> Result<SigningKey> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read key file {path}"))?;
+    let bytes = from_hex::<32>(&text).with_context(|| format!("invalid secret key in {path}"))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// sign raw bytes, such as a `meta.json` or a repository index
+pub fn sign(key: &SigningKey, data: &[u8]) -> Signature {
+    Signature {
+        key: public_key_string(&key.verifying_key()),
+        signature: to_hex(&key.sign(data).to_bytes()),
+    }
+}
+
+impl Signature {
+
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn from_reader<R: Read>(r: &mut R) -> Result<Self> {
+        serde_json::from_reader(r).context("invalid signature file")
+    }
+
+    /// check that this is a valid signature of `data` by `self.key`
+    pub fn verify(&self, data: &[u8]) -> Result<VerifyingKey> {
+        let key = parse_public_key(&self.key)?;
+        let signature = ed25519_dalek::Signature::from_bytes(&from_hex::<64>(&self.signature).context("invalid signature")?);
+        key.verify(data, &signature).map_err(|_| anyhow::anyhow!("bad signature"))?;
+        Ok(key)
+    }
+}
+
+/// read `signature.json` from a package file, if it is signed
+pub fn read_signature(pkg_file: &mut File) -> Result<Option<Signature>> {
+    pkg_file.rewind()?;
+    let mut tar = tar::Archive::new(pkg_file);
+    for entry in tar.entries_with_seek().context("failed to read tar archive")? {
+        let mut entry = entry.context("failed to read tar archive")?;
+        if entry.path().is_ok_and(|p| p == Utf8Path::new(SIGNATURE_FILE_NAME)) {
+            return Ok(Some(Signature::from_reader(&mut entry)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Which keys a package must be signed with
+#[derive(Clone, Debug, Default)]
+pub struct TrustPolicy {
+
+    /// Trusted public keys. When empty, signatures are not checked.
+    pub keys: Vec<VerifyingKey>,
+
+    /// accept packages that are unsigned or signed by an untrusted key
+    pub allow_unsigned: bool,
+}
+
+impl TrustPolicy {
+
+    pub fn enforced(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Check the signature of a package file against the trusted keys.
+    /// A signature that does not verify is always rejected.
+    pub fn check(&self, pkg_file: &mut File) -> Result<()> {
+
+        if !self.enforced() {
+            return Ok(());
+        }
+
+        let Some(signature) = read_signature(pkg_file)? else {
+            if self.allow_unsigned {
+                tracing::debug!("accepting unsigned package");
+                return Ok(());
+            }
+            anyhow::bail!("package is not signed");
+        };
+
+        let metadata_raw = crate::read_metadata(pkg_file)?;
+        let key = signature.verify(&metadata_raw).context("package signature is invalid")?;
+
+        if self.keys.contains(&key) {
+            return Ok(());
+        }
+
+        if self.allow_unsigned {
+            tracing::debug!(key = signature.key, "accepting package signed by an untrusted key");
+            return Ok(());
+        }
+
+        anyhow::bail!("package is signed by an untrusted key {}", signature.key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sign_verify() -> Result<()> {
+        let key = generate_key();
+        let signature = sign(&key, b"{\"name\":\"foo\"}");
+
+        assert_eq!(signature.verify(b"{\"name\":\"foo\"}")?, key.verifying_key());
+        assert!(signature.verify(b"{\"name\":\"bar\"}").is_err());
+
+        let other = generate_key();
+        let forged = Signature { key: public_key_string(&other.verifying_key()), ..signature.clone() };
+        assert!(forged.verify(b"{\"name\":\"foo\"}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn key_strings() -> Result<()> {
+        let key = generate_key();
+        let public = public_key_string(&key.verifying_key());
+        assert_eq!(public.len(), 64);
+        assert_eq!(parse_public_key(&public)?, key.verifying_key());
+        assert_eq!(from_hex::<32>(&secret_key_string(&key))?, *key.as_bytes());
+        assert!(parse_public_key("abc").is_err());
+        assert!(parse_public_key(&"zz".repeat(32)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Repository index
+//!
+//! A provider can publish an `index.json` at its root, a snapshot of the packages it holds.
+//! Package uris in an index are relative to the provider root.
+//! The index may be compressed with zstd, it is still named `index.json`.
+//!
+//! The index may be signed with a detached signature in `index.json.sig`.
+//! Every time an index is regenerated its sequence number increases, which lets a client
+//! refuse an index older than one it has already seen.
+
+use anyhow::Context;
+use anyhow::Result;
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::ScanResult;
+
+pub const INDEX_FILE_NAME: &str = "index.json";
+pub const INDEX_SIGNATURE_FILE_NAME: &str = "index.json.sig";
+
+/// newest index format this crate understands
+pub const INDEX_FORMAT: u32 = 1;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+
+    pub format: u32,
+
+    /// increases every time the index is regenerated
+    pub sequence: u64,
+
+    pub created: DateTime<Utc>,
+
+    /// the index must not be used after this time
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+
+    #[serde(flatten)]
+    pub packages: ScanResult,
+}
+
+impl Index {
+
+    pub fn new(sequence: u64, packages: ScanResult) -> Self {
+        Self {
+            format: INDEX_FORMAT,
+            sequence,
+            created: Utc::now().round_subsecs(0),
+            expires: None,
+            packages,
+        }
+    }
+
+    /// parse an index, decompressing it first if it is zstd compressed
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        let index: Self = if data.starts_with(&ZSTD_MAGIC) {
+            let data = zstd::decode_all(data).context("invalid compressed index")?;
+            serde_json::from_slice(&data).context("invalid index")?
+        } else {
+            serde_json::from_slice(data).context("invalid index")?
+        };
+        if index.format > INDEX_FORMAT {
+            anyhow::bail!("unsupported index format {}", index.format);
+        }
+        Ok(index)
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn to_vec_compressed(&self, level: i32) -> Result<Vec<u8>> {
+        let data = serde_json::to_vec(self)?;
+        Ok(zstd::encode_all(data.as_slice(), level)?)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// rewrite the uri of every package entry and delta
+    pub fn map_uris<F: FnMut(&str) -> Result<String>>(&mut self, mut f: F) -> Result<()> {
+        for vinfo in self.packages.packages.values_mut()
+            .flat_map(|pinfo| pinfo.versions.values_mut())
+            .flat_map(|vlist| vlist.iter_mut())
+        {
+            vinfo.uri = f(&vinfo.uri)?;
+            for delta in vinfo.deltas.values_mut() {
+                delta.uri = f(&delta.uri)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut packages = ScanResult::default();
+        let vinfo = packages.add_version("foo", "1.0.0", None, Some("stable"), "foo_1.0.0.bpm", "foo/foo_1.0.0.bpm");
+        vinfo.size = Some(1234);
+        vinfo.blake3 = Some("ab".repeat(32));
+
+        let mut index = Index::new(7, packages);
+        index.expires = Some(index.created + chrono::Duration::days(1));
+
+        let data = index.to_vec()?;
+        let back = Index::from_slice(&data)?;
+        assert_eq!(back, index);
+
+        let data = index.to_vec_compressed(3)?;
+        assert!(data.starts_with(&ZSTD_MAGIC));
+        assert_eq!(Index::from_slice(&data)?, index);
+
+        assert!(!back.is_expired(index.created));
+        assert!(back.is_expired(index.created + chrono::Duration::days(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn map_uris() -> Result<()> {
+        let mut packages = ScanResult::default();
+        packages.add_version("foo", "1.0.0", None, None, "foo_1.0.0.bpm", "foo/foo_1.0.0.bpm");
+        packages.add_version("bar", "2.0.0", Some("x86_64"), None, "bar_2.0.0_x86_64.bpm", "bar_2.0.0_x86_64.bpm");
+
+        let mut index = Index::new(1, packages);
+        index.map_uris(|uri| Ok(format!("/repo/{uri}")))?;
+
+        let uris: Vec<&str> = index.packages.packages.values()
+            .flat_map(|pinfo| pinfo.versions.values())
+            .flatten()
+            .map(|vinfo| vinfo.uri.as_str())
+            .collect();
+        assert_eq!(uris, ["/repo/bar_2.0.0_x86_64.bpm", "/repo/foo/foo_1.0.0.bpm"]);
+        Ok(())
+    }
+
+    #[test]
+    fn newer_format() {
+        let data = br#"{"format": 99, "sequence": 1, "created": "2024-01-01T00:00:00Z", "packages": {}}"#;
+        assert!(Index::from_slice(data).is_err());
+    }
+}
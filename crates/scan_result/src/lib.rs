@@ -1,4 +1,7 @@
 
+pub mod index;
+
+use chrono::{DateTime, Utc};
 use std::collections::{BTreeMap, HashMap};
 use serde::{Serialize, Deserialize};
 
@@ -14,6 +17,33 @@ pub struct VersionInfo {
     pub filename: String,
     pub channels: Vec<ChannelName>,
     pub arch: Option<ArchName>,
+
+    /// patches that rebuild this package file from an older version's, by the older version
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub deltas: BTreeMap<String, DeltaInfo>,
+
+    /// size of the package file in bytes, if the provider lists it
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+
+    /// when the package file was last modified, if the provider lists it
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<DateTime<Utc>>,
+
+    /// blake3 hash of the package file, if the provider lists it
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+/// a patch from one package file to another
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeltaInfo {
+    pub uri: String,
+    pub size: u64,
 }
 
 impl VersionInfo {
@@ -48,6 +78,7 @@ pub struct ScanResult {
 impl ScanResult {
 
     /// add a name, version, arch, ?channel?
+    /// returns the entry, which may have been added before
     pub fn add_version<S: Into<String>>(
         &mut self,
         pkg_name: &str,
@@ -56,24 +87,31 @@ impl ScanResult {
         channel: Option<&str>,
         filename: S,
         uri: S
-    ) {
+    ) -> &mut VersionInfo {
 
         let version = VersionString::from(version);
 
         let pinfo = self.packages.entry(pkg_name.into()).or_default();
         let vlist = pinfo.versions.entry(version).or_default();
-        if let Some(existing) = vlist.iter_mut().find(|ent| ent.arch.as_deref() == arch) {
-            existing.add_channel(channel);
-        } else {
-            let mut vinfo = VersionInfo{
-                uri: uri.into(),
-                filename: filename.into(),
-                channels: vec![],
-                arch: arch.map(String::from),
-            };
-            vinfo.add_channel(channel);
-            vlist.push(vinfo);
-        }
+        let index = match vlist.iter().position(|ent| ent.arch.as_deref() == arch) {
+            Some(index) => index,
+            None => {
+                vlist.push(VersionInfo{
+                    uri: uri.into(),
+                    filename: filename.into(),
+                    channels: vec![],
+                    arch: arch.map(String::from),
+                    deltas: BTreeMap::new(),
+                    size: None,
+                    mtime: None,
+                    blake3: None,
+                });
+                vlist.len() - 1
+            }
+        };
+        let vinfo = &mut vlist[index];
+        vinfo.add_channel(channel);
+        vinfo
     }
 
     /// apply a channel to all entries for that version
@@ -167,9 +205,9 @@ impl ScanResult {
 
     /// count of all versions+archs for all packages
     pub fn unique_count(&self) -> usize {
-        self.packages.iter()
-            .flat_map(|(_pname, pinfo)| pinfo.versions.iter())
-            .map(|(_version, vlist)| vlist.len())
+        self.packages.values()
+            .flat_map(|pinfo| pinfo.versions.values())
+            .map(|vlist| vlist.len())
             .sum()
     }
 
@@ -216,8 +254,8 @@ impl PackageInfo {
     }
 
     pub fn has_channel(&self, c: &str) -> bool {
-        self.versions.iter()
-            .flat_map(|(_version, vlist)| vlist.iter())
+        self.versions.values()
+            .flat_map(|vlist| vlist.iter())
             .flat_map(|ent| ent.channels.iter())
             .any(|chan| chan == c)
     }
@@ -238,6 +276,9 @@ impl PackageInfo {
 
 pub trait Scan {
     fn scan(&self, arch_filter: Option<&[&str]>) -> anyhow::Result<ScanResult>;
+
+    /// scan for a single package
+    fn scan_package(&self, pkg_name: &str, arch_filter: Option<&[&str]>) -> anyhow::Result<ScanResult>;
 }
 
 #[cfg(test)]
@@ -1,5 +1,8 @@
 use serde::{Serialize, Deserialize};
 
+mod req;
+pub use req::*;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Version {
     raw: VersionString,
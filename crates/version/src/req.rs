@@ -0,0 +1,315 @@
+//! Version requirements, such as `>=1.2, <2`, `^1.4`, or `~1.4.2`
+//!
+//! Comparisons use the bpm version ordering, not semver ordering.
+//! A requirement is a comma separated list of comparators, all of which must match.
+//!
+//! - `*` any version
+//! - `1.2` or `=1.2` exactly 1.2
+//! - `>1.2`, `>=1.2`, `<1.2`, `<=1.2`
+//! - `^1.4` at least 1.4, but less than 2. (`^0.4` is less than 0.5, `^0.0.4` is less than 0.0.5)
+//! - `~1.4.2` at least 1.4.2, but less than 1.5. (`~1` is less than 2)
+
+use crate::VersionString;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Any,
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Caret,
+    Tilde,
+}
+
+impl Op {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Op::Any       => "*",
+            Op::Exact     => "=",
+            Op::Greater   => ">",
+            Op::GreaterEq => ">=",
+            Op::Less      => "<",
+            Op::LessEq    => "<=",
+            Op::Caret     => "^",
+            Op::Tilde     => "~",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparator {
+    pub op: Op,
+    pub version: VersionString,
+
+    /// exclusive upper bound for `^` and `~`
+    upper: Option<VersionString>,
+}
+
+impl Comparator {
+
+    fn parse(s: &str) -> Result<Self, ReqParseError> {
+
+        let s = s.trim();
+        if s == "*" {
+            return Ok(Self { op: Op::Any, version: "*".into(), upper: None });
+        }
+
+        // longest operators first
+        let (op, rest) = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">",  Op::Greater),
+            ("<",  Op::Less),
+            ("=",  Op::Exact),
+            ("^",  Op::Caret),
+            ("~",  Op::Tilde),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| s.strip_prefix(prefix).map(|rest| (op, rest)))
+        .unwrap_or((Op::Exact, s));
+
+        let version = rest.trim();
+        if version.is_empty() {
+            return Err(ReqParseError(format!("missing version in '{s}'")));
+        }
+        if version.contains(|c: char| c.is_whitespace() || "<>=^~*,".contains(c)) {
+            return Err(ReqParseError(format!("invalid version '{version}' in '{s}'")));
+        }
+
+        let upper = match op {
+            Op::Caret | Op::Tilde => Some(upper_bound(op, version).ok_or_else(|| {
+                ReqParseError(format!("'{}' requires a version starting with a number, got '{version}'", op.as_str()))
+            })?),
+            _ => None,
+        };
+
+        Ok(Self { op, version: version.into(), upper })
+    }
+
+    pub fn matches(&self, v: &VersionString) -> bool {
+        match self.op {
+            Op::Any       => true,
+            Op::Exact     => *v == self.version,
+            Op::Greater   => *v >  self.version,
+            Op::GreaterEq => *v >= self.version,
+            Op::Less      => *v <  self.version,
+            Op::LessEq    => *v <= self.version,
+            Op::Caret |
+            Op::Tilde     => *v >= self.version && self.upper.as_ref().is_some_and(|upper| v < upper),
+        }
+    }
+}
+
+impl std::fmt::Display for Comparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.op {
+            Op::Any => write!(f, "*"),
+            op => write!(f, "{}{}", op.as_str(), self.version),
+        }
+    }
+}
+
+/// Get the leading numeric components of a version.
+/// "1.4.2-rc1" -> [1, 4, 2]
+fn numeric_parts(v: &str) -> Vec<u64> {
+    let mut parts = Vec::new();
+    for part in v.split('.') {
+        let digits = part.find(|c: char| !c.is_ascii_digit()).unwrap_or(part.len());
+        match part[..digits].parse::<u64>() {
+            Ok(n) => parts.push(n),
+            Err(_) => break,
+        }
+        if digits != part.len() {
+            break;
+        }
+    }
+    parts
+}
+
+/// the exclusive upper bound of a `^` or `~` comparator
+fn upper_bound(op: Op, v: &str) -> Option<VersionString> {
+
+    let parts = numeric_parts(v);
+    if parts.is_empty() {
+        return None;
+    }
+
+    // index of the component that gets incremented
+    let idx = match op {
+        Op::Caret => parts.iter().position(|n| *n != 0).unwrap_or(parts.len() - 1),
+        _ => if parts.len() > 1 { 1 } else { 0 },
+    };
+
+    let mut upper = parts[..=idx].to_vec();
+    upper[idx] += 1;
+    let upper = upper.iter().map(u64::to_string).collect::<Vec<_>>().join(".");
+    Some(upper.into())
+}
+
+/// A set of comparators that a version must satisfy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+
+    pub fn parse(s: &str) -> Result<Self, ReqParseError> {
+        if s.trim().is_empty() {
+            return Err(ReqParseError("empty version requirement".into()));
+        }
+        let comparators = s.split(',')
+            .map(Comparator::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { comparators })
+    }
+
+    /// Does a string use requirement syntax, as opposed to being a plain version or channel name?
+    pub fn is_req(s: &str) -> bool {
+        let s = s.trim_start();
+        s.contains(',') || s.starts_with(['*', '=', '<', '>', '^', '~'])
+    }
+
+    pub fn matches(&self, v: &str) -> bool {
+        let v = VersionString::from(v);
+        self.comparators.iter().all(|c| c.matches(&v))
+    }
+
+    /// If this requirement is a single exact version, get that version
+    pub fn exact(&self) -> Option<&str> {
+        match self.comparators.as_slice() {
+            [Comparator { op: Op::Exact, version, .. }] => Some(version.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn comparators(&self) -> &[Comparator] {
+        &self.comparators
+    }
+}
+
+impl std::str::FromStr for VersionReq {
+    type Err = ReqParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl std::fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, c) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{c}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReqParseError(String);
+
+impl std::fmt::Display for ReqParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid version requirement: {}", self.0)
+    }
+}
+
+impl std::error::Error for ReqParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(s: &str) -> VersionReq {
+        VersionReq::parse(s).unwrap()
+    }
+
+    #[test]
+    fn ranges() {
+        let r = req(">=1.2, <2");
+        assert!(!r.matches("1.1"));
+        assert!(r.matches("1.2"));
+        assert!(r.matches("1.10"));
+        assert!(r.matches("1.99.3-beta"));
+        assert!(!r.matches("2"));
+        assert!(!r.matches("2.0.0-rc1"));
+        assert!(!r.matches("3"));
+
+        let r = req(">1.2,<=1.4");
+        assert!(!r.matches("1.2"));
+        assert!(r.matches("1.3"));
+        assert!(r.matches("1.4"));
+        assert!(!r.matches("1.4.1"));
+    }
+
+    #[test]
+    fn caret() {
+        let r = req("^1.4");
+        assert!(!r.matches("1.3.9"));
+        assert!(r.matches("1.4"));
+        assert!(r.matches("1.4.0"));
+        assert!(r.matches("1.20"));
+        assert!(!r.matches("2"));
+        assert!(!r.matches("2.0"));
+
+        let r = req("^0.4.1");
+        assert!(r.matches("0.4.7"));
+        assert!(!r.matches("0.5"));
+
+        let r = req("^0.0.4");
+        assert!(r.matches("0.0.4"));
+        assert!(!r.matches("0.0.5"));
+    }
+
+    #[test]
+    fn tilde() {
+        let r = req("~1.4.2");
+        assert!(!r.matches("1.4.1"));
+        assert!(r.matches("1.4.2"));
+        assert!(r.matches("1.4.10"));
+        assert!(!r.matches("1.5"));
+
+        let r = req("~1");
+        assert!(r.matches("1.9"));
+        assert!(!r.matches("2"));
+    }
+
+    #[test]
+    fn exact_and_any() {
+        assert!(req("*").matches("anything"));
+        assert!(req("1.2.3").matches("1.2.3"));
+        assert!(req("= 1.2.3").matches("1.2.3"));
+        assert!(!req("1.2.3").matches("1.2.4"));
+        assert_eq!(req("=1.2").exact(), Some("1.2"));
+        assert_eq!(req("^1.2").exact(), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(VersionReq::parse("").is_err());
+        assert!(VersionReq::parse(">=").is_err());
+        assert!(VersionReq::parse(">=1, ").is_err());
+        assert!(VersionReq::parse("^beta").is_err());
+        assert!(VersionReq::parse(">>1").is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(req(">=1.2,<2").to_string(), ">=1.2, <2");
+        assert_eq!(req("^1.4").to_string(), "^1.4");
+        assert_eq!(req("1.4").to_string(), "=1.4");
+    }
+
+    #[test]
+    fn is_req() {
+        assert!(VersionReq::is_req("^1.4"));
+        assert!(VersionReq::is_req(">=1, <2"));
+        assert!(VersionReq::is_req("*"));
+        assert!(!VersionReq::is_req("1.4"));
+        assert!(!VersionReq::is_req("stable"));
+    }
+}
@@ -0,0 +1,100 @@
+//! Patch files with zstd
+//!
+//! A patch is the new file compressed with the old file as a zstd prefix.
+//! When the files are similar, most of the new file is encoded as references into the old one.
+//! The old file is held in memory while making or applying a patch.
+
+use anyhow::Result;
+use std::io::{Read, Write};
+
+/// largest window used when making a patch, and accepted when applying one
+const WINDOW_LOG_MAX: u32 = 31;
+
+/// smallest window used when making a patch
+const WINDOW_LOG_MIN: u32 = 10;
+
+/// Largest old file a patch is made from.
+/// A reference can only reach back one window, the start of a larger old file could not be referenced.
+pub const MAX_OLD_SIZE: u64 = 1 << WINDOW_LOG_MAX;
+
+const LEVEL: i32 = 12;
+
+fn get_threads() -> u32 {
+    std::thread::available_parallelism().map_or(1, |v| v.get() as u32)
+}
+
+/// The window that reaches from the end of the new file back to the start of the old one.
+/// With no size for the new file, the largest window.
+fn window_log(old_size: u64, new_size: Option<u64>) -> u32 {
+    match new_size {
+        Some(new_size) => {
+            let total = old_size.saturating_add(new_size).max(1);
+            let log = u64::BITS - (total - 1).leading_zeros();
+            log.clamp(WINDOW_LOG_MIN, WINDOW_LOG_MAX)
+        }
+        None => WINDOW_LOG_MAX,
+    }
+}
+
+/// Write a patch that rebuilds `new` from `old`.
+/// `new_size` is used to size the window, and should be given when known.
+/// Fails if `old` is larger than `MAX_OLD_SIZE`.
+pub fn make_patch(old: &[u8], new: &mut dyn Read, new_size: Option<u64>, out: &mut dyn Write) -> Result<()> {
+
+    if old.len() as u64 > MAX_OLD_SIZE {
+        anyhow::bail!("old file is too large to patch from, {} bytes, at most {MAX_OLD_SIZE}", old.len());
+    }
+
+    let mut compressor = zstd::stream::write::Encoder::with_ref_prefix(out, LEVEL, old)?;
+    compressor.long_distance_matching(true)?;
+    compressor.set_parameter(zstd::zstd_safe::CParameter::WindowLog(window_log(old.len() as u64, new_size)))?;
+    compressor.set_parameter(zstd::zstd_safe::CParameter::TargetLength(4096))?;
+    compressor.multithread(get_threads())?;
+
+    std::io::copy(new, &mut compressor)?;
+    compressor.finish()?;
+    Ok(())
+}
+
+/// Apply a patch to `old`, writing the new file. Returns the size of the new file.
+pub fn apply_patch(old: &[u8], patch: &mut dyn Read, out: &mut dyn Write) -> Result<u64> {
+
+    let mut decompressor = zstd::stream::read::Decoder::with_ref_prefix(std::io::BufReader::new(patch), old)?;
+    decompressor.set_parameter(zstd::zstd_safe::DParameter::WindowLogMax(WINDOW_LOG_MAX))?;
+
+    let n = std::io::copy(&mut decompressor, out)?;
+    out.flush()?;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let old: Vec<u8> = (0..200_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        let mut new = old.clone();
+        new[1000..1010].copy_from_slice(b"0123456789");
+        new.extend_from_slice(b"appended");
+
+        let mut patch = Vec::new();
+        make_patch(&old, &mut new.as_slice(), Some(new.len() as u64), &mut patch)?;
+        assert!(patch.len() < new.len() / 10);
+
+        let mut rebuilt = Vec::new();
+        let n = apply_patch(&old, &mut patch.as_slice(), &mut rebuilt)?;
+        assert_eq!(n, new.len() as u64);
+        assert_eq!(rebuilt, new);
+        Ok(())
+    }
+
+    #[test]
+    fn window() {
+        assert_eq!(window_log(0, Some(0)), WINDOW_LOG_MIN);
+        assert_eq!(window_log(1 << 20, Some(1 << 20)), 21);
+        assert_eq!(window_log((1 << 20) + 1, Some(1 << 20)), 22);
+        assert_eq!(window_log(MAX_OLD_SIZE, Some(1 << 30)), WINDOW_LOG_MAX);
+        assert_eq!(window_log(1, None), WINDOW_LOG_MAX);
+    }
+}
@@ -86,7 +86,7 @@ fn make_patch(oldfile: &str, newfile: &str, output: Option<&String>) -> Result<(
 
     let newfile = std::fs::File::open(newfile).expect("failed to open file");
     let new_file_sz = newfile.metadata()?.len();
-    let mut newfile : Box<dyn Read> = if new_compressed {
+    let newfile : Box<dyn Read> = if new_compressed {
         Box::new(zstd::stream::read::Decoder::new(newfile)?)
     } else {
         Box::new(newfile)
@@ -98,19 +98,6 @@ fn make_patch(oldfile: &str, newfile: &str, output: Option<&String>) -> Result<(
     std::io::copy(&mut BufReader::new(&mut oldfile), &mut dict_data)?;
     eprintln!("dictionary size: {}", bytesize::to_string(dict_data.len() as u64, true));
 
-    let dict = zstd::dict::EncoderDictionary::new(&dict_data[..], 12);
-
-    let mut compressor = zstd::stream::write::Encoder::with_prepared_dictionary(&mut outfile, &dict)?;
-    compressor.long_distance_matching(true)?;
-    //compressor.set_pledged_src_size(Some(new_file_sz))?;
-    //compressor.window_log(31)?;
-    compressor.set_parameter(zstd::zstd_safe::CParameter::WindowLog(31))?;
-    //compressor.set_parameter(zstd::zstd_safe::CParameter::ForceMaxWindow(true))?;
-    compressor.set_parameter(zstd::zstd_safe::CParameter::TargetLength(4096))?;
-    //compressor.set_parameter(zstd::zstd_safe::CParameter::ChainLog(30))?;
-    compressor.multithread(get_threads())?;
-    let mut compressor = compressor.auto_finish();
-
     let pb = ProgressBar::new(new_file_sz);
         //.with_style(ProgressStyle::with_template("{elapsed} {eta} {wide_bar:.blue/white} {bytes}/{total_bytes}").unwrap());
     if new_compressed {
@@ -120,15 +107,9 @@ fn make_patch(oldfile: &str, newfile: &str, output: Option<&String>) -> Result<(
     }
     pb.enable_steady_tick(std::time::Duration::from_millis(250));
 
-    let mut buf = [0u8; 4096];
-    loop {
-        let n = newfile.read(&mut buf[..])?;
-        if n == 0 {
-            break;
-        }
-        compressor.write_all(&buf[0..n])?;
-        pb.inc(n as u64);
-    }
+    // the size of a compressed file says nothing about how large it is decompressed
+    let new_size = (!new_compressed).then_some(new_file_sz);
+    zstd_patch::make_patch(&dict_data, &mut pb.wrap_read(newfile), new_size, &mut outfile)?;
 
     pb.finish_and_clear();
 
@@ -149,11 +130,10 @@ fn apply_patch(oldfile: &str, patchfile: &str, output: Option<&String>) -> Resul
         Box::new(oldfile)
     };
 
-    let patchfile = std::fs::File::open(patchfile).expect("failed to open file");
-    let mut patchfile = BufReader::new(patchfile);
+    let mut patchfile = std::fs::File::open(patchfile).expect("failed to open file");
 
     let outfile = std::fs::File::create(output.expect("no out file")).expect("failed to open file");
-    let mut outfile : Box<dyn Write> = if out_compressed {
+    let outfile : Box<dyn Write> = if out_compressed {
         let mut enc = zstd::stream::write::Encoder::new(outfile, 3)?;
         enc.multithread(get_threads())?;
         let enc = enc.auto_finish();
@@ -165,26 +145,12 @@ fn apply_patch(oldfile: &str, patchfile: &str, output: Option<&String>) -> Resul
     let mut dict_data = Vec::new();
     let _n = std::io::copy(&mut BufReader::new(oldfile), &mut dict_data)?;
 
-    let dict = zstd::dict::DecoderDictionary::new(&dict_data[..]);
-
-    let mut decompressor = zstd::stream::read::Decoder::with_prepared_dictionary(&mut patchfile, &dict)?;
-    decompressor.set_parameter(zstd::zstd_safe::DParameter::WindowLogMax(31))?;
-
     let pb = ProgressBar::new_spinner()
         .with_style(ProgressStyle::with_template("{bytes}  {spinner}").unwrap());
     pb.enable_steady_tick(std::time::Duration::from_millis(250));
 
-    let mut buf = [0u8; 1204 * 1024];
-    loop {
-        let n = decompressor.read(&mut buf[..])?;
-        if n == 0 {
-            break;
-        }
-        outfile.write_all(&buf[0..n])?;
-        pb.inc(n as u64);
-    }
+    zstd_patch::apply_patch(&dict_data, &mut patchfile, &mut pb.wrap_write(outfile))?;
 
-    outfile.flush()?;
     pb.finish();
 
     Ok(())